@@ -46,6 +46,7 @@ impl Default for NpcBundle {
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn see_player(
     check_visibility: CheckVisibility,
     player_query: Query<Entity, With<Player>>,
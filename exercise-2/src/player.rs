@@ -26,6 +26,7 @@ fn setup(mut commands: Commands) {
         .insert(Visible);
 }
 
+#[allow(clippy::type_complexity)]
 fn object_visibility(
     check_visibility: CheckVisibility,
     player_query: Query<Entity, With<Player>>,
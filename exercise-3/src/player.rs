@@ -48,6 +48,7 @@ impl Default for PlayerBundle {
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn object_visibility(
     check_visibility: CheckVisibility,
     player_query: Query<Entity, With<Player>>,
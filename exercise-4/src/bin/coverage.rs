@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+use boxybox::{
+    coverage::{CoverageMap, CoverageOptions},
+    headless::HEADLESS_VIEW_SIZE,
+    level::{Level, LevelObject},
+};
+
+const USAGE: &str = "usage: coverage <level file> [options]
+
+Counts how many guard positions see each walkable point of a level, taking the NPCs' patrol routes,
+ranges and fields of view into account, and prints a summary.
+
+options:
+    --bounds <x,y,x,y>         the corners of the area to sample (default: the game's view)
+    --cell <size>              the spacing of the sampled points (default: 10)
+    --patrol-spacing <length>  the spacing of guard positions along patrol routes (default: 25)
+    --start <object or x,y>    where safe routes start, like players[0] or 10,-20
+                               (default: players[0], if there is one)
+    --goal <x,y>               check that the goal can be reached from the start unseen
+    --output <file>            write the heatmap to a .png, .ppm or .txt file";
+
+struct Options {
+    level_path: String,
+    coverage: CoverageOptions,
+    start: Option<String>,
+    goal: Option<Vec2>,
+    output: Option<String>,
+}
+
+fn parse_numbers<const N: usize>(value: &str) -> Result<[f32; N], String> {
+    let numbers: Vec<f32> = value
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("expected {} comma-separated numbers, got {}", N, value))?;
+    numbers
+        .try_into()
+        .map_err(|_| format!("expected {} comma-separated numbers, got {}", N, value))
+}
+
+fn parse_length(name: &str, value: &str) -> Result<f32, String> {
+    let [length] = parse_numbers(value)?;
+    if !(length.is_finite() && length > 0.0) {
+        return Err(format!("the {} must be positive", name));
+    }
+    Ok(length)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        level_path: String::new(),
+        coverage: CoverageOptions {
+            bounds: Rect::from_center_size(Vec2::ZERO, HEADLESS_VIEW_SIZE),
+            cell_size: 10.0,
+            patrol_spacing: 25.0,
+        },
+        start: None,
+        goal: None,
+        output: None,
+    };
+    let mut level_path = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            level_path = Some(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--bounds" => {
+                let [min_x, min_y, max_x, max_y] = parse_numbers(&value)?;
+                options.coverage.bounds = Rect::new(min_x, min_y, max_x, max_y);
+            }
+            "--cell" => options.coverage.cell_size = parse_length("cell size", &value)?,
+            "--patrol-spacing" => {
+                options.coverage.patrol_spacing = parse_length("patrol spacing", &value)?
+            }
+            "--start" => options.start = Some(value),
+            "--goal" => options.goal = Some(Vec2::from(parse_numbers::<2>(&value)?)),
+            "--output" => options.output = Some(value),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    options.level_path = level_path.ok_or("missing level file")?;
+    Ok(options)
+}
+
+fn start(level: &Level, start: Option<&str>) -> Result<Option<Vec2>, String> {
+    let Some(start) = start else {
+        return Ok(level.players.first().map(|player| player.position));
+    };
+    if let Ok(point) = parse_numbers(start) {
+        return Ok(Some(Vec2::from(point)));
+    }
+
+    let object: LevelObject = start.parse()?;
+    level
+        .viewer(object)
+        .map(|(transform, _)| Some(transform.translation.truncate()))
+        .ok_or_else(|| format!("{} isn't in the level, or can't see", object))
+}
+
+/// Returns whether the goal, if there is one, can be reached unseen.
+fn run(options: Options) -> Result<bool, String> {
+    let level = Level::load(&options.level_path)
+        .map_err(|err| format!("{}: {}", options.level_path, err))?;
+    let start = start(&level, options.start.as_deref())?;
+
+    let map = CoverageMap::analyse(&level, options.coverage);
+
+    if let Some(output) = &options.output {
+        let contents = match output.rsplit('.').next().unwrap_or_default() {
+            "png" => map.to_png().map_err(|err| err.to_string())?,
+            "ppm" => map.to_ppm(),
+            "txt" => map.to_ascii().into_bytes(),
+            _ => return Err(format!("{} isn't a .png, .ppm or .txt file", output)),
+        };
+        std::fs::write(output, contents).map_err(|err| format!("{}: {}", output, err))?;
+    }
+
+    println!("{}", map.summary(start));
+
+    let Some(goal) = options.goal else {
+        return Ok(true);
+    };
+    let start = start.ok_or("a goal needs a start, and the level has no players")?;
+    let safe = map.safe_route_exists(start, goal);
+    println!(
+        "safe route from {},{} to {},{}: {}",
+        start.x,
+        start.y,
+        goal.x,
+        goal.y,
+        if safe { "yes" } else { "no" }
+    );
+    Ok(safe)
+}
+
+/// Shows which parts of a level are safe from its guards, without a window.
+fn main() {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match run(options) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use boxybox::scenario::Scenario;
+
+/// Runs each scenario file given on the command line, and exits with an error if any fail.
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: scenario <scenario file>...");
+        std::process::exit(2);
+    }
+
+    let mut passed = true;
+    for path in paths {
+        match Scenario::load(&path) {
+            Ok(scenario) => {
+                let report = scenario.run();
+                println!("{}\n{}\n", path, report);
+                passed &= report.passed();
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                passed = false;
+            }
+        }
+    }
+
+    if !passed {
+        std::process::exit(1);
+    }
+}
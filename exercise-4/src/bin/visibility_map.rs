@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use boxybox::{
+    headless::HEADLESS_VIEW_SIZE,
+    level::{Level, LevelObject},
+    sight::Sighted,
+    visibility_map::{MapMode, VisibilityMap},
+};
+
+const USAGE: &str = "usage: visibility_map <level file> [options]
+
+options:
+    --viewer <object or x,y>   where to look from, like npcs[0] or 10,-20 (default: players[0])
+    --mode <lit or visible>    draw the region lit from the viewer, or the region the viewer can
+                               see within its range and field of view (default: lit)
+    --bounds <x,y,x,y>         the corners of the area to draw (default: the game's view)
+    --cell <size>              the size of each pixel or character (default: 4, or 16 for ASCII)
+    --output <file>            write a .png, .ppm or .txt file instead of ASCII to stdout";
+
+struct Options {
+    level_path: String,
+    viewer: String,
+    mode: MapMode,
+    bounds: Rect,
+    cell_size: Option<f32>,
+    output: Option<String>,
+}
+
+fn parse_numbers<const N: usize>(value: &str) -> Result<[f32; N], String> {
+    let numbers: Vec<f32> = value
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("expected {} comma-separated numbers, got {}", N, value))?;
+    numbers
+        .try_into()
+        .map_err(|_| format!("expected {} comma-separated numbers, got {}", N, value))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        level_path: String::new(),
+        viewer: "players[0]".to_string(),
+        mode: MapMode::Lit,
+        bounds: Rect::from_center_size(Vec2::ZERO, HEADLESS_VIEW_SIZE),
+        cell_size: None,
+        output: None,
+    };
+    let mut level_path = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            level_path = Some(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--viewer" => options.viewer = value,
+            "--mode" => {
+                options.mode = match value.as_str() {
+                    "lit" => MapMode::Lit,
+                    "visible" => MapMode::Visible,
+                    _ => return Err(format!("unknown mode {}", value)),
+                }
+            }
+            "--bounds" => {
+                let [min_x, min_y, max_x, max_y] = parse_numbers(&value)?;
+                options.bounds = Rect::new(min_x, min_y, max_x, max_y);
+            }
+            "--cell" => {
+                let [cell_size] = parse_numbers(&value)?;
+                if !(cell_size.is_finite() && cell_size > 0.0) {
+                    return Err("the cell size must be positive".to_string());
+                }
+                options.cell_size = Some(cell_size);
+            }
+            "--output" => options.output = Some(value),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    options.level_path = level_path.ok_or("missing level file")?;
+    Ok(options)
+}
+
+fn viewer(level: &Level, viewer: &str) -> Result<(Transform, Sighted), String> {
+    if let Ok([x, y]) = parse_numbers(viewer) {
+        return Ok((Transform::from_xyz(x, y, 0.0), Sighted::default()));
+    }
+
+    let object: LevelObject = viewer.parse()?;
+    level
+        .viewer(object)
+        .ok_or_else(|| format!("{} isn't in the level, or can't see", object))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let level = Level::load(&options.level_path)
+        .map_err(|err| format!("{}: {}", options.level_path, err))?;
+    let (transform, sighted) = viewer(&level, &options.viewer)?;
+
+    let extension = options
+        .output
+        .as_deref()
+        .map(|output| output.rsplit('.').next().unwrap_or_default());
+    let default_cell_size = match extension {
+        None | Some("txt") => 16.0,
+        _ => 4.0,
+    };
+
+    let map = VisibilityMap::render(
+        options.mode,
+        (&transform, &sighted),
+        &level.occluders(),
+        options.bounds,
+        options.cell_size.unwrap_or(default_cell_size),
+    );
+
+    let Some(output) = &options.output else {
+        print!("{}", map.to_ascii());
+        return Ok(());
+    };
+    let contents = match extension {
+        Some("png") => map.to_png().map_err(|err| err.to_string())?,
+        Some("ppm") => map.to_ppm(),
+        Some("txt") => map.to_ascii().into_bytes(),
+        _ => return Err(format!("{} isn't a .png, .ppm or .txt file", output)),
+    };
+    std::fs::write(output, contents).map_err(|err| format!("{}: {}", output, err))
+}
+
+/// Draws a level's lit or visible region from a viewpoint, without a window.
+fn main() {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
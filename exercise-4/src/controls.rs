@@ -1,58 +1,74 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::movement;
 
-#[derive(Component)]
-pub struct Controlled;
+/// Moves the entity using the given keys.
+#[derive(Component, Debug, Clone, Copy, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct Controlled {
+    pub up: KeyCode,
+    pub left: KeyCode,
+    pub down: KeyCode,
+    pub right: KeyCode,
+}
+
+impl Controlled {
+    pub const WASD: Self = Self {
+        up: KeyCode::W,
+        left: KeyCode::A,
+        down: KeyCode::S,
+        right: KeyCode::D,
+    };
+
+    pub const ARROWS: Self = Self {
+        up: KeyCode::Up,
+        left: KeyCode::Left,
+        down: KeyCode::Down,
+        right: KeyCode::Right,
+    };
+}
+
+impl Default for Controlled {
+    fn default() -> Self {
+        Self::WASD
+    }
+}
 
 fn set_direction(
     input: Res<Input<KeyCode>>,
-    mut query: Query<&mut movement::Direction, With<Controlled>>,
+    mut query: Query<(&mut movement::Direction, &Controlled)>,
 ) {
-    if input.just_pressed(KeyCode::W) {
-        for mut direction in query.iter_mut() {
+    for (mut direction, controlled) in query.iter_mut() {
+        if input.just_pressed(controlled.up) {
             direction.value += Vec2::Y;
         }
-    }
 
-    if input.just_released(KeyCode::W) {
-        for mut direction in query.iter_mut() {
+        if input.just_released(controlled.up) {
             direction.value -= Vec2::Y;
         }
-    }
 
-    if input.just_pressed(KeyCode::A) {
-        for mut direction in query.iter_mut() {
+        if input.just_pressed(controlled.left) {
             direction.value -= Vec2::X;
         }
-    }
 
-    if input.just_released(KeyCode::A) {
-        for mut direction in query.iter_mut() {
+        if input.just_released(controlled.left) {
             direction.value += Vec2::X;
         }
-    }
 
-    if input.just_pressed(KeyCode::S) {
-        for mut direction in query.iter_mut() {
+        if input.just_pressed(controlled.down) {
             direction.value -= Vec2::Y;
         }
-    }
 
-    if input.just_released(KeyCode::S) {
-        for mut direction in query.iter_mut() {
+        if input.just_released(controlled.down) {
             direction.value += Vec2::Y;
         }
-    }
 
-    if input.just_pressed(KeyCode::D) {
-        for mut direction in query.iter_mut() {
+        if input.just_pressed(controlled.right) {
             direction.value += Vec2::X;
         }
-    }
 
-    if input.just_released(KeyCode::D) {
-        for mut direction in query.iter_mut() {
+        if input.just_released(controlled.right) {
             direction.value -= Vec2::X;
         }
     }
@@ -65,6 +81,8 @@ pub struct ControlsPlugin;
 
 impl Plugin for ControlsPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        app.register_type::<Controlled>();
+
         app.add_system(set_direction.in_set(ControlsSet));
     }
 }
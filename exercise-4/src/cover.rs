@@ -0,0 +1,312 @@
+use bevy::prelude::*;
+
+use crate::sight::{
+    unobstructed_fraction, visibility_polygon, Occluder, OcclusionLayers, Segment, Sighted,
+};
+
+/// How far past the edge of a viewer's visible region a point of cover is placed, so that it's
+/// clearly hidden rather than on the edge.
+const COVER_MARGIN: f32 = 0.5;
+
+/// What a viewer can see: the region around it that isn't in the shadow of an occluder, limited by
+/// its range and field of view.
+struct VisibleRegion<'a> {
+    origin: Vec3,
+    facing: Vec3,
+    sighted: &'a Sighted,
+    /// The occluders that block the viewer's sight.
+    occluders: Vec<&'a Occluder>,
+    polygon: Vec<Vec3>,
+}
+
+/// The occluders on any of `layers`, and their segments that block from `origin`.
+fn blocking(
+    occluders: &[Occluder],
+    layers: OcclusionLayers,
+    origin: Vec3,
+) -> (Vec<&Occluder>, Vec<Segment>) {
+    let occluders: Vec<&Occluder> = occluders
+        .iter()
+        .filter(|occluder| occluder.blocks(layers))
+        .collect();
+    let segments = occluders
+        .iter()
+        .flat_map(|occluder| occluder.iter_segments_from(origin))
+        .collect();
+    (occluders, segments)
+}
+
+impl VisibleRegion<'_> {
+    /// The edges of the region: those of its polygon, and the sides of its field of view.
+    fn edges(&self, bounds: Rect) -> Vec<Segment> {
+        let mut edges = polygon_edges(&self.polygon);
+        if let Some(field_of_view) = self.sighted.field_of_view {
+            let length = self.sighted.range.unwrap_or_else(|| bounds.size().length());
+            for angle in [field_of_view / 2.0, -field_of_view / 2.0] {
+                let side = Quat::from_rotation_z(angle) * self.facing;
+                edges.push(Segment(self.origin, self.origin + side * length));
+            }
+        }
+        edges
+    }
+
+    /// The circle at the limit of the viewer's range, if it has one.
+    fn range_circle(&self) -> Option<(Vec3, f32)> {
+        self.sighted.range.map(|range| (self.origin, range))
+    }
+}
+
+fn polygon_edges(polygon: &[Vec3]) -> Vec<Segment> {
+    polygon
+        .iter()
+        .enumerate()
+        .map(|(index, a)| Segment(*a, polygon[(index + 1) % polygon.len()]))
+        .collect()
+}
+
+/// The point of `segment` closest to `point`.
+fn closest_point_on_segment(segment: &Segment, point: Vec3) -> Vec3 {
+    let direction = segment.1 - segment.0;
+    let length_squared = direction.length_squared();
+    if length_squared == 0.0 {
+        return segment.0;
+    }
+    let t = ((point - segment.0).dot(direction) / length_squared).clamp(0.0, 1.0);
+    segment.0 + direction * t
+}
+
+/// Where two segments cross, if they do.
+fn segment_crossing(a: &Segment, b: &Segment) -> Option<Vec3> {
+    let (a_direction, b_direction) = (a.1 - a.0, b.1 - b.0);
+    let denominator = a_direction.truncate().perp_dot(b_direction.truncate());
+    if denominator == 0.0 {
+        return None;
+    }
+    let offset = (b.0 - a.0).truncate();
+    let s = offset.perp_dot(b_direction.truncate()) / denominator;
+    let t = offset.perp_dot(a_direction.truncate()) / denominator;
+    ((0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t)).then(|| a.0 + a_direction * s)
+}
+
+/// Where a segment crosses a circle.
+fn circle_crossings(segment: &Segment, (centre, radius): (Vec3, f32)) -> Vec<Vec3> {
+    let direction = segment.1 - segment.0;
+    let offset = segment.0 - centre;
+    let a = direction.length_squared();
+    let b = 2.0 * offset.dot(direction);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if a == 0.0 || discriminant < 0.0 {
+        return Vec::new();
+    }
+    [-1.0, 1.0]
+        .into_iter()
+        .map(|sign| (-b + sign * discriminant.sqrt()) / (2.0 * a))
+        .filter(|t| (0.0..=1.0).contains(t))
+        .map(|t| segment.0 + direction * t)
+        .collect()
+}
+
+/// Where two circles cross.
+fn circles_crossings((a, a_radius): (Vec3, f32), (b, b_radius): (Vec3, f32)) -> Vec<Vec3> {
+    let distance = a.distance(b);
+    if distance == 0.0 || distance > a_radius + b_radius || distance < (a_radius - b_radius).abs() {
+        return Vec::new();
+    }
+    let along =
+        (a_radius * a_radius - b_radius * b_radius + distance * distance) / (2.0 * distance);
+    let across = (a_radius * a_radius - along * along).max(0.0).sqrt();
+    let direction = (b - a) / distance;
+    let middle = a + direction * along;
+    let perpendicular = Vec3::new(-direction.y, direction.x, 0.0);
+    vec![
+        middle + perpendicular * across,
+        middle - perpendicular * across,
+    ]
+}
+
+/// The nearest point to `position` that none of the `viewers` can see, and that can be reached
+/// from `position` in a straight line without passing through an occluder that blocks movement, if
+/// there is one within `bounds` and `max_distance`.
+///
+/// Rather than sampling the area, the points considered are where the edges of the viewers'
+/// visible regions, and of the region reachable from `position`, come closest to `position` or
+/// cross each other, since the nearest hidden point is always at one of them. Points are hidden
+/// from a viewer if the line from the viewer's position to them is blocked by an occluder on one
+/// of its [`Sighted::blocked_by`] layers, or if they're out of its range or field of view.
+pub fn nearest_cover(
+    position: Vec3,
+    viewers: &[(&Transform, &Sighted)],
+    occluders: &[Occluder],
+    bounds: Rect,
+    max_distance: Option<f32>,
+) -> Option<Vec3> {
+    let regions: Vec<VisibleRegion> = viewers
+        .iter()
+        .map(|(transform, sighted)| {
+            let (occluders, segments) =
+                blocking(occluders, sighted.blocked_by, transform.translation);
+            VisibleRegion {
+                origin: transform.translation,
+                facing: transform.rotation * Vec3::X,
+                sighted,
+                occluders,
+                polygon: visibility_polygon(transform.translation, &segments, bounds),
+            }
+        })
+        .collect();
+    let (obstacles, obstacle_segments) = blocking(occluders, OcclusionLayers::MOVEMENT, position);
+
+    let is_cover = |point: Vec3| {
+        bounds.contains(point.truncate())
+            && max_distance.map_or(true, |max_distance| {
+                position.distance(point) <= max_distance
+            })
+            && regions.iter().all(|region| {
+                let offset = point - region.origin;
+                !region.sighted.in_range(offset)
+                    || !region.sighted.in_field_of_view(region.facing, offset)
+                    || unobstructed_fraction(&[region.origin], &[point], &region.occluders) == 0.0
+            })
+            && unobstructed_fraction(&[position], &[point], &obstacles) == 1.0
+    };
+
+    if is_cover(position) {
+        return Some(position);
+    }
+
+    let reachable = visibility_polygon(position, &obstacle_segments, bounds);
+    let mut edges = polygon_edges(&reachable);
+    let mut circles = Vec::new();
+    for region in &regions {
+        edges.extend(region.edges(bounds));
+        circles.extend(region.range_circle());
+    }
+
+    let mut candidates: Vec<Vec3> = Vec::new();
+    for (index, edge) in edges.iter().enumerate() {
+        candidates.extend([closest_point_on_segment(edge, position), edge.0, edge.1]);
+        candidates.extend(
+            edges[index + 1..]
+                .iter()
+                .filter_map(|other| segment_crossing(edge, other)),
+        );
+        for circle in &circles {
+            candidates.extend(circle_crossings(edge, *circle));
+        }
+    }
+    for (index, circle) in circles.iter().enumerate() {
+        let (centre, radius) = *circle;
+        let away = (position - centre).try_normalize().unwrap_or(Vec3::X);
+        candidates.push(centre + away * radius);
+        for other in &circles[index + 1..] {
+            candidates.extend(circles_crossings(*circle, *other));
+        }
+    }
+
+    // Each candidate is on an edge, so look just past it in each direction for a hidden point, at
+    // the height of `position` rather than that of the edge.
+    let nudges: Vec<Vec3> = (0..8)
+        .map(|step| Quat::from_rotation_z(step as f32 * std::f32::consts::FRAC_PI_4) * Vec3::X)
+        .collect();
+    candidates
+        .into_iter()
+        .map(|candidate| candidate.truncate().extend(position.z))
+        .flat_map(|candidate| {
+            let away = (candidate - position).try_normalize();
+            away.into_iter()
+                .chain(nudges.iter().copied())
+                .map(move |nudge| candidate + nudge * COVER_MARGIN)
+        })
+        .filter(|point| is_cover(*point))
+        .min_by(|a, b| position.distance(*a).total_cmp(&position.distance(*b)))
+}
+
+#[cfg(test)]
+fn post() -> Occluder {
+    Occluder {
+        top_left: Vec3::new(-5.0, 5.0, 0.0),
+        bottom_right: Vec3::new(5.0, -5.0, 0.0),
+        ..default()
+    }
+}
+
+#[test]
+fn nearest_cover_test_1() {
+    // Behind a post, from a viewer on the other side.
+    let viewer = (Transform::from_xyz(-50.0, 0.0, 0.0), Sighted::default());
+    let bounds = Rect::new(-100.0, -100.0, 100.0, 100.0);
+    let cover = nearest_cover(
+        Vec3::new(20.0, 20.0, 0.0),
+        &[(&viewer.0, &viewer.1)],
+        &[post()],
+        bounds,
+        None,
+    )
+    .unwrap();
+
+    // The shadow's edge is the line from the viewer through the post's corner at (-5, 5).
+    let shadow_edge = Segment(viewer.0.translation, Vec3::new(100.0, 150.0 / 9.0, 0.0));
+    let closest = closest_point_on_segment(&shadow_edge, Vec3::new(20.0, 20.0, 0.0));
+    assert!(
+        cover.distance(closest) < 1.0,
+        "{} isn't near {}",
+        cover,
+        closest
+    );
+    assert!(cover.y < closest.y);
+
+    assert_eq!(
+        nearest_cover(
+            Vec3::new(20.0, 20.0, 0.0),
+            &[(&viewer.0, &viewer.1)],
+            &[post()],
+            bounds,
+            Some(2.0),
+        ),
+        None
+    );
+}
+
+#[test]
+fn nearest_cover_test_2() {
+    // Between two viewers on either side of a post, it's only hidden from both along the post's
+    // other sides, or outside their ranges.
+    let sighted = Sighted {
+        range: Some(60.0),
+        ..default()
+    };
+    let left = Transform::from_xyz(-50.0, 0.0, 0.0);
+    let right = Transform::from_xyz(50.0, 0.0, 0.0);
+    let position = Vec3::new(0.0, 30.0, 0.0);
+    let cover = nearest_cover(
+        position,
+        &[(&left, &sighted), (&right, &sighted)],
+        &[post()],
+        Rect::new(-100.0, -100.0, 100.0, 100.0),
+        None,
+    )
+    .unwrap();
+
+    // Out of both viewers' ranges, straight up.
+    let expected = Vec3::new(0.0, (60.0f32.powi(2) - 50.0f32.powi(2)).sqrt(), 0.0);
+    assert!(
+        cover.distance(expected) < 1.0,
+        "{} isn't near {}",
+        cover,
+        expected
+    );
+
+    // Already hidden from one viewer, just behind the post.
+    let hidden = Vec3::new(0.0, -5.5, 0.0);
+    assert_eq!(
+        nearest_cover(
+            hidden,
+            &[(&left, &Sighted::default())],
+            &[post()],
+            Rect::new(-100.0, -100.0, 100.0, 100.0),
+            None
+        ),
+        Some(hidden)
+    );
+}
@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+
+use bevy::{ecs::system::SystemState, prelude::*};
+
+use crate::{
+    level::Level,
+    player::PLAYER_SIZE,
+    sight::{
+        CheckVisibility, OcclusionLayers, SightConfig, SightStats, Sighted, VisibilityShape,
+        Visible,
+    },
+    visibility_map::{color_rgb, encode_png, encode_ppm},
+};
+
+/// How a level's walkable area is sampled by [`CoverageMap::analyse`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoverageOptions {
+    /// The area to sample.
+    pub bounds: Rect,
+    /// The spacing of the sampled points, which are the centres of square cells.
+    pub cell_size: f32,
+    /// How far apart the guard positions are along each NPC's patrol route.
+    pub patrol_spacing: f32,
+}
+
+/// What's at a point in a [`CoverageMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageCell {
+    /// A player can't stand here, because it would overlap an occluder that blocks movement.
+    Wall,
+    /// A player standing here is seen from this many guard positions.
+    Exposure(u32),
+}
+
+impl CoverageCell {
+    fn ascii(&self) -> char {
+        match self {
+            CoverageCell::Wall => '#',
+            CoverageCell::Exposure(0) => '.',
+            CoverageCell::Exposure(exposure @ 1..=9) => char::from_digit(*exposure, 10).unwrap(),
+            CoverageCell::Exposure(_) => '+',
+        }
+    }
+}
+
+/// A grid of the points of a level that a player could walk to, each counting the guard positions
+/// that it's seen from, as checked by [`CheckVisibility`].
+///
+/// The guard positions are every NPC's position, or points along its patrol route facing the way
+/// it walks, so that a point which is safe is never seen by any guard at any time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageMap {
+    pub bounds: Rect,
+    pub cell_size: f32,
+    pub width: usize,
+    pub height: usize,
+    /// How many guard positions were checked.
+    pub guard_positions: usize,
+    /// The cells in rows from the top of `bounds` to the bottom.
+    cells: Vec<CoverageCell>,
+}
+
+/// The guard positions of the level's NPCs, with what each can see.
+fn guard_positions(level: &Level, patrol_spacing: f32) -> Vec<(Transform, Sighted)> {
+    level
+        .npcs
+        .iter()
+        .flat_map(|npc| {
+            let sighted = npc.sight.sighted();
+            let poses = npc.patrol().poses(patrol_spacing);
+            if poses.is_empty() {
+                return vec![(npc.transform(), sighted)];
+            }
+
+            poses
+                .into_iter()
+                .map(|(position, facing)| {
+                    let transform = Transform::from_translation(position.extend(npc.elevation))
+                        .with_rotation(Quat::from_rotation_z(facing.y.atan2(facing.x)));
+                    (transform, sighted.clone())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl CoverageMap {
+    /// Samples the walkable area of `level`, and checks which guard positions see a player at
+    /// each point.
+    pub fn analyse(level: &Level, options: CoverageOptions) -> Self {
+        let CoverageOptions {
+            bounds,
+            cell_size,
+            patrol_spacing,
+        } = options;
+        let width = (bounds.width() / cell_size).ceil().max(1.0) as usize;
+        let height = (bounds.height() / cell_size).ceil().max(1.0) as usize;
+
+        let occluders = level.occluders();
+        let points: Vec<Option<Vec2>> = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let point = Vec2::new(
+                    bounds.min.x + (column as f32 + 0.5) * cell_size,
+                    bounds.max.y - (row as f32 + 0.5) * cell_size,
+                );
+                let footprint = Rect::from_center_size(point, PLAYER_SIZE);
+                let walkable = !occluders.iter().any(|occluder| {
+                    occluder.blocks(OcclusionLayers::MOVEMENT)
+                        && !occluder.rect().intersect(footprint).is_empty()
+                });
+                walkable.then_some(point)
+            })
+            .collect();
+
+        // A world with just the occluders and portals, a guard and a player to move between the
+        // positions.
+        let mut world = World::new();
+        world.init_resource::<SightStats>();
+        world.init_resource::<SightConfig>();
+        for occluder in occluders {
+            world.spawn(occluder);
+        }
+        for portal in &level.portals {
+            world.spawn(portal.portal());
+        }
+        let guard = world.spawn((Transform::default(), Sighted::default())).id();
+        let probe = world
+            .spawn((
+                Transform::default(),
+                Visible,
+                VisibilityShape::Rectangle { size: PLAYER_SIZE },
+            ))
+            .id();
+        let mut system_state: SystemState<CheckVisibility> = SystemState::new(&mut world);
+
+        let guard_positions = guard_positions(level, patrol_spacing);
+        let mut exposures = vec![0; points.len()];
+        for (transform, sighted) in &guard_positions {
+            world
+                .entity_mut(guard)
+                .insert((*transform, sighted.clone()));
+            for (point, exposure) in points.iter().zip(exposures.iter_mut()) {
+                let Some(point) = point else {
+                    continue;
+                };
+                *world.get_mut::<Transform>(probe).unwrap() =
+                    Transform::from_translation(point.extend(0.0));
+                if system_state.get(&world).sees(guard, probe) == Ok(true) {
+                    *exposure += 1;
+                }
+            }
+        }
+
+        let cells = points
+            .iter()
+            .zip(exposures)
+            .map(|(point, exposure)| match point {
+                Some(_) => CoverageCell::Exposure(exposure),
+                None => CoverageCell::Wall,
+            })
+            .collect();
+
+        Self {
+            bounds,
+            cell_size,
+            width,
+            height,
+            guard_positions: guard_positions.len(),
+            cells,
+        }
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> Option<CoverageCell> {
+        if column < self.width && row < self.height {
+            Some(self.cells[row * self.width + column])
+        } else {
+            None
+        }
+    }
+
+    /// The column and row of the cell containing `point`, if it's in the map.
+    pub fn cell_at(&self, point: Vec2) -> Option<(usize, usize)> {
+        let column = ((point.x - self.bounds.min.x) / self.cell_size).floor();
+        let row = ((self.bounds.max.y - point.y) / self.cell_size).floor();
+        if column < 0.0 || row < 0.0 || column as usize >= self.width || row as usize >= self.height
+        {
+            return None;
+        }
+        Some((column as usize, row as usize))
+    }
+
+    /// The cells that can be walked to from `start` without ever being seen, moving between
+    /// neighbouring cells.
+    fn safe_region(&self, start: Vec2) -> Vec<bool> {
+        let mut reached = vec![false; self.cells.len()];
+        let Some((column, row)) = self.cell_at(start) else {
+            return reached;
+        };
+
+        let mut queue = VecDeque::from([(column, row)]);
+        while let Some((column, row)) = queue.pop_front() {
+            let index = row * self.width + column;
+            if reached[index] || self.cells[index] != CoverageCell::Exposure(0) {
+                continue;
+            }
+            reached[index] = true;
+
+            if column > 0 {
+                queue.push_back((column - 1, row));
+            }
+            if column + 1 < self.width {
+                queue.push_back((column + 1, row));
+            }
+            if row > 0 {
+                queue.push_back((column, row - 1));
+            }
+            if row + 1 < self.height {
+                queue.push_back((column, row + 1));
+            }
+        }
+        reached
+    }
+
+    /// Whether a player can walk from `start` to `goal` without being seen by any guard.
+    pub fn safe_route_exists(&self, start: Vec2, goal: Vec2) -> bool {
+        match self.cell_at(goal) {
+            Some((column, row)) => self.safe_region(start)[row * self.width + column],
+            None => false,
+        }
+    }
+
+    /// Counts of the safe and exposed points, and of the safe points reachable from `start`.
+    pub fn summary(&self, start: Option<Vec2>) -> CoverageSummary {
+        let exposures: Vec<u32> = self
+            .cells
+            .iter()
+            .filter_map(|cell| match cell {
+                CoverageCell::Wall => None,
+                CoverageCell::Exposure(exposure) => Some(*exposure),
+            })
+            .collect();
+        let walkable = exposures.len();
+        let safe = exposures.iter().filter(|exposure| **exposure == 0).count();
+
+        CoverageSummary {
+            walkable,
+            safe,
+            guard_positions: self.guard_positions,
+            max_exposure: exposures.iter().copied().max().unwrap_or(0),
+            mean_exposure: match walkable {
+                0 => 0.0,
+                _ => exposures.iter().sum::<u32>() as f32 / walkable as f32,
+            },
+            reachable_safe: start.map(|start| {
+                self.safe_region(start)
+                    .into_iter()
+                    .filter(|reached| *reached)
+                    .count()
+            }),
+        }
+    }
+
+    /// One character per cell, and one line per row: `#` for walls, `.` for safe points, and the
+    /// number of guard positions that see the others, or `+` if it's more than 9.
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width + 1) * self.height);
+        for row in self.cells.chunks(self.width) {
+            ascii.extend(row.iter().map(CoverageCell::ascii));
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    /// Walls are black, safe points green, and exposed points from yellow to red as they're seen
+    /// from more guard positions.
+    fn color(&self, cell: CoverageCell) -> Color {
+        let max_exposure = self.guard_positions.max(1) as f32;
+        match cell {
+            CoverageCell::Wall => Color::BLACK,
+            CoverageCell::Exposure(0) => Color::GREEN,
+            CoverageCell::Exposure(exposure) => {
+                let heat = exposure as f32 / max_exposure;
+                Color::rgb(1.0, 1.0 - heat, 0.0)
+            }
+        }
+    }
+
+    fn rgb(&self) -> Vec<u8> {
+        self.cells
+            .iter()
+            .flat_map(|cell| color_rgb(self.color(*cell)))
+            .collect()
+    }
+
+    /// A binary PPM heatmap with one pixel per cell.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        encode_ppm(self.width, self.height, &self.rgb())
+    }
+
+    /// A PNG heatmap with one pixel per cell.
+    pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+        encode_png(self.width, self.height, &self.rgb())
+    }
+}
+
+/// How much of a level's walkable area is safe from its guards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageSummary {
+    /// How many points a player could stand at.
+    pub walkable: usize,
+    /// How many of those points no guard position sees.
+    pub safe: usize,
+    pub guard_positions: usize,
+    /// The most guard positions that see any one point.
+    pub max_exposure: u32,
+    /// How many guard positions see a point, on average.
+    pub mean_exposure: f32,
+    /// How many safe points can be walked to from the start without being seen, if there's a
+    /// start.
+    pub reachable_safe: Option<usize>,
+}
+
+impl CoverageSummary {
+    fn percentage(&self, count: usize) -> f32 {
+        match self.walkable {
+            0 => 0.0,
+            walkable => 100.0 * count as f32 / walkable as f32,
+        }
+    }
+}
+
+impl std::fmt::Display for CoverageSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let exposed = self.walkable - self.safe;
+        writeln!(f, "walkable points: {}", self.walkable)?;
+        writeln!(f, "guard positions: {}", self.guard_positions)?;
+        writeln!(
+            f,
+            "safe: {} ({:.1}%)",
+            self.safe,
+            self.percentage(self.safe)
+        )?;
+        writeln!(f, "exposed: {} ({:.1}%)", exposed, self.percentage(exposed))?;
+        write!(
+            f,
+            "exposure: at most {}, {:.2} on average",
+            self.max_exposure, self.mean_exposure
+        )?;
+        if let Some(reachable_safe) = self.reachable_safe {
+            write!(
+                f,
+                "\nreachable safely from the start: {} ({:.1}%)",
+                reachable_safe,
+                self.percentage(reachable_safe)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The default level, with an NPC that looks ahead within a right angle.
+#[cfg(test)]
+fn watchful_level() -> Level {
+    let mut level = Level::default();
+    level.npcs[0].sight.field_of_view = Some(90.0);
+    level
+}
+
+#[cfg(test)]
+fn level_coverage(level: &Level) -> CoverageMap {
+    CoverageMap::analyse(
+        level,
+        CoverageOptions {
+            bounds: Rect::from_center_size(Vec2::ZERO, Vec2::new(400.0, 240.0)),
+            cell_size: 10.0,
+            patrol_spacing: 50.0,
+        },
+    )
+}
+
+#[test]
+fn coverage_golden_test_1() {
+    let map = level_coverage(&watchful_level());
+    crate::golden::check("goldens/default_coverage.txt", map.to_ascii().as_bytes());
+
+    let summary = map.summary(Some(Vec2::new(100.0, 0.0)));
+    assert_eq!(summary.guard_positions, 1);
+    assert_eq!(summary.max_exposure, 1);
+    assert!(summary.safe > 0 && summary.safe < summary.walkable);
+    // The wall in front of the NPC hides the player's side of the level.
+    assert!(map.safe_route_exists(Vec2::new(100.0, 0.0), Vec2::new(100.0, 100.0)));
+    assert!(!map.safe_route_exists(Vec2::new(100.0, 0.0), Vec2::new(-60.0, 30.0)));
+}
+
+#[test]
+fn coverage_patrol_test_1() {
+    let mut level = watchful_level();
+    level.npcs[0].patrol = vec![Vec2::new(-100.0, 100.0)];
+    let map = level_coverage(&level);
+    let summary = map.summary(None);
+
+    // Out and back along the route, 50 apart.
+    assert_eq!(summary.guard_positions, 4);
+    assert!(summary.safe < level_coverage(&watchful_level()).summary(None).safe);
+    // Behind the start of the route, where the NPC only looks while it walks back.
+    let (column, row) = map.cell_at(Vec2::new(-100.0, -60.0)).unwrap();
+    assert!(matches!(
+        map.get(column, row),
+        Some(CoverageCell::Exposure(1..))
+    ));
+}
@@ -0,0 +1,397 @@
+use bevy::{
+    ecs::system::SystemParam,
+    input::InputSystem,
+    prelude::*,
+    render::{render_resource::PrimitiveTopology, view::RenderLayers},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    window::PrimaryWindow,
+};
+
+use crate::{
+    light::{ColorMaterials, IndirectLight, SegmentShadow},
+    npc::{Npc, NpcSet},
+    player::{Player, PlayerSet},
+    sight::{
+        CheckVisibility, Portal, SightConfig, SightDiagnosis, SightStats, Sighted, VisibilityShape,
+    },
+    team::{TeamSet, VisionPolygon},
+    viewport::MAX_ELEVATION,
+};
+
+/// Which debugging aids are drawn over the scene.
+///
+/// Press F3 to toggle the overlay, and F4 to F8 to toggle its layers in the order of the fields
+/// below. While the overlay is enabled, the window title shows how much sight checking was done
+/// in the last frame.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+    /// Outline occluders, through [`SightConfig::display_occluders`].
+    pub occluders: bool,
+    /// Outline shadow quads, vision polygons, indirect light and portals.
+    pub shadow_edges: bool,
+    /// Draw each NPC's sight lines to the players, coloured by the result of the sight check.
+    pub sight_lines: bool,
+    /// Draw the field of view of each [`Sighted`] entity that has a limited one.
+    pub fields_of_view: bool,
+    /// Outline each [`VisibilityShape`], green if some player can see it and red otherwise.
+    pub visibility_state: bool,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            occluders: true,
+            shadow_edges: true,
+            sight_lines: true,
+            fields_of_view: true,
+            visibility_state: true,
+        }
+    }
+}
+
+/// How far a field of view is drawn when the viewer's range is unlimited.
+const UNLIMITED_RANGE_LENGTH: f32 = 200.0;
+
+/// In front of everything else in the scene, even the highest entities.
+const OVERLAY_Z: f32 = MAX_ELEVATION + 10.0;
+
+/// The mesh that the overlay's lines are drawn into.
+#[derive(Component)]
+struct DebugLines;
+
+/// Coloured line segments, drawn as a single mesh.
+#[derive(Debug, Default)]
+struct Lines {
+    positions: Vec<Vec3>,
+    colors: Vec<[f32; 4]>,
+}
+
+impl Lines {
+    fn line(&mut self, start: Vec3, end: Vec3, color: Color) {
+        self.positions
+            .extend([start.truncate().extend(0.0), end.truncate().extend(0.0)]);
+        self.colors
+            .extend([color.as_linear_rgba_f32(), color.as_linear_rgba_f32()]);
+    }
+
+    /// A closed outline through `points`.
+    fn outline(&mut self, points: &[Vec3], color: Color) {
+        for (index, point) in points.iter().enumerate() {
+            self.line(*point, points[(index + 1) % points.len()], color);
+        }
+    }
+
+    fn cross(&mut self, centre: Vec3, size: f32, color: Color) {
+        self.line(
+            centre - size / 2.0 * Vec3::X,
+            centre + size / 2.0 * Vec3::X,
+            color,
+        );
+        self.line(
+            centre - size / 2.0 * Vec3::Y,
+            centre + size / 2.0 * Vec3::Y,
+            color,
+        );
+    }
+
+    /// A circular sector of `radius` around `centre`, spanning `angle` radians centred on
+    /// `facing`.
+    fn sector(&mut self, centre: Vec3, facing: Vec3, angle: f32, radius: f32, color: Color) {
+        let facing_angle = facing.y.atan2(facing.x);
+        let steps = 16;
+        let rim: Vec<Vec3> = (0..=steps)
+            .map(|step| {
+                let angle = facing_angle - angle / 2.0 + angle * step as f32 / steps as f32;
+                centre + radius * Vec3::new(angle.cos(), angle.sin(), 0.0)
+            })
+            .collect();
+
+        self.line(centre, rim[0], color);
+        for pair in rim.windows(2) {
+            self.line(pair[0], pair[1], color);
+        }
+        self.line(rim[steps], centre, color);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+impl From<Lines> for Mesh {
+    fn from(value: Lines) -> Self {
+        let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, value.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, value.colors);
+        mesh
+    }
+}
+
+/// The sight lines from each NPC to each player.
+#[derive(SystemParam)]
+pub(crate) struct SightLines<'w, 's> {
+    check_visibility: CheckVisibility<'w, 's>,
+    npcs: Query<'w, 's, Entity, (With<Npc>, With<Sighted>)>,
+    players: Query<'w, 's, Entity, With<Player>>,
+    transforms: Query<'w, 's, &'static Transform, With<Sighted>>,
+}
+
+impl<'w, 's> SightLines<'w, 's> {
+    /// The start and end of each sight line, and why the player is or isn't seen along it.
+    pub(crate) fn diagnose(&self) -> Vec<(Vec3, Vec3, SightDiagnosis)> {
+        let mut sight_lines = Vec::new();
+        for npc in self.npcs.iter() {
+            for player in self.players.iter() {
+                let (Ok(npc_transform), Ok(player_transform)) =
+                    (self.transforms.get(npc), self.transforms.get(player))
+                else {
+                    continue;
+                };
+
+                if let Ok(diagnosis) = self.check_visibility.explain(npc, player) {
+                    sight_lines.push((
+                        npc_transform.translation,
+                        player_transform.translation,
+                        diagnosis,
+                    ));
+                }
+            }
+        }
+        sight_lines
+    }
+}
+
+pub(crate) fn toggle_debug_overlay(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut debug_overlay: ResMut<DebugOverlay>,
+    mut sight_config: ResMut<SightConfig>,
+) {
+    let overlay = debug_overlay.bypass_change_detection();
+    let toggles = [
+        (KeyCode::F3, &mut overlay.enabled, "debug overlay"),
+        (KeyCode::F4, &mut overlay.occluders, "occluders"),
+        (KeyCode::F5, &mut overlay.shadow_edges, "shadow edges"),
+        (KeyCode::F6, &mut overlay.sight_lines, "sight lines"),
+        (KeyCode::F7, &mut overlay.fields_of_view, "fields of view"),
+        (
+            KeyCode::F8,
+            &mut overlay.visibility_state,
+            "visibility state",
+        ),
+    ];
+
+    let mut toggled = false;
+    for (key_code, toggle, name) in toggles {
+        if keyboard_input.just_pressed(key_code) {
+            *toggle = !*toggle;
+            toggled = true;
+            info!("{}: {}", name, if *toggle { "on" } else { "off" });
+        }
+    }
+    if toggled {
+        debug_overlay.set_changed();
+    }
+
+    if debug_overlay.is_changed() {
+        let display_occluders = debug_overlay.enabled && debug_overlay.occluders;
+        if sight_config.display_occluders != display_occluders {
+            sight_config.display_occluders = display_occluders;
+        }
+    }
+}
+
+/// Shows the sight counters in the primary window's title while the overlay is enabled.
+fn show_sight_stats(
+    debug_overlay: Res<DebugOverlay>,
+    sight_stats: Res<SightStats>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut original_title: Local<Option<String>>,
+) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+
+    if debug_overlay.enabled {
+        let original_title = original_title.get_or_insert_with(|| window.title.clone());
+        let title = format!(
+            "{} | sight checks: {} | segments tested: {}",
+            original_title,
+            sight_stats.checks(),
+            sight_stats.segments_tested()
+        );
+        if window.title != title {
+            window.title = title;
+        }
+    } else if let Some(original_title) = original_title.take() {
+        window.title = original_title;
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn draw_debug_overlay(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    debug_overlay: Res<DebugOverlay>,
+    sight_lines: SightLines,
+    segment_shadows: Query<&SegmentShadow>,
+    vision_polygons: Query<&VisionPolygon>,
+    indirect_lights: Query<&IndirectLight>,
+    portals: Query<&Portal>,
+    sighteds: Query<(&Sighted, &Transform)>,
+    visibility_shapes: Query<(&VisibilityShape, &GlobalTransform, &Visibility)>,
+    mut debug_lines: Query<
+        (&Mesh2dHandle, &mut Visibility),
+        (With<DebugLines>, Without<VisibilityShape>),
+    >,
+) {
+    let mut lines = Lines::default();
+
+    if debug_overlay.enabled && debug_overlay.shadow_edges {
+        for segment_shadow in segment_shadows.iter() {
+            lines.outline(&segment_shadow.corners(), Color::RED);
+            let barycentre = segment_shadow.corners().iter().sum::<Vec3>() / 4.0;
+            lines.cross(barycentre, 4.0, Color::RED);
+        }
+
+        for vision_polygon in vision_polygons.iter() {
+            lines.outline(&vision_polygon.vertices, Color::BLUE);
+        }
+
+        for indirect_light in indirect_lights.iter() {
+            lines.outline(&indirect_light.region.outline(), Color::CYAN);
+        }
+
+        for portal in portals.iter() {
+            for segment in [portal.a(), portal.b()] {
+                lines.line(segment.0, segment.1, Color::PURPLE);
+            }
+        }
+    }
+
+    if debug_overlay.enabled && debug_overlay.sight_lines {
+        for (start, end, diagnosis) in sight_lines.diagnose() {
+            match diagnosis {
+                SightDiagnosis::Seen { .. } => lines.line(start, end, Color::GREEN),
+                SightDiagnosis::Indirect { point, .. } => {
+                    lines.line(start, point, Color::GREEN);
+                    lines.line(point, end, Color::CYAN);
+                }
+                SightDiagnosis::Blocked {
+                    point: Some(point), ..
+                } => {
+                    lines.line(start, point, Color::RED);
+                    lines.line(point, end, Color::GRAY);
+                    lines.cross(point, 4.0, Color::RED);
+                }
+                SightDiagnosis::Blocked { point: None, .. } => lines.line(start, end, Color::RED),
+                SightDiagnosis::OutsideFieldOfView => lines.line(start, end, Color::YELLOW),
+                SightDiagnosis::OutOfRange => lines.line(start, end, Color::GRAY),
+                SightDiagnosis::VieweeNotVisible | SightDiagnosis::ViewerNotSighted => {}
+            }
+        }
+    }
+
+    if debug_overlay.enabled && debug_overlay.fields_of_view {
+        for (sighted, transform) in sighteds.iter() {
+            let facing = transform.rotation * Vec3::X;
+            match (sighted.field_of_view, sighted.range) {
+                (Some(field_of_view), range) => lines.sector(
+                    transform.translation,
+                    facing,
+                    field_of_view,
+                    range.unwrap_or(UNLIMITED_RANGE_LENGTH),
+                    Color::CYAN,
+                ),
+                (None, Some(range)) => lines.sector(
+                    transform.translation,
+                    facing,
+                    std::f32::consts::TAU,
+                    range,
+                    Color::CYAN,
+                ),
+                (None, None) => {}
+            }
+        }
+    }
+
+    if debug_overlay.enabled && debug_overlay.visibility_state {
+        for (shape, global_transform, visibility) in visibility_shapes.iter() {
+            let color = if *visibility == Visibility::Hidden {
+                Color::RED
+            } else {
+                Color::GREEN
+            };
+            let outline: Vec<Vec3> = shape
+                .outline_points()
+                .into_iter()
+                .map(|point| global_transform.transform_point(point.extend(0.0)))
+                .collect();
+            match outline.len() {
+                0 => lines.cross(global_transform.translation(), 4.0, color),
+                _ => lines.outline(&outline, color),
+            }
+        }
+    }
+
+    match debug_lines.get_single_mut() {
+        Ok((mesh_handle, mut visibility)) => {
+            // An empty mesh can't be drawn, so the overlay is hidden instead.
+            let new_visibility = if lines.is_empty() {
+                Visibility::Hidden
+            } else {
+                Visibility::Visible
+            };
+            if *visibility != new_visibility {
+                *visibility = new_visibility;
+            }
+
+            if !lines.is_empty() {
+                if let Some(mesh) = meshes.get_mut(&mesh_handle.0) {
+                    *mesh = lines.into();
+                }
+            }
+        }
+        Err(_) => {
+            if !lines.is_empty() {
+                commands.spawn((
+                    DebugLines,
+                    MaterialMesh2dBundle {
+                        mesh: meshes.add(lines.into()).into(),
+                        material: color_materials.get_or_add(Color::WHITE, &mut materials),
+                        transform: Transform::from_xyz(0.0, 0.0, OVERLAY_Z),
+                        ..default()
+                    },
+                    RenderLayers::all(),
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub struct DebugSet;
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DebugOverlay>();
+
+        app.configure_set(DebugSet.after(PlayerSet).after(NpcSet).after(TeamSet));
+
+        app.add_system(
+            toggle_debug_overlay
+                .in_base_set(CoreSet::PreUpdate)
+                .after(InputSystem),
+        )
+        .add_systems(
+            (show_sight_stats, draw_debug_overlay)
+                .chain()
+                .in_set(DebugSet),
+        );
+    }
+}
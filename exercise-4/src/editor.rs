@@ -0,0 +1,615 @@
+use bevy::{input::InputSystem, prelude::*, window::PrimaryWindow};
+
+use crate::{
+    debug::{toggle_debug_overlay, DebugSet},
+    level::{
+        reload_level_file, Level, LevelFile, LevelObject, LevelObjectKind, LightSpec, NpcSpec,
+        WallSpec,
+    },
+    sight::{OccluderHandles, SightConfig},
+    viewport::{PlayerCamera, ViewportSet},
+};
+
+/// The level editor.
+///
+/// Press F2 to toggle editing. While editing:
+///
+/// * Click an object to select it, and drag it to move it.
+/// * Drag a corner handle of the selected wall or occluder to resize it.
+/// * Shift-drag on empty space to draw a new wall.
+/// * Press N or L to place an NPC or a light at the cursor.
+/// * Press Delete or Backspace to delete the selected object.
+/// * Press Q and E to turn the selected NPC, `[` and `]` to narrow and widen its field of view,
+///   `-` and `=` to shorten and lengthen its range, and 0 to make its sight unlimited.
+/// * Press Ctrl+S to save the level to its file, or to `level.ron` if it doesn't have one.
+///
+/// Edits change the [`Level`] resource, so the level's entities, shadows and sight checks update
+/// as you go.
+#[derive(Resource, Debug, Default)]
+pub struct Editor {
+    pub enabled: bool,
+    pub selection: Option<LevelObject>,
+    drag: Option<Drag>,
+    /// [`SightConfig::display_occluders`] from before editing, which is restored afterwards.
+    display_occluders: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Drag {
+    /// Moving an object, which is grabbed at an offset from its position.
+    Move { grab_offset: Vec2 },
+    /// Resizing a wall or an occluder by one corner, keeping the opposite corner fixed.
+    Resize { anchor: Vec2 },
+}
+
+/// The radius within which the cursor grabs an NPC, a light or a corner handle.
+const GRAB_RADIUS: f32 = 8.0;
+
+/// Walls and occluders can't be resized smaller than this.
+const MIN_SIZE: f32 = 4.0;
+
+const TURN_STEP_DEGREES: f32 = 15.0;
+const FIELD_OF_VIEW_STEP_DEGREES: f32 = 10.0;
+const RANGE_STEP: f32 = 10.0;
+
+/// The range that an NPC with unlimited range is given when its range is first shortened.
+const INITIAL_RANGE: f32 = 300.0;
+
+/// The file that a level is saved to when it wasn't loaded from one.
+const DEFAULT_LEVEL_PATH: &str = "level.ron";
+
+/// The area covered by a wall or an occluder.
+fn object_rect(level: &Level, object: LevelObject) -> Option<Rect> {
+    match object.kind {
+        LevelObjectKind::Wall => level
+            .walls
+            .get(object.index)
+            .map(|wall| Rect::from_center_size(wall.position, wall.size)),
+        LevelObjectKind::Occluder => level
+            .occluders
+            .get(object.index)
+            .map(|occluder| Rect::from_corners(occluder.top_left, occluder.bottom_right)),
+        _ => None,
+    }
+}
+
+fn set_object_rect(level: &mut Level, object: LevelObject, rect: Rect) {
+    match object.kind {
+        LevelObjectKind::Wall => {
+            if let Some(wall) = level.walls.get_mut(object.index) {
+                wall.position = rect.center();
+                wall.size = rect.size();
+            }
+        }
+        LevelObjectKind::Occluder => {
+            if let Some(occluder) = level.occluders.get_mut(object.index) {
+                occluder.top_left = Vec2::new(rect.min.x, rect.max.y);
+                occluder.bottom_right = Vec2::new(rect.max.x, rect.min.y);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn object_position(level: &Level, object: LevelObject) -> Option<Vec2> {
+    match object.kind {
+        LevelObjectKind::Player => level
+            .players
+            .get(object.index)
+            .map(|player| player.position),
+        LevelObjectKind::Npc => level.npcs.get(object.index).map(|npc| npc.position),
+        LevelObjectKind::Light => level.lights.get(object.index).map(|light| light.position),
+        LevelObjectKind::Wall | LevelObjectKind::Occluder => {
+            object_rect(level, object).map(|rect| rect.center())
+        }
+        // Portals are edited in the level file.
+        LevelObjectKind::Portal => None,
+    }
+}
+
+fn set_object_position(level: &mut Level, object: LevelObject, position: Vec2) {
+    match object.kind {
+        LevelObjectKind::Player => {
+            if let Some(player) = level.players.get_mut(object.index) {
+                player.position = position;
+            }
+        }
+        LevelObjectKind::Npc => {
+            if let Some(npc) = level.npcs.get_mut(object.index) {
+                npc.position = position;
+            }
+        }
+        LevelObjectKind::Light => {
+            if let Some(light) = level.lights.get_mut(object.index) {
+                light.position = position;
+            }
+        }
+        LevelObjectKind::Wall | LevelObjectKind::Occluder => {
+            if let Some(rect) = object_rect(level, object) {
+                set_object_rect(level, object, Rect::from_center_size(position, rect.size()));
+            }
+        }
+        LevelObjectKind::Portal => {}
+    }
+}
+
+/// The editable object under `point`. NPCs and lights are found before the walls and occluders
+/// that they might be standing on, and later objects before earlier ones.
+fn object_at(level: &Level, point: Vec2) -> Option<LevelObject> {
+    let near = |position: Vec2| position.distance(point) <= GRAB_RADIUS;
+
+    let npcs = (0..level.npcs.len())
+        .rev()
+        .filter(|index| near(level.npcs[*index].position))
+        .map(|index| LevelObject {
+            kind: LevelObjectKind::Npc,
+            index,
+        });
+    let lights = (0..level.lights.len())
+        .rev()
+        .filter(|index| near(level.lights[*index].position))
+        .map(|index| LevelObject {
+            kind: LevelObjectKind::Light,
+            index,
+        });
+    let walls = (0..level.walls.len()).rev().map(|index| LevelObject {
+        kind: LevelObjectKind::Wall,
+        index,
+    });
+    let occluders = (0..level.occluders.len()).rev().map(|index| LevelObject {
+        kind: LevelObjectKind::Occluder,
+        index,
+    });
+    let rects = walls
+        .chain(occluders)
+        .filter(|object| object_rect(level, *object).is_some_and(|rect| rect.contains(point)));
+
+    npcs.chain(lights).chain(rects).next()
+}
+
+#[test]
+fn object_at_test_1() {
+    let level = Level::default();
+
+    assert_eq!(
+        object_at(&level, Vec2::new(-50.0, 40.0)),
+        Some(LevelObject {
+            kind: LevelObjectKind::Wall,
+            index: 0
+        })
+    );
+    assert_eq!(
+        object_at(&level, Vec2::new(-102.0, 3.0)),
+        Some(LevelObject {
+            kind: LevelObjectKind::Npc,
+            index: 0
+        })
+    );
+    assert_eq!(object_at(&level, Vec2::new(0.0, 80.0)), None);
+}
+
+/// The corner of `rect` opposite the one under `point`.
+fn opposite_corner(rect: Rect, point: Vec2) -> Option<Vec2> {
+    [
+        (rect.min, rect.max),
+        (rect.max, rect.min),
+        (
+            Vec2::new(rect.min.x, rect.max.y),
+            Vec2::new(rect.max.x, rect.min.y),
+        ),
+        (
+            Vec2::new(rect.max.x, rect.min.y),
+            Vec2::new(rect.min.x, rect.max.y),
+        ),
+    ]
+    .into_iter()
+    .find(|(corner, _)| corner.distance(point) <= GRAB_RADIUS)
+    .map(|(_, opposite)| opposite)
+}
+
+/// The rectangle spanned by `anchor` and `point`, grown to at least [`MIN_SIZE`] away from the
+/// anchor.
+fn resized_rect(anchor: Vec2, point: Vec2) -> Rect {
+    let offset = point - anchor;
+    let grow = |offset: f32| {
+        if offset.abs() >= MIN_SIZE {
+            offset
+        } else if offset < 0.0 {
+            -MIN_SIZE
+        } else {
+            MIN_SIZE
+        }
+    };
+    Rect::from_corners(anchor, anchor + Vec2::new(grow(offset.x), grow(offset.y)))
+}
+
+#[test]
+fn resized_rect_test_1() {
+    let rect = resized_rect(Vec2::new(10.0, 10.0), Vec2::new(0.0, 12.0));
+    assert_eq!(rect.min, Vec2::new(0.0, 10.0));
+    assert_eq!(rect.max, Vec2::new(10.0, 14.0));
+}
+
+fn remove_object(level: &mut Level, object: LevelObject) {
+    fn remove<T>(items: &mut Vec<T>, index: usize) {
+        if index < items.len() {
+            items.remove(index);
+        }
+    }
+
+    match object.kind {
+        // The level must keep at least one player.
+        LevelObjectKind::Player => {}
+        LevelObjectKind::Wall => remove(&mut level.walls, object.index),
+        LevelObjectKind::Occluder => remove(&mut level.occluders, object.index),
+        LevelObjectKind::Npc => remove(&mut level.npcs, object.index),
+        LevelObjectKind::Light => remove(&mut level.lights, object.index),
+        LevelObjectKind::Portal => remove(&mut level.portals, object.index),
+    }
+}
+
+/// The world position under the cursor, as seen by the topmost player camera under it.
+fn cursor_world_position(
+    window: &Window,
+    cameras: &Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+) -> Option<Vec2> {
+    // The cursor's origin is the bottom left of the window, and a viewport's is its top left.
+    let cursor = window.cursor_position()?;
+    let cursor_from_top = Vec2::new(cursor.x, window.height() - cursor.y);
+
+    cameras
+        .iter()
+        .filter_map(|(camera, camera_transform)| {
+            let (min, max) = camera.logical_viewport_rect()?;
+            if !(Rect { min, max }).contains(cursor_from_top) {
+                return None;
+            }
+            let viewport_position = Vec2::new(cursor_from_top.x - min.x, max.y - cursor_from_top.y);
+            let ray = camera.viewport_to_world(camera_transform, viewport_position)?;
+            Some((camera.order, ray.origin.truncate()))
+        })
+        .max_by_key(|(order, _)| *order)
+        .map(|(_, position)| position)
+}
+
+fn toggle_editor(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    mut sight_config: ResMut<SightConfig>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F2) {
+        return;
+    }
+
+    editor.enabled = !editor.enabled;
+    info!("editor: {}", if editor.enabled { "on" } else { "off" });
+
+    if editor.enabled {
+        editor.display_occluders = sight_config.display_occluders;
+        sight_config.display_occluders = true;
+    } else {
+        editor.selection = None;
+        editor.drag = None;
+        sight_config.display_occluders = editor.display_occluders;
+    }
+}
+
+fn edit_level(
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+    mut editor: ResMut<Editor>,
+    mut level: ResMut<Level>,
+) {
+    let cursor = windows
+        .get_single()
+        .ok()
+        .and_then(|window| cursor_world_position(window, &cameras));
+
+    // Edits are made to a copy, so that the level is only marked as changed when it is.
+    let mut edited = level.clone();
+
+    if let Some(cursor) = cursor {
+        if mouse_input.just_pressed(MouseButton::Left) {
+            let handle = editor.selection.and_then(|selection| {
+                let rect = object_rect(&edited, selection)?;
+                opposite_corner(rect, cursor)
+            });
+
+            if let Some(anchor) = handle {
+                editor.drag = Some(Drag::Resize { anchor });
+            } else if keyboard_input.pressed(KeyCode::LShift) {
+                edited.walls.push(WallSpec {
+                    position: cursor,
+                    size: Vec2::splat(MIN_SIZE),
+                    layers: default(),
+                    opacity: 1.0,
+                    tint: Color::WHITE,
+                    facing: default(),
+                    mirror: false,
+                    height: None,
+                });
+                editor.selection = Some(LevelObject {
+                    kind: LevelObjectKind::Wall,
+                    index: edited.walls.len() - 1,
+                });
+                editor.drag = Some(Drag::Resize { anchor: cursor });
+            } else {
+                editor.selection = object_at(&edited, cursor);
+                editor.drag = editor.selection.and_then(|selection| {
+                    let position = object_position(&edited, selection)?;
+                    Some(Drag::Move {
+                        grab_offset: cursor - position,
+                    })
+                });
+            }
+        }
+
+        if mouse_input.pressed(MouseButton::Left) {
+            if let (Some(selection), Some(drag)) = (editor.selection, editor.drag) {
+                match drag {
+                    Drag::Move { grab_offset } => {
+                        set_object_position(&mut edited, selection, cursor - grab_offset)
+                    }
+                    Drag::Resize { anchor } => {
+                        set_object_rect(&mut edited, selection, resized_rect(anchor, cursor))
+                    }
+                }
+            }
+        } else {
+            editor.drag = None;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::N) {
+            edited.npcs.push(NpcSpec {
+                position: cursor,
+                facing: 0.0,
+                elevation: 0.0,
+                sight: default(),
+                patrol: Vec::new(),
+                flashlight: None,
+            });
+            editor.selection = Some(LevelObject {
+                kind: LevelObjectKind::Npc,
+                index: edited.npcs.len() - 1,
+            });
+        }
+
+        if keyboard_input.just_pressed(KeyCode::L) {
+            let team = edited
+                .players
+                .iter()
+                .find_map(|player| player.team)
+                .unwrap_or_default();
+            edited.lights.push(LightSpec {
+                position: cursor,
+                team,
+            });
+            editor.selection = Some(LevelObject {
+                kind: LevelObjectKind::Light,
+                index: edited.lights.len() - 1,
+            });
+        }
+    }
+
+    if let Some(selection) = editor.selection {
+        if keyboard_input.any_just_pressed([KeyCode::Delete, KeyCode::Back]) {
+            remove_object(&mut edited, selection);
+            editor.selection = None;
+            editor.drag = None;
+        }
+    }
+
+    let selected_npc = editor.selection.and_then(|selection| {
+        (selection.kind == LevelObjectKind::Npc)
+            .then(|| edited.npcs.get_mut(selection.index))
+            .flatten()
+    });
+    if let Some(npc) = selected_npc {
+        if keyboard_input.just_pressed(KeyCode::Q) {
+            npc.facing += TURN_STEP_DEGREES;
+        }
+        if keyboard_input.just_pressed(KeyCode::E) {
+            npc.facing -= TURN_STEP_DEGREES;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::LBracket) {
+            let field_of_view = npc.sight.field_of_view.unwrap_or(360.0);
+            npc.sight.field_of_view =
+                Some((field_of_view - FIELD_OF_VIEW_STEP_DEGREES).max(FIELD_OF_VIEW_STEP_DEGREES));
+        }
+        if keyboard_input.just_pressed(KeyCode::RBracket) {
+            npc.sight.field_of_view = npc
+                .sight
+                .field_of_view
+                .map(|field_of_view| field_of_view + FIELD_OF_VIEW_STEP_DEGREES)
+                .filter(|field_of_view| *field_of_view < 360.0);
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Minus) {
+            let range = npc.sight.range.unwrap_or(INITIAL_RANGE + RANGE_STEP);
+            npc.sight.range = Some((range - RANGE_STEP).max(RANGE_STEP));
+        }
+        if keyboard_input.just_pressed(KeyCode::Equals) {
+            npc.sight.range = npc.sight.range.map(|range| range + RANGE_STEP);
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Key0) {
+            npc.sight.range = None;
+            npc.sight.field_of_view = None;
+        }
+    }
+
+    if edited != *level {
+        *level = edited;
+    }
+}
+
+/// Saves the level on Ctrl+S. The S key is then reset, so that it doesn't also move a player
+/// controlled with WASD, either when it's pressed or when it's released.
+fn save_level(
+    mut commands: Commands,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    level: Res<Level>,
+    level_file: Option<ResMut<LevelFile>>,
+) {
+    if !(keyboard_input.pressed(KeyCode::LControl) && keyboard_input.just_pressed(KeyCode::S)) {
+        return;
+    }
+    keyboard_input.reset(KeyCode::S);
+
+    let result = match level_file {
+        Some(mut level_file) => level_file.save(&level).map(|_| level_file.path.clone()),
+        None => {
+            let mut level_file = LevelFile::new(DEFAULT_LEVEL_PATH);
+            let result = level_file.save(&level).map(|_| level_file.path.clone());
+            commands.insert_resource(level_file);
+            result
+        }
+    };
+    match result {
+        Ok(path) => info!("saved {}", path.display()),
+        Err(err) => warn!("couldn't save the level: {}", err),
+    }
+}
+
+#[test]
+fn save_level_test_1() {
+    use bevy::input::{keyboard::KeyboardInput, ButtonState};
+
+    use crate::{movement::Direction, player::Player};
+
+    // The file is unique to this run, so that concurrent test runs don't share it.
+    let path = std::env::temp_dir().join(format!(
+        "boxybox_save_level_test_1_{}.ron",
+        std::process::id()
+    ));
+    let mut app = crate::headless::app(Level::default());
+    app.insert_resource(LevelFile::new(&path));
+    app.world.resource_mut::<Editor>().enabled = true;
+    app.update();
+
+    let send_keys = |app: &mut App, state: ButtonState| {
+        for key_code in [KeyCode::LControl, KeyCode::S] {
+            app.world.send_event(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key_code),
+                state,
+            });
+        }
+        app.update();
+    };
+    let player_direction = |app: &mut App| {
+        app.world
+            .query_filtered::<&Direction, With<Player>>()
+            .single(&app.world)
+            .value
+    };
+
+    // Saving doesn't move the player that S moves down, neither when pressed nor when released.
+    send_keys(&mut app, ButtonState::Pressed);
+    let saved = std::fs::remove_file(&path).is_ok();
+    assert!(saved);
+    assert_eq!(player_direction(&mut app), Vec2::ZERO);
+    send_keys(&mut app, ButtonState::Released);
+    assert_eq!(player_direction(&mut app), Vec2::ZERO);
+}
+
+/// Marks the selected NPC or light.
+#[derive(Component)]
+struct SelectionMarker;
+
+/// Shows handles on the selected wall or occluder, and a marker under the selected NPC or light.
+fn show_selection(
+    mut commands: Commands,
+    editor: Res<Editor>,
+    objects: Query<(Entity, &LevelObject)>,
+    with_handles: Query<Entity, With<OccluderHandles>>,
+    markers: Query<(Entity, &Parent), With<SelectionMarker>>,
+) {
+    let selected = editor.selection.and_then(|selection| {
+        objects
+            .iter()
+            .find(|(_, object)| **object == selection)
+            .map(|(entity, object)| (entity, object.kind))
+    });
+
+    let (handles_entity, marker_entity) = match selected {
+        Some((entity, LevelObjectKind::Wall | LevelObjectKind::Occluder)) => (Some(entity), None),
+        Some((entity, _)) => (None, Some(entity)),
+        None => (None, None),
+    };
+
+    for entity in with_handles.iter() {
+        if Some(entity) != handles_entity {
+            commands.entity(entity).remove::<OccluderHandles>();
+        }
+    }
+    if let Some(entity) = handles_entity {
+        if !with_handles.contains(entity) {
+            commands.entity(entity).insert(OccluderHandles);
+        }
+    }
+
+    for (entity, parent) in markers.iter() {
+        if Some(parent.get()) != marker_entity {
+            let mut marker_commands = commands.entity(entity);
+            marker_commands.remove_parent();
+            marker_commands.despawn_recursive();
+        }
+    }
+    if let Some(entity) = marker_entity {
+        if !markers.iter().any(|(_, parent)| parent.get() == entity) {
+            commands.entity(entity).with_children(|parent| {
+                parent.spawn((
+                    SelectionMarker,
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::YELLOW,
+                            custom_size: Some(Vec2::splat(2.0 * GRAB_RADIUS)),
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(0.0, 0.0, -0.01),
+                        ..default()
+                    },
+                ));
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub struct EditorSet;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Editor>();
+
+        app.configure_set(EditorSet.after(ViewportSet).before(DebugSet));
+
+        app.add_system(
+            toggle_editor
+                .in_base_set(CoreSet::PreUpdate)
+                .after(InputSystem)
+                .after(toggle_debug_overlay),
+        )
+        .add_system(
+            save_level
+                .run_if(|editor: Res<Editor>| editor.enabled)
+                .in_base_set(CoreSet::PreUpdate)
+                .after(toggle_editor)
+                .before(reload_level_file),
+        )
+        .add_systems(
+            (
+                edit_level.run_if(|editor: Res<Editor>| editor.enabled),
+                show_selection,
+            )
+                .chain()
+                .in_set(EditorSet),
+        );
+    }
+}
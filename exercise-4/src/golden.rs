@@ -0,0 +1,18 @@
+use std::path::Path;
+
+/// Checks `actual` against the golden file at `path`, relative to the crate, or overwrites the
+/// file when the `UPDATE_GOLDENS` environment variable is set.
+pub fn check(path: &str, actual: &[u8]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+    if std::env::var_os("UPDATE_GOLDENS").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read(&path).unwrap();
+    assert!(
+        expected == actual,
+        "{} doesn't match; rerun with UPDATE_GOLDENS=1 to update it",
+        path.display()
+    );
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+use bevy::{
+    asset::AssetPlugin,
+    input::InputPlugin,
+    prelude::*,
+    render::camera::CameraProjection,
+    time::{TimeSystem, TimeUpdateStrategy},
+};
+
+use crate::{level::Level, viewport::PlayerCamera, GamePlugin};
+
+/// The time that passes in each update of a headless app, as if it ran at 60 frames per second.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// The size of the view given to each player's camera, in place of a window's.
+pub const HEADLESS_VIEW_SIZE: Vec2 = Vec2::new(1280.0, 720.0);
+
+/// Runs the game's logic without a window or a GPU, for use with [`crate::GamePlugin`].
+///
+/// Each update advances time by exactly [`FRAME_DURATION`], so runs are reproducible.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Mesh>()
+            .add_asset::<Image>()
+            .add_asset::<TextureAtlas>()
+            .add_asset::<ColorMaterial>()
+            .insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+
+        app.add_system(
+            advance_headless_time
+                .in_base_set(CoreSet::First)
+                .before(TimeSystem),
+        )
+        .add_system(size_headless_cameras.in_base_set(CoreSet::PreUpdate));
+    }
+}
+
+/// A headless app that plays `level`.
+pub fn app(level: Level) -> App {
+    let mut app = App::new();
+    app.insert_resource(level)
+        .add_plugin(HeadlessPlugin)
+        .add_plugin(GamePlugin);
+    app
+}
+
+fn advance_headless_time(mut time_update_strategy: ResMut<TimeUpdateStrategy>) {
+    if let TimeUpdateStrategy::ManualInstant(instant) = time_update_strategy.as_mut() {
+        *instant += FRAME_DURATION;
+    }
+}
+
+/// Sizes the player cameras' projections as a window would, so that shadows can be cast.
+fn size_headless_cameras(mut projections: Query<&mut OrthographicProjection, With<PlayerCamera>>) {
+    for mut projection in projections.iter_mut() {
+        let area = Rect::from_center_size(Vec2::ZERO, HEADLESS_VIEW_SIZE * projection.scale);
+        if projection.area != area {
+            projection.update(HEADLESS_VIEW_SIZE.x, HEADLESS_VIEW_SIZE.y);
+        }
+    }
+}
@@ -0,0 +1,1205 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controls::Controlled,
+    npc::{NpcBundle, Patrol},
+    player::PlayerBundle,
+    sight::{
+        fully_opaque, EdgeFacings, Occluder, OcclusionLayers, Portal, SightSampling, Sighted, Team,
+        VisibilityShape,
+    },
+    spotlight::Spotlight,
+    sun::{DayNightCycle, Sunlight},
+    team::TeamCameraBundle,
+    viewport::MAX_ELEVATION,
+    wall::WallBundle,
+};
+
+/// A level, as described in a [RON](https://github.com/ron-rs/ron) file.
+///
+/// See `levels/default.ron` for an example.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub players: Vec<PlayerSpawn>,
+    #[serde(default)]
+    pub walls: Vec<WallSpec>,
+    /// Invisible occluders.
+    #[serde(default)]
+    pub occluders: Vec<OccluderSpec>,
+    #[serde(default)]
+    pub npcs: Vec<NpcSpec>,
+    #[serde(default)]
+    pub lights: Vec<LightSpec>,
+    #[serde(default)]
+    pub portals: Vec<PortalSpec>,
+    /// Sunlight or moonlight for outdoor levels, which casts the same shadows in every view.
+    #[serde(default)]
+    pub sun: Option<SunSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSpawn {
+    pub position: Vec2,
+    /// How high the player stands, such as on a balcony, like [`NpcSpec::elevation`].
+    #[serde(default)]
+    pub elevation: f32,
+    #[serde(default)]
+    pub controls: ControlScheme,
+    #[serde(default)]
+    pub team: Option<u32>,
+    /// A light that the player carries, which points the way they're going.
+    #[serde(default)]
+    pub flashlight: Option<SpotlightSpec>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlScheme {
+    #[default]
+    Wasd,
+    Arrows,
+}
+
+impl ControlScheme {
+    pub fn controls(&self) -> Controlled {
+        match self {
+            ControlScheme::Wasd => Controlled::WASD,
+            ControlScheme::Arrows => Controlled::ARROWS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WallSpec {
+    /// The centre of the wall.
+    pub position: Vec2,
+    pub size: Vec2,
+    /// What the wall blocks, as a mask of [`OcclusionLayers`]: 1 for sight, 2 for light and 4 for
+    /// movement. Walls block everything by default.
+    #[serde(default)]
+    pub layers: OcclusionLayers,
+    /// Like [`Occluder::opacity`]. A semi-transparent wall is drawn in its tint.
+    #[serde(default = "fully_opaque")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub tint: Color,
+    /// Which side each edge blocks sight and light from, like [`Occluder::facing`]. For example,
+    /// `(bottom: Outside, top: Neither, left: Neither, right: Neither)` is a cliff edge that can
+    /// be seen down from above but not up from below.
+    #[serde(default)]
+    pub facing: EdgeFacings,
+    /// Whether the wall reflects sight and light, like [`Occluder::mirror`]. Mirrors are drawn in
+    /// silver.
+    #[serde(default)]
+    pub mirror: bool,
+    /// How tall the wall is, like [`Occluder::height`]. Walls can't be seen over by default.
+    #[serde(default)]
+    pub height: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OccluderSpec {
+    pub top_left: Vec2,
+    pub bottom_right: Vec2,
+    /// Like [`WallSpec::layers`].
+    #[serde(default)]
+    pub layers: OcclusionLayers,
+    #[serde(default = "fully_opaque")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub tint: Color,
+    #[serde(default)]
+    pub facing: EdgeFacings,
+    #[serde(default)]
+    pub mirror: bool,
+    #[serde(default)]
+    pub height: Option<f32>,
+}
+
+/// A pair of edges that sight and light pass through, like [`Portal`]. Each edge is usually along
+/// the side of a wall.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortalSpec {
+    pub a_start: Vec2,
+    pub a_end: Vec2,
+    pub b_start: Vec2,
+    pub b_end: Vec2,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NpcSpec {
+    pub position: Vec2,
+    /// The direction the NPC faces, in degrees anticlockwise from the X axis.
+    #[serde(default)]
+    pub facing: f32,
+    /// How high the NPC stands above the ground, such as on a watchtower, which lets it see over
+    /// walls lower than it. It's the Z of the NPC's translation, up to
+    /// [`crate::viewport::MAX_ELEVATION`].
+    #[serde(default)]
+    pub elevation: f32,
+    #[serde(default)]
+    pub sight: SightSpec,
+    /// Waypoints that the NPC walks through in a loop, starting from and returning to its
+    /// `position`.
+    #[serde(default)]
+    pub patrol: Vec<Vec2>,
+    /// A light that the NPC carries, which points the way it faces. A search light is an NPC with
+    /// a flashlight that stands still.
+    #[serde(default)]
+    pub flashlight: Option<SpotlightSpec>,
+}
+
+/// The parameters of a [`Sighted`] entity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SightSpec {
+    /// How sight lines are drawn, like [`Sighted::sampling`]. A single line to the centre of what's
+    /// seen by default, or `Bounds` for lines across its whole shape.
+    pub sampling: SamplingSpec,
+    pub threshold: f32,
+    pub range: Option<f32>,
+    /// The angle of the field of view, in degrees.
+    pub field_of_view: Option<f32>,
+    /// The layers of the occluders that block the view, like [`WallSpec::layers`].
+    pub blocked_by: OcclusionLayers,
+}
+
+impl Default for SightSpec {
+    fn default() -> Self {
+        Self {
+            sampling: SamplingSpec::Centre,
+            threshold: 0.5,
+            range: None,
+            field_of_view: None,
+            blocked_by: OcclusionLayers::SIGHT,
+        }
+    }
+}
+
+impl SightSpec {
+    pub fn sighted(&self) -> Sighted {
+        Sighted {
+            sampling: match self.sampling {
+                SamplingSpec::Centre => SightSampling::Centre,
+                SamplingSpec::Bounds => SightSampling::Bounds {
+                    eye: VisibilityShape::Point,
+                },
+            },
+            threshold: self.threshold,
+            range: self.range,
+            field_of_view: self.field_of_view.map(f32::to_radians),
+            blocked_by: self.blocked_by,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SamplingSpec {
+    #[default]
+    Centre,
+    Bounds,
+}
+
+/// A cone of light, like [`Spotlight`], with its angles in degrees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpotlightSpec {
+    /// Where the light points, in degrees anticlockwise from the way its carrier faces.
+    pub direction: f32,
+    /// How wide the cone is, in degrees.
+    pub angle: f32,
+    pub range: f32,
+    pub softness: f32,
+    pub color: Color,
+}
+
+impl Default for SpotlightSpec {
+    fn default() -> Self {
+        let spotlight = Spotlight::default();
+        Self {
+            direction: spotlight.direction.to_degrees(),
+            angle: spotlight.angle.to_degrees(),
+            range: spotlight.range,
+            softness: spotlight.softness,
+            color: spotlight.color,
+        }
+    }
+}
+
+impl SpotlightSpec {
+    pub fn spotlight(&self) -> Spotlight {
+        Spotlight {
+            direction: self.direction.to_radians(),
+            angle: self.angle.to_radians(),
+            range: self.range,
+            softness: self.softness,
+            color: self.color,
+        }
+    }
+}
+
+/// A stationary light that lights up what it can see for the players on its team.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightSpec {
+    pub position: Vec2,
+    pub team: u32,
+}
+
+/// Light from the sun or the moon, like [`Sunlight`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SunSpec {
+    /// The direction that shadows point, in degrees anticlockwise from the X axis.
+    pub direction: f32,
+    /// How high the light is, in degrees above the horizon.
+    pub elevation: f32,
+    /// How long a whole day and night last, in seconds. If it's set, the level starts at noon,
+    /// with `direction` and `elevation` as the sun's at noon, and the sun moves across the sky
+    /// like [`DayNightCycle`].
+    #[serde(default)]
+    pub day_length: Option<f32>,
+}
+
+impl SunSpec {
+    pub fn sunlight(&self) -> Sunlight {
+        Sunlight {
+            direction: self.direction.to_radians(),
+            elevation: self.elevation.to_radians(),
+            ..default()
+        }
+    }
+
+    /// The cycle that moves the sun, if the level has one.
+    pub fn day_night_cycle(&self) -> Option<DayNightCycle> {
+        self.day_length.map(|length| DayNightCycle {
+            length,
+            time: 0.25,
+            noon_direction: self.direction.to_radians(),
+            noon_elevation: self.elevation.to_radians(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    /// The file isn't valid RON, or doesn't have the shape of a [`Level`].
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The file is well formed, but a value in it isn't allowed.
+    Invalid {
+        /// The path to the value, such as `npcs[1].sight.threshold`.
+        field: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for LevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "{}", err),
+            LevelError::Serialize(err) => write!(f, "{}", err),
+            LevelError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}: {}", line, column, message),
+            LevelError::Invalid { field, message } => write!(f, "{}: {}", field, message),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<std::io::Error> for LevelError {
+    fn from(value: std::io::Error) -> Self {
+        LevelError::Io(value)
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Reflect, FromReflect, Serialize, Deserialize,
+)]
+#[reflect(Default)]
+pub enum LevelObjectKind {
+    #[default]
+    Player,
+    Wall,
+    Occluder,
+    Npc,
+    Light,
+    Portal,
+}
+
+impl LevelObjectKind {
+    const ALL: [LevelObjectKind; 6] = [
+        LevelObjectKind::Player,
+        LevelObjectKind::Wall,
+        LevelObjectKind::Occluder,
+        LevelObjectKind::Npc,
+        LevelObjectKind::Light,
+        LevelObjectKind::Portal,
+    ];
+
+    /// The name of the level's list of objects of this kind.
+    fn field_name(&self) -> &'static str {
+        match self {
+            LevelObjectKind::Player => "players",
+            LevelObjectKind::Wall => "walls",
+            LevelObjectKind::Occluder => "occluders",
+            LevelObjectKind::Npc => "npcs",
+            LevelObjectKind::Light => "lights",
+            LevelObjectKind::Portal => "portals",
+        }
+    }
+}
+
+/// An entity that was spawned from the [`Level`], and where it's described.
+#[derive(
+    Component,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Reflect,
+    FromReflect,
+    Serialize,
+    Deserialize,
+)]
+#[reflect(Component, Default)]
+pub struct LevelObject {
+    pub kind: LevelObjectKind,
+    /// The object's index in the level's list of objects of its kind.
+    pub index: usize,
+}
+
+impl std::fmt::Display for LevelObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]", self.kind.field_name(), self.index)
+    }
+}
+
+/// Parses an object in the form that it's displayed, like `npcs[0]`.
+impl std::str::FromStr for LevelObject {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field_name, index) = s
+            .strip_suffix(']')
+            .and_then(|s| s.split_once('['))
+            .ok_or_else(|| format!("expected an object like npcs[0], got {}", s))?;
+        let kind = LevelObjectKind::ALL
+            .into_iter()
+            .find(|kind| kind.field_name() == field_name)
+            .ok_or_else(|| format!("no kind of object is called {}", field_name))?;
+        let index = index
+            .parse()
+            .map_err(|_| format!("{} isn't an index", index))?;
+        Ok(LevelObject { kind, index })
+    }
+}
+
+fn invalid(field: String, message: &str) -> LevelError {
+    LevelError::Invalid {
+        field,
+        message: message.to_string(),
+    }
+}
+
+fn validate_point(field: String, point: Vec2) -> Result<(), LevelError> {
+    if point.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid(field, "must be finite"))
+    }
+}
+
+fn validate_opacity(field: String, opacity: f32) -> Result<(), LevelError> {
+    if (0.0..=1.0).contains(&opacity) {
+        Ok(())
+    } else {
+        Err(invalid(field, "must be between 0 and 1"))
+    }
+}
+
+fn validate_height(field: String, height: Option<f32>) -> Result<(), LevelError> {
+    if height.map_or(true, |height| height.is_finite() && height > 0.0) {
+        Ok(())
+    } else {
+        Err(invalid(field, "must be positive"))
+    }
+}
+
+fn validate_elevation(field: String, elevation: f32) -> Result<(), LevelError> {
+    if (0.0..=MAX_ELEVATION).contains(&elevation) {
+        Ok(())
+    } else {
+        Err(invalid(
+            field,
+            &format!("must be between 0 and {}", MAX_ELEVATION),
+        ))
+    }
+}
+
+fn validate_flashlight(
+    field: String,
+    flashlight: &Option<SpotlightSpec>,
+) -> Result<(), LevelError> {
+    let Some(flashlight) = flashlight else {
+        return Ok(());
+    };
+    if !flashlight.direction.is_finite() {
+        return Err(invalid(format!("{}.direction", field), "must be finite"));
+    }
+    if !(flashlight.angle > 0.0 && flashlight.angle <= 360.0) {
+        return Err(invalid(
+            format!("{}.angle", field),
+            "must be more than 0 and at most 360 degrees",
+        ));
+    }
+    if !(flashlight.range.is_finite() && flashlight.range > 0.0) {
+        return Err(invalid(format!("{}.range", field), "must be positive"));
+    }
+    if !(0.0..=1.0).contains(&flashlight.softness) {
+        return Err(invalid(
+            format!("{}.softness", field),
+            "must be between 0 and 1",
+        ));
+    }
+    Ok(())
+}
+
+impl Level {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, LevelError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(LevelError::Serialize)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LevelError> {
+        let level: Level = ron::from_str(source).map_err(|err| LevelError::Parse {
+            line: err.position.line,
+            column: err.position.col,
+            message: err.code.to_string(),
+        })?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    pub fn validate(&self) -> Result<(), LevelError> {
+        if self.players.is_empty() {
+            return Err(invalid(
+                "players".to_string(),
+                "there must be at least one player",
+            ));
+        }
+
+        for (index, player) in self.players.iter().enumerate() {
+            validate_point(format!("players[{}].position", index), player.position)?;
+            validate_elevation(format!("players[{}].elevation", index), player.elevation)?;
+            validate_flashlight(format!("players[{}].flashlight", index), &player.flashlight)?;
+        }
+
+        for (index, wall) in self.walls.iter().enumerate() {
+            validate_point(format!("walls[{}].position", index), wall.position)?;
+            if !(wall.size.is_finite() && wall.size.x > 0.0 && wall.size.y > 0.0) {
+                return Err(invalid(
+                    format!("walls[{}].size", index),
+                    "must be positive",
+                ));
+            }
+            validate_opacity(format!("walls[{}].opacity", index), wall.opacity)?;
+            validate_height(format!("walls[{}].height", index), wall.height)?;
+        }
+
+        for (index, occluder) in self.occluders.iter().enumerate() {
+            validate_point(format!("occluders[{}].top_left", index), occluder.top_left)?;
+            validate_point(
+                format!("occluders[{}].bottom_right", index),
+                occluder.bottom_right,
+            )?;
+            if occluder.top_left.x >= occluder.bottom_right.x
+                || occluder.top_left.y <= occluder.bottom_right.y
+            {
+                return Err(invalid(
+                    format!("occluders[{}]", index),
+                    "top_left must be above and to the left of bottom_right",
+                ));
+            }
+            validate_opacity(format!("occluders[{}].opacity", index), occluder.opacity)?;
+            validate_height(format!("occluders[{}].height", index), occluder.height)?;
+        }
+
+        for (index, npc) in self.npcs.iter().enumerate() {
+            validate_point(format!("npcs[{}].position", index), npc.position)?;
+            for (waypoint_index, waypoint) in npc.patrol.iter().enumerate() {
+                validate_point(
+                    format!("npcs[{}].patrol[{}]", index, waypoint_index),
+                    *waypoint,
+                )?;
+            }
+            if !npc.facing.is_finite() {
+                return Err(invalid(format!("npcs[{}].facing", index), "must be finite"));
+            }
+            validate_elevation(format!("npcs[{}].elevation", index), npc.elevation)?;
+            validate_flashlight(format!("npcs[{}].flashlight", index), &npc.flashlight)?;
+            if !(0.0..=1.0).contains(&npc.sight.threshold) {
+                return Err(invalid(
+                    format!("npcs[{}].sight.threshold", index),
+                    "must be between 0 and 1",
+                ));
+            }
+            if npc
+                .sight
+                .range
+                .is_some_and(|range| range.is_nan() || range <= 0.0)
+            {
+                return Err(invalid(
+                    format!("npcs[{}].sight.range", index),
+                    "must be positive",
+                ));
+            }
+            if npc
+                .sight
+                .field_of_view
+                .is_some_and(|field_of_view| !(field_of_view > 0.0 && field_of_view <= 360.0))
+            {
+                return Err(invalid(
+                    format!("npcs[{}].sight.field_of_view", index),
+                    "must be more than 0 and at most 360 degrees",
+                ));
+            }
+        }
+
+        for (index, light) in self.lights.iter().enumerate() {
+            validate_point(format!("lights[{}].position", index), light.position)?;
+        }
+
+        for (index, portal) in self.portals.iter().enumerate() {
+            for (name, start, end) in [
+                ("a", portal.a_start, portal.a_end),
+                ("b", portal.b_start, portal.b_end),
+            ] {
+                validate_point(format!("portals[{}].{}_start", index, name), start)?;
+                validate_point(format!("portals[{}].{}_end", index, name), end)?;
+                if start == end {
+                    return Err(invalid(
+                        format!("portals[{}].{}_end", index, name),
+                        "must be different from the start",
+                    ));
+                }
+            }
+        }
+
+        if let Some(sun) = &self.sun {
+            if !sun.direction.is_finite() {
+                return Err(invalid("sun.direction".to_string(), "must be finite"));
+            }
+            if !(-90.0..=90.0).contains(&sun.elevation) {
+                return Err(invalid(
+                    "sun.elevation".to_string(),
+                    "must be between -90 and 90 degrees",
+                ));
+            }
+            if sun
+                .day_length
+                .is_some_and(|day_length| !(day_length.is_finite() && day_length > 0.0))
+            {
+                return Err(invalid("sun.day_length".to_string(), "must be positive"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn spawn(&self, commands: &mut Commands) {
+        for kind in LevelObjectKind::ALL {
+            for index in 0..self.len(kind) {
+                self.spawn_object(commands, LevelObject { kind, index });
+            }
+        }
+        self.insert_sun(commands);
+    }
+
+    /// Inserts the level's [`Sunlight`] and [`DayNightCycle`], or removes them if it has none.
+    fn insert_sun(&self, commands: &mut Commands) {
+        match &self.sun {
+            Some(sun) => {
+                commands.insert_resource(sun.sunlight());
+                match sun.day_night_cycle() {
+                    Some(cycle) => commands.insert_resource(cycle),
+                    None => commands.remove_resource::<DayNightCycle>(),
+                }
+            }
+            None => {
+                commands.remove_resource::<Sunlight>();
+                commands.remove_resource::<DayNightCycle>();
+            }
+        }
+    }
+
+    /// The occluders of the level's walls, and its invisible occluders.
+    pub fn occluders(&self) -> Vec<Occluder> {
+        self.walls
+            .iter()
+            .map(|wall| wall.bundle().occluder)
+            .chain(self.occluders.iter().map(|occluder| occluder.bundle().0))
+            .collect()
+    }
+
+    /// Where `object` is and what it can see, if it's something that sees.
+    pub fn viewer(&self, object: LevelObject) -> Option<(Transform, Sighted)> {
+        match object.kind {
+            LevelObjectKind::Player => self
+                .players
+                .get(object.index)
+                .map(|player| (player.transform(), Sighted::default())),
+            LevelObjectKind::Npc => self
+                .npcs
+                .get(object.index)
+                .map(|npc| (npc.transform(), npc.sight.sighted())),
+            LevelObjectKind::Light => self.lights.get(object.index).map(|light| {
+                let bundle = light.bundle();
+                (bundle.sprite_bundle.transform, bundle.sighted)
+            }),
+            LevelObjectKind::Wall | LevelObjectKind::Occluder | LevelObjectKind::Portal => None,
+        }
+    }
+
+    /// Whether `object` is described in the level.
+    pub fn contains(&self, object: LevelObject) -> bool {
+        object.index < self.len(object.kind)
+    }
+
+    /// The number of objects of a kind in the level.
+    fn len(&self, kind: LevelObjectKind) -> usize {
+        match kind {
+            LevelObjectKind::Player => self.players.len(),
+            LevelObjectKind::Wall => self.walls.len(),
+            LevelObjectKind::Occluder => self.occluders.len(),
+            LevelObjectKind::Npc => self.npcs.len(),
+            LevelObjectKind::Light => self.lights.len(),
+            LevelObjectKind::Portal => self.portals.len(),
+        }
+    }
+
+    fn spawn_object(&self, commands: &mut Commands, object: LevelObject) {
+        let index = object.index;
+        let entity = match object.kind {
+            LevelObjectKind::Player => self.players[index].spawn(commands),
+            LevelObjectKind::Wall => commands.spawn(self.walls[index].bundle()).id(),
+            LevelObjectKind::Occluder => commands.spawn(self.occluders[index].bundle()).id(),
+            LevelObjectKind::Npc => commands.spawn(self.npcs[index].bundle()).id(),
+            LevelObjectKind::Light => commands.spawn(self.lights[index].bundle()).id(),
+            LevelObjectKind::Portal => commands.spawn(self.portals[index].portal()).id(),
+        };
+        commands.entity(entity).insert(object);
+        if let Some(flashlight) = self.flashlight(object) {
+            commands.entity(entity).insert(flashlight.spotlight());
+        }
+    }
+
+    /// The light that `object` carries, if it's a player or an NPC with one.
+    fn flashlight(&self, object: LevelObject) -> Option<&SpotlightSpec> {
+        match object.kind {
+            LevelObjectKind::Player => self.players[object.index].flashlight.as_ref(),
+            LevelObjectKind::Npc => self.npcs[object.index].flashlight.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Updates an entity that was spawned from `object` to match the object's current
+    /// description, keeping the entity so that anything that refers to it stays valid.
+    fn update_object(&self, commands: &mut Commands, entity: Entity, object: LevelObject) {
+        let index = object.index;
+        match object.kind {
+            LevelObjectKind::Player => self.players[index].update(commands, entity),
+            LevelObjectKind::Wall => {
+                commands.entity(entity).insert(self.walls[index].bundle());
+            }
+            LevelObjectKind::Occluder => {
+                commands
+                    .entity(entity)
+                    .insert(self.occluders[index].bundle());
+            }
+            LevelObjectKind::Npc => {
+                commands.entity(entity).insert(self.npcs[index].bundle());
+            }
+            LevelObjectKind::Light => {
+                commands.entity(entity).insert(self.lights[index].bundle());
+            }
+            LevelObjectKind::Portal => {
+                commands.entity(entity).insert(self.portals[index].portal());
+            }
+        }
+        match self.flashlight(object) {
+            Some(flashlight) => commands.entity(entity).insert(flashlight.spotlight()),
+            None => commands.entity(entity).remove::<Spotlight>(),
+        };
+    }
+
+    /// The objects that differ between the levels, or that are only in one of them.
+    fn changed_objects(&self, other: &Level) -> Vec<LevelObject> {
+        fn changed<T: PartialEq>(kind: LevelObjectKind, a: &[T], b: &[T]) -> Vec<LevelObject> {
+            (0..a.len().max(b.len()))
+                .filter(|index| a.get(*index) != b.get(*index))
+                .map(|index| LevelObject { kind, index })
+                .collect()
+        }
+
+        LevelObjectKind::ALL
+            .into_iter()
+            .flat_map(|kind| match kind {
+                LevelObjectKind::Player => changed(kind, &self.players, &other.players),
+                LevelObjectKind::Wall => changed(kind, &self.walls, &other.walls),
+                LevelObjectKind::Occluder => changed(kind, &self.occluders, &other.occluders),
+                LevelObjectKind::Npc => changed(kind, &self.npcs, &other.npcs),
+                LevelObjectKind::Light => changed(kind, &self.lights, &other.lights),
+                LevelObjectKind::Portal => changed(kind, &self.portals, &other.portals),
+            })
+            .collect()
+    }
+}
+
+impl PlayerSpawn {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position.extend(self.elevation))
+    }
+
+    pub fn spawn(&self, commands: &mut Commands) -> Entity {
+        let mut entity = commands.spawn(
+            PlayerBundle::default()
+                .with_transform(self.transform())
+                .with_controls(self.controls.controls()),
+        );
+        if let Some(team) = self.team {
+            entity.insert(Team(team));
+        }
+        entity.id()
+    }
+
+    /// Updates a spawned player to match this spawn, leaving it where it has moved to on the
+    /// ground but standing at this spawn's elevation.
+    fn update(&self, commands: &mut Commands, player: Entity) {
+        let elevation = self.elevation;
+        let mut entity = commands.entity(player);
+        entity.add(move |player, world: &mut World| {
+            if let Some(mut transform) = world.get_mut::<Transform>(player) {
+                transform.translation.z = elevation;
+            }
+        });
+        entity.insert(self.controls.controls());
+        match self.team {
+            Some(team) => entity.insert(Team(team)),
+            None => entity.remove::<Team>(),
+        };
+    }
+}
+
+impl WallSpec {
+    pub fn bundle(&self) -> WallBundle {
+        WallBundle::default()
+            .with_transform(Transform::from_translation(self.position.extend(0.0)))
+            .with_size(self.size)
+            .with_layers(self.layers)
+            .with_opacity(self.opacity, self.tint)
+            .with_facing(self.facing)
+            .with_mirror(self.mirror)
+            .with_height(self.height)
+    }
+}
+
+impl OccluderSpec {
+    pub fn bundle(&self) -> (Occluder, SpatialBundle) {
+        let centre = (self.top_left + self.bottom_right) / 2.0;
+        (
+            Occluder {
+                top_left: self.top_left.extend(0.0),
+                bottom_right: self.bottom_right.extend(0.0),
+                layers: self.layers,
+                opacity: self.opacity,
+                tint: self.tint,
+                facing: self.facing,
+                mirror: self.mirror,
+                height: self.height,
+            },
+            SpatialBundle::from_transform(Transform::from_translation(centre.extend(0.0))),
+        )
+    }
+}
+
+impl PortalSpec {
+    pub fn portal(&self) -> Portal {
+        Portal {
+            a_start: self.a_start.extend(0.0),
+            a_end: self.a_end.extend(0.0),
+            b_start: self.b_start.extend(0.0),
+            b_end: self.b_end.extend(0.0),
+        }
+    }
+}
+
+impl NpcSpec {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position.extend(self.elevation))
+            .with_rotation(Quat::from_rotation_z(self.facing.to_radians()))
+    }
+
+    /// The NPC's patrol route, which is empty if it stands still.
+    pub fn patrol(&self) -> Patrol {
+        if self.patrol.is_empty() {
+            return Patrol::default();
+        }
+        Patrol::new(
+            std::iter::once(self.position)
+                .chain(self.patrol.iter().copied())
+                .collect(),
+        )
+    }
+
+    pub fn bundle(&self) -> NpcBundle {
+        NpcBundle::default()
+            .with_transform(self.transform())
+            .with_sighted(self.sight.sighted())
+            .with_patrol(self.patrol())
+    }
+}
+
+impl LightSpec {
+    pub fn bundle(&self) -> TeamCameraBundle {
+        TeamCameraBundle::new(Team(self.team))
+            .with_transform(Transform::from_translation(self.position.extend(0.0)))
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            players: vec![PlayerSpawn {
+                position: Vec2::ZERO,
+                elevation: 0.0,
+                controls: ControlScheme::Wasd,
+                team: None,
+                flashlight: None,
+            }],
+            walls: vec![
+                WallSpec {
+                    position: Vec2::new(-50.0, 0.0),
+                    size: Vec2::new(10.0, 100.0),
+                    layers: OcclusionLayers::ALL,
+                    opacity: 1.0,
+                    tint: Color::WHITE,
+                    facing: EdgeFacings::default(),
+                    mirror: false,
+                    height: None,
+                },
+                WallSpec {
+                    position: Vec2::new(50.0, 40.0),
+                    size: Vec2::new(10.0, 40.0),
+                    layers: OcclusionLayers::ALL,
+                    opacity: 1.0,
+                    tint: Color::WHITE,
+                    facing: EdgeFacings::default(),
+                    mirror: false,
+                    height: None,
+                },
+                WallSpec {
+                    position: Vec2::new(50.0, -40.0),
+                    size: Vec2::new(10.0, 40.0),
+                    layers: OcclusionLayers::ALL,
+                    opacity: 1.0,
+                    tint: Color::WHITE,
+                    facing: EdgeFacings::default(),
+                    mirror: false,
+                    height: None,
+                },
+            ],
+            occluders: Vec::new(),
+            npcs: vec![NpcSpec {
+                position: Vec2::new(-100.0, 0.0),
+                facing: 0.0,
+                elevation: 0.0,
+                sight: SightSpec::default(),
+                patrol: Vec::new(),
+                flashlight: None,
+            }],
+            lights: Vec::new(),
+            portals: Vec::new(),
+            sun: None,
+        }
+    }
+}
+
+/// The file that the [`Level`] was loaded from, which is reloaded when it changes.
+#[derive(Resource, Debug)]
+pub struct LevelFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+    poll_timer: Timer,
+}
+
+impl LevelFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+        Self {
+            path,
+            modified,
+            poll_timer: Timer::from_seconds(0.5, TimerMode::Repeating),
+        }
+    }
+
+    /// Writes `level` to the file, without it being reloaded.
+    pub fn save(&mut self, level: &Level) -> Result<(), LevelError> {
+        std::fs::write(&self.path, level.to_ron()?)?;
+        self.modified = modified_time(&self.path);
+        Ok(())
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Replaces the [`Level`] when its file changes. The file is left alone if it's invalid.
+pub(crate) fn reload_level_file(
+    time: Res<Time>,
+    mut level_file: ResMut<LevelFile>,
+    mut level: ResMut<Level>,
+) {
+    if !level_file.poll_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = modified_time(&level_file.path);
+    if modified == level_file.modified {
+        return;
+    }
+    level_file.modified = modified;
+
+    match Level::load(&level_file.path) {
+        Ok(new_level) => {
+            if *level != new_level {
+                info!("reloading {}", level_file.path.display());
+                *level = new_level;
+            }
+        }
+        Err(err) => {
+            warn!("{}: {}", level_file.path.display(), err);
+        }
+    }
+}
+
+/// Spawns the [`Level`]'s objects, and updates the ones that change in place.
+///
+/// Players keep their positions on the ground, so that they stay where they've moved to.
+fn spawn_level(
+    mut commands: Commands,
+    level: Res<Level>,
+    mut spawned_level: Local<Option<Level>>,
+    objects: Query<(Entity, &LevelObject)>,
+) {
+    let Some(spawned_level) = spawned_level.as_mut() else {
+        level.spawn(&mut commands);
+        *spawned_level = Some(level.clone());
+        return;
+    };
+
+    for object in spawned_level.changed_objects(&level) {
+        let entity = objects
+            .iter()
+            .find(|(_, spawned_object)| **spawned_object == object)
+            .map(|(entity, _)| entity);
+
+        let in_level = level.contains(object);
+        match entity {
+            Some(entity) if in_level => level.update_object(&mut commands, entity, object),
+            Some(entity) => commands.entity(entity).despawn_recursive(),
+            None if in_level => level.spawn_object(&mut commands, object),
+            None => {}
+        }
+    }
+    if spawned_level.sun != level.sun {
+        level.insert_sun(&mut commands);
+    }
+
+    *spawned_level = level.clone();
+}
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Level>()
+            .register_type::<LevelObject>()
+            .register_type::<LevelObjectKind>();
+
+        app.add_systems(
+            (
+                reload_level_file.run_if(resource_exists::<LevelFile>()),
+                spawn_level.run_if(resource_changed::<Level>()),
+            )
+                .chain()
+                .in_base_set(CoreSet::PreUpdate),
+        );
+    }
+}
+
+#[test]
+fn spawn_level_test_1() {
+    use crate::player::Player;
+
+    let mut app = crate::headless::app(Level::default());
+    app.update();
+
+    let object_entity = |app: &mut App, object: LevelObject| {
+        app.world
+            .query::<(Entity, &LevelObject)>()
+            .iter(&app.world)
+            .find(|(_, spawned_object)| **spawned_object == object)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    };
+    let wall_object = LevelObject {
+        kind: LevelObjectKind::Wall,
+        index: 0,
+    };
+    let wall = object_entity(&mut app, wall_object);
+    let wall_occluder = app.world.get::<Occluder>(wall).unwrap().clone();
+    let player = app
+        .world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&app.world);
+    let moved_to = Vec3::new(12.0, 34.0, 0.0);
+    app.world.get_mut::<Transform>(player).unwrap().translation = moved_to;
+
+    // Moving a wall in the level updates the wall in place, and the player stays where it went.
+    let mut level = app.world.resource::<Level>().clone();
+    level.walls[0].position.x += 5.0;
+    app.insert_resource(level.clone());
+    app.update();
+
+    assert_eq!(object_entity(&mut app, wall_object), wall);
+    let occluder = app.world.get::<Occluder>(wall).unwrap();
+    assert_eq!(
+        occluder.top_left,
+        wall_occluder.top_left + Vec3::new(5.0, 0.0, 0.0)
+    );
+    assert_eq!(
+        occluder.bottom_right,
+        wall_occluder.bottom_right + Vec3::new(5.0, 0.0, 0.0)
+    );
+    assert_eq!(
+        app.world.get::<Transform>(player).unwrap().translation,
+        moved_to
+    );
+
+    // Raising the player lifts it where it is.
+    level.players[0].elevation = 20.0;
+    app.insert_resource(level);
+    app.update();
+
+    assert_eq!(
+        app.world.get::<Transform>(player).unwrap().translation,
+        Vec3::new(12.0, 34.0, 20.0)
+    );
+}
+
+#[test]
+fn level_parse_test_1() {
+    assert_eq!(
+        Level::parse(include_str!("../levels/default.ron")).unwrap(),
+        Level::default()
+    );
+}
+
+#[test]
+fn level_parse_test_2() {
+    let source =
+        "(\n    players: [(position: (0.0, 0.0))],\n    walls: [(position: (1.0, 2.0))],\n)";
+    assert!(matches!(
+        Level::parse(source),
+        Err(LevelError::Parse { line: 3, .. })
+    ));
+
+    let source = "(\n    players: [(position: (0.0, 0.0))],\n    npcs: [\n        (position: (0.0, 0.0)),\n        (position: (1.0, 0.0), sight: (threshold: 2.0)),\n    ],\n)";
+    match Level::parse(source) {
+        Err(LevelError::Invalid { field, .. }) => assert_eq!(field, "npcs[1].sight.threshold"),
+        result => panic!("expected an invalid field, got {:?}", result),
+    }
+}
+
+#[test]
+fn level_validate_test_1() {
+    let mut level = Level::default();
+    level.npcs[0].elevation = MAX_ELEVATION;
+    assert!(level.validate().is_ok());
+
+    level.npcs[0].elevation = MAX_ELEVATION + 1.0;
+    match level.validate() {
+        Err(LevelError::Invalid { field, .. }) => assert_eq!(field, "npcs[0].elevation"),
+        result => panic!("expected an invalid field, got {:?}", result),
+    }
+}
+
+#[test]
+fn level_object_from_str_test_1() {
+    let object = LevelObject {
+        kind: LevelObjectKind::Npc,
+        index: 2,
+    };
+    assert_eq!(object.to_string().parse(), Ok(object));
+    assert!("npcs".parse::<LevelObject>().is_err());
+    assert!("guards[0]".parse::<LevelObject>().is_err());
+    assert!("npcs[x]".parse::<LevelObject>().is_err());
+}
+
+#[test]
+fn level_to_ron_test_1() {
+    let level = Level::default();
+    assert_eq!(Level::parse(&level.to_ron().unwrap()).unwrap(), level);
+}
+
+#[test]
+fn level_changed_objects_test_1() {
+    let level = Level::default();
+
+    let mut new_level = level.clone();
+    new_level.walls[1].size.y = 60.0;
+    new_level.walls.pop();
+    new_level.npcs.push(new_level.npcs[0].clone());
+
+    assert_eq!(
+        level.changed_objects(&new_level),
+        vec![
+            LevelObject {
+                kind: LevelObjectKind::Wall,
+                index: 1
+            },
+            LevelObject {
+                kind: LevelObjectKind::Wall,
+                index: 2
+            },
+            LevelObject {
+                kind: LevelObjectKind::Npc,
+                index: 1
+            },
+        ]
+    );
+}
@@ -1,42 +1,34 @@
 pub mod controls;
+pub mod cover;
+pub mod coverage;
+pub mod debug;
+pub mod editor;
+#[cfg(test)]
+mod golden;
+pub mod headless;
+pub mod level;
 pub mod light;
+pub mod mirror;
 pub mod movement;
 pub mod npc;
 pub mod player;
+pub mod scenario;
 pub mod sight;
+pub mod spotlight;
+pub mod sun;
+pub mod svg;
+pub mod team;
+pub mod viewport;
+pub mod visibility_map;
 pub mod wall;
+pub mod world_scene;
 
 use bevy::{
     ecs::schedule::{LogLevel, ScheduleBuildSettings},
     prelude::*,
 };
 
-fn setup(mut commands: Commands) {
-    trace!("setup");
-
-    commands.spawn(Camera2dBundle {
-        camera_2d: Camera2d {
-            clear_color: bevy::core_pipeline::clear_color::ClearColorConfig::Custom(Color::WHITE),
-        },
-        ..default()
-    });
-
-    commands.spawn(player::PlayerBundle::default());
-    commands.spawn(npc::NpcBundle::default().with_transform(Transform::from_xyz(-100.0, 0.0, 0.0)));
-    commands
-        .spawn(wall::WallBundle::default().with_transform(Transform::from_xyz(-50.0, 0.0, 0.0)));
-    commands.spawn(
-        wall::WallBundle::default()
-            .with_transform(Transform::from_xyz(50.0, 40.0, 0.0))
-            .with_size(Vec2 { x: 10.0, y: 40.0 }),
-    );
-    commands.spawn(
-        wall::WallBundle::default()
-            .with_transform(Transform::from_xyz(50.0, -40.0, 0.0))
-            .with_size(Vec2 { x: 10.0, y: 40.0 }),
-    );
-}
-
+/// Runs the game in the [`level::Level`] resource, or the default level if there isn't one.
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
@@ -53,9 +45,19 @@ impl Plugin for GamePlugin {
         .add_plugin(npc::NpcPlugin)
         .add_plugin(sight::SightPlugin)
         .add_plugin(light::LightPlugin)
-        .add_startup_system(setup)
+        .add_plugin(sun::SunPlugin)
+        .add_plugin(spotlight::SpotlightPlugin)
+        .add_plugin(viewport::ViewportPlugin)
+        .add_plugin(team::TeamPlugin)
+        .add_plugin(debug::DebugPlugin)
+        .add_plugin(level::LevelPlugin)
+        .add_plugin(editor::EditorPlugin)
+        .add_plugin(world_scene::WorldScenePlugin)
+        .add_plugin(svg::SvgPlugin)
+        .register_type::<wall::Wall>()
         .insert_resource(sight::SightConfig {
             display_occluders: false,
+            ..default()
         });
     }
 }
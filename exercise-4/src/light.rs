@@ -2,60 +2,51 @@ use std::collections::HashSet;
 
 use bevy::{
     prelude::*,
-    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    render::{mesh::Indices, render_resource::PrimitiveTopology, view::RenderLayers},
     sprite::{MaterialMesh2dBundle, Mesh2dHandle},
 };
 
 use crate::{
+    mirror::{indirect_views, IndirectRegion},
     movement::MovementSet,
     player::Player,
-    sight::{ray_intersects_segment, Occluder, Segment},
+    sight::{
+        ray_intersects_segment, Occluder, OccluderEdge, OcclusionLayers, Portal, Segment,
+        SightConfig, Sighted, Team, VisibilityError,
+    },
+    viewport::{view_bounds, PlayerCamera, ViewLayer, ViewportSet, BACKGROUND_Z},
 };
 
-/// Render shadow quad outlines and barycenters.
-pub const DEBUG: bool = false;
+/// Just above the view background, and below everything else, so that entities aren't darkened
+/// by the shadows they're in.
+const SHADOW_Z: f32 = BACKGROUND_Z + 0.01;
 
-#[derive(Component)]
-struct PlayerRay {
-    occluder: Entity,
-    through: Vec3,
-    end: Vec3,
-}
-
-#[derive(Debug)]
-struct Line {
-    start: Vec3,
-    end: Vec3,
-}
+/// Just above the shadows, which indirect light lights up again.
+const INDIRECT_LIGHT_Z: f32 = BACKGROUND_Z + 0.02;
 
-impl From<Line> for Mesh {
-    fn from(value: Line) -> Self {
-        let mut mesh = Mesh::new(PrimitiveTopology::LineList);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![value.start, value.end]);
-        mesh.set_indices(Some(Indices::U32(vec![0, 1])));
-        mesh
+/// Compute the closest point at which a ray will intersect the edge of the view.
+fn project_ray_to_view_edge(bounds: Rect, ray: Ray) -> Result<f32, VisibilityError> {
+    if ray.direction == Vec3::ZERO {
+        return Err(VisibilityError::DegenerateRay);
     }
-}
 
-/// Compute the closest point at which a ray will intersect the edge of the screen.
-fn project_ray_to_window_edge(width: f32, height: f32, ray: Ray) -> f32 {
-    assert!(ray.direction != Vec3::ZERO);
-    assert!(-width / 2.0 <= ray.origin.x && ray.origin.x <= width / 2.0);
-    assert!(-height / 2.0 <= ray.origin.y && ray.origin.y <= height / 2.0);
+    if !bounds.contains(ray.origin.truncate()) {
+        return Err(VisibilityError::OutsideView);
+    }
 
     let t_left_right = if ray.direction.x != 0.0 {
         Some(f32::max(
-            (-width / 2.0 - ray.origin.x) / ray.direction.x,
-            (width / 2.0 - ray.origin.x) / ray.direction.x,
+            (bounds.min.x - ray.origin.x) / ray.direction.x,
+            (bounds.max.x - ray.origin.x) / ray.direction.x,
         ))
     } else {
         None
     };
 
-    let t_top_bottom = if ray.direction.x != 0.0 {
+    let t_top_bottom = if ray.direction.y != 0.0 {
         Some(f32::max(
-            (-height / 2.0 - ray.origin.y) / ray.direction.y,
-            (height / 2.0 - ray.origin.y) / ray.direction.y,
+            (bounds.min.y - ray.origin.y) / ray.direction.y,
+            (bounds.max.y - ray.origin.y) / ray.direction.y,
         ))
     } else {
         None
@@ -68,9 +59,49 @@ fn project_ray_to_window_edge(width: f32, height: f32, ray: Ray) -> f32 {
         (Some(t_left_right), Some(t_top_bottom)) => f32::max(t_left_right, t_top_bottom),
     };
 
-    assert!(t >= 0.0);
+    Ok(t)
+}
+
+#[test]
+fn project_ray_to_view_edge_test_1() {
+    let bounds = Rect::new(-2.0, -1.0, 2.0, 1.0);
+
+    let ray = Ray {
+        origin: Vec3::ZERO,
+        direction: Vec3::X,
+    };
+    assert_eq!(project_ray_to_view_edge(bounds, ray), Ok(2.0));
+
+    let ray = Ray {
+        origin: Vec3::ZERO,
+        direction: -Vec3::Y,
+    };
+    assert_eq!(project_ray_to_view_edge(bounds, ray), Ok(1.0));
 
-    t
+    let ray = Ray {
+        origin: 3.0 * Vec3::X,
+        direction: Vec3::X,
+    };
+    assert_eq!(
+        project_ray_to_view_edge(bounds, ray),
+        Err(VisibilityError::OutsideView)
+    );
+
+    let ray = Ray {
+        origin: Vec3::ZERO,
+        direction: Vec3::ZERO,
+    };
+    assert_eq!(
+        project_ray_to_view_edge(bounds, ray),
+        Err(VisibilityError::DegenerateRay)
+    );
+
+    let bounds = Rect::new(8.0, 9.0, 12.0, 11.0);
+    let ray = Ray {
+        origin: Vec3::new(10.0, 10.0, 0.0),
+        direction: Vec3::X,
+    };
+    assert_eq!(project_ray_to_view_edge(bounds, ray), Ok(2.0));
 }
 
 fn angle_ccw(barycentre: &Vec3, point: &Vec3) -> f32 {
@@ -135,23 +166,44 @@ impl From<Quad> for Mesh {
     }
 }
 
-fn project_points_to_window_edge(
-    width: f32,
-    height: f32,
+fn project_points_to_view_edge(
+    bounds: Rect,
     from_point: &Vec3,
     through_point: &Vec3,
-) -> Vec3 {
+) -> Result<Vec3, VisibilityError> {
     let ray = Ray {
         origin: *from_point,
         direction: *through_point - *from_point,
     };
-    let t = project_ray_to_window_edge(width, height, ray);
-    ray.get_point(t)
+    let t = project_ray_to_view_edge(bounds, ray)?;
+    Ok(ray.get_point(t))
 }
 
+/// The shadow that an occluder casts from a viewer's point of view.
 #[derive(Component)]
 pub struct PlayerShadow {
+    pub viewer: Entity,
     pub occluder: Entity,
+    /// The colour that the shadow is drawn in, as given by [`shadow_color`].
+    pub color: Color,
+}
+
+/// The edge of a [`PlayerShadow`]'s occluder that one of its [`SegmentShadow`]s is cast by.
+#[derive(Component)]
+struct ShadowEdge(OccluderEdge);
+
+/// The colour of an occluder's shadow: dark grey for an opaque occluder, or a translucent shade
+/// of its tint for a semi-transparent one, so that the light that gets through is dimmed and
+/// tinted, and overlapping shadows dim it further.
+pub fn shadow_color(occluder: &Occluder) -> Color {
+    let [shade, ..] = Color::DARK_GRAY.as_rgba_f32();
+    let [r, g, b, _] = occluder.tint.as_rgba_f32();
+    Color::rgba(
+        r * shade,
+        g * shade,
+        b * shade,
+        occluder.opacity.clamp(0.0, 1.0),
+    )
 }
 
 #[derive(Component)]
@@ -163,7 +215,114 @@ pub struct SegmentShadow {
 }
 
 impl SegmentShadow {
+    /// The shadow of `segment` from `ray_1_end` and `ray_2_end`, where the rays through the
+    /// segment's endpoints meet the view's edge.
+    fn new(segment: Segment, ray_1_end: Vec3, ray_2_end: Vec3) -> Self {
+        Self {
+            segment,
+            shadow_edge_1: Segment(segment.0, ray_1_end),
+            shadow_edge_2: Segment(ray_1_end, ray_2_end),
+            shadow_edge_3: Segment(ray_2_end, segment.1),
+        }
+    }
+
+    /// The shadow of `segment` in parallel light, such as [`crate::sun::Sunlight`], that reaches
+    /// `offset` across the ground from it.
+    pub(crate) fn swept(segment: Segment, offset: Vec2) -> Self {
+        let offset = offset.extend(0.0);
+        Self::new(segment, segment.0 + offset, segment.1 + offset)
+    }
+
+    /// No shadow, for an edge that can't be projected or that doesn't block light from the
+    /// viewer's side.
+    fn empty(segment: Segment) -> Self {
+        Self::new(segment, segment.0, segment.1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shadow_edge_1.1 == self.segment.0 && self.shadow_edge_2.1 == self.segment.1
+    }
+
+    /// The shadow that `segment` casts across the ground from `viewer_position` to the edge of
+    /// `bounds`, or only `reach` times as far past the segment as the segment is from the viewer,
+    /// as given by [`Occluder::shadow_reach`].
+    pub fn project(
+        viewer_position: Vec3,
+        segment: Segment,
+        bounds: Rect,
+        reach: Option<f32>,
+    ) -> Result<Self, VisibilityError> {
+        // The shadow lies on the ground, whatever the viewer's height.
+        let viewer_position = viewer_position.truncate().extend(segment.0.z);
+        let project = |point: Vec3| {
+            let edge_point = project_points_to_view_edge(bounds, &viewer_position, &point)?;
+            Ok(match reach {
+                Some(reach)
+                    if point.distance(edge_point) > point.distance(viewer_position) * reach =>
+                {
+                    point + (point - viewer_position) * reach
+                }
+                _ => edge_point,
+            })
+        };
+        Ok(Self::new(segment, project(segment.0)?, project(segment.1)?))
+    }
+
+    /// The corners of the shadow quad: the occluding segment's endpoints, and where the rays
+    /// through them meet the view's edge.
+    pub fn corners(&self) -> [Vec3; 4] {
+        [
+            self.segment.0,
+            self.shadow_edge_1.1,
+            self.shadow_edge_2.1,
+            self.segment.1,
+        ]
+    }
+
+    fn quad(&self) -> Quad {
+        let [v1, v2, v3, v4] = self.corners();
+        Quad(v1, v2, v3, v4)
+    }
+
+    /// The mesh of the whole shadow quad.
+    pub(crate) fn quad_mesh(&self) -> Mesh {
+        self.quad().into()
+    }
+
+    /// The mesh that the shadow is drawn with.
+    ///
+    /// The shadows of the edges of a semi-transparent occluder that face away from the viewer are
+    /// left empty. They're covered by the shadows of the edges that face the viewer, and would
+    /// otherwise darken the shadow twice where they overlap.
+    fn mesh(&self, occluder: &Occluder, viewer_position: Vec3) -> Mesh {
+        if occluder.is_opaque() || occluder.is_outside(&self.segment, viewer_position) {
+            self.quad().into()
+        } else {
+            Quad(
+                self.segment.0,
+                self.segment.0,
+                self.segment.0,
+                self.segment.0,
+            )
+            .into()
+        }
+    }
+
+    /// The edges of the shadow quad, starting with the occluding segment.
+    pub fn edges(&self) -> [Segment; 4] {
+        [
+            self.segment,
+            self.shadow_edge_1,
+            self.shadow_edge_2,
+            self.shadow_edge_3,
+        ]
+    }
+
     pub fn contains_point(&self, point: &Vec3) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
         let ray = Ray {
             origin: *point,
             direction: Vec3::X,
@@ -172,12 +331,7 @@ impl SegmentShadow {
         let mut vertex_count = 0;
         let mut edge_count = 0;
 
-        for segment in [
-            self.segment,
-            self.shadow_edge_1,
-            self.shadow_edge_2,
-            self.shadow_edge_3,
-        ] {
+        for segment in self.edges() {
             if ray_intersects_segment(&ray, &segment) {
                 let ray_intersects_segment_0 = segment.0.y == point.y && segment.0.z == point.z;
                 let ray_intersects_segment_1 = segment.1.y == point.y && segment.1.z == point.z;
@@ -199,7 +353,11 @@ impl SegmentShadow {
             }
         }
 
-        assert!(vertex_count % 2 == 0);
+        // Each vertex that the ray passes through is shared by two edges, so it's counted twice,
+        // unless the other edge runs along the ray. Then the point is on the shadow's edge.
+        if vertex_count % 2 == 1 {
+            return true;
+        }
         let count = vertex_count / 2 + edge_count;
 
         count % 2 == 1
@@ -217,6 +375,8 @@ fn segment_shadow_contains_point_test_1() {
 
     assert!(segment_shadow.contains_point(&Vec3::ZERO));
     assert!(segment_shadow.contains_point(&(-Vec3::X - Vec3::Y)));
+    // The ray from a point on the top edge runs along it, and only meets one other edge at its end.
+    assert!(segment_shadow.contains_point(&Vec3::Y));
 
     assert!(!segment_shadow.contains_point(&(5.0 * Vec3::X)));
 }
@@ -236,228 +396,426 @@ fn segment_shadow_contains_point_test_2() {
     assert!(!segment_shadow.contains_point(&(-3.0 * Vec3::X)));
 }
 
-#[derive(Component)]
-struct ShadowBarycentre;
+#[test]
+fn segment_shadow_project_test_1() {
+    let bounds = Rect::new(-100.0, -100.0, 100.0, 100.0);
+    let segment = Segment(Vec3::new(10.0, -5.0, 0.0), Vec3::new(10.0, 5.0, 0.0));
+
+    // From the ground, the shadow reaches past the edge of the view.
+    let shadow = SegmentShadow::project(Vec3::ZERO, segment, bounds, None).unwrap();
+    assert_eq!(shadow.corners()[1], Vec3::new(200.0, -100.0, 0.0));
+
+    // From above, a low wall's shadow reaches as far past it as it's reached for.
+    let shadow =
+        SegmentShadow::project(Vec3::new(0.0, 0.0, 20.0), segment, bounds, Some(0.5)).unwrap();
+    assert_eq!(shadow.corners()[1], Vec3::new(15.0, -7.5, 0.0));
+    assert!(shadow.contains_point(&Vec3::new(12.0, 0.0, 0.0)));
+    assert!(!shadow.contains_point(&Vec3::new(20.0, 0.0, 0.0)));
+
+    // A long reach is still cut off at the edge of the view.
+    let shadow = SegmentShadow::project(Vec3::ZERO, segment, bounds, Some(100.0)).unwrap();
+    assert_eq!(shadow.corners()[2], Vec3::new(200.0, 100.0, 0.0));
+}
+
+pub(crate) type PlayerCameraQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static PlayerCamera,
+        &'static Transform,
+        &'static OrthographicProjection,
+    ),
+    (Without<Player>, Without<Sighted>),
+>;
+
+/// The area of the world that any player's camera can see.
+pub(crate) fn all_view_bounds(cameras: &PlayerCameraQuery) -> Option<Rect> {
+    cameras
+        .iter()
+        .map(|(_, transform, projection)| view_bounds(transform, projection))
+        .reduce(|bounds, other| bounds.union(other))
+}
+
+/// The entities whose shadows are computed: players, and the members of teams.
+type ViewerFilter = (With<Sighted>, Or<(With<Player>, With<Team>)>);
+
+/// The layers on which a viewer's shadows are rendered.
+///
+/// Shadows are only rendered for players who aren't in a team. A team's view is rendered as the
+/// union of its members' vision polygons instead; see [`crate::team`].
+fn shadow_render_layers(view_layer: Option<&ViewLayer>, team: Option<&Team>) -> RenderLayers {
+    match (view_layer, team) {
+        (Some(view_layer), None) => view_layer.render_layers(),
+        _ => RenderLayers::none(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_player_shadow(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    shadow_material: &Handle<ColorMaterial>,
+    viewer: Entity,
+    render_layers: RenderLayers,
+    viewer_position: Vec3,
+    bounds: Rect,
+    occluder_entity: Entity,
+    occluder: &Occluder,
+) {
+    let player_shadow_entity = commands
+        .spawn((
+            PlayerShadow {
+                viewer,
+                occluder: occluder_entity,
+                color: shadow_color(occluder),
+            },
+            SpatialBundle::default(),
+            render_layers,
+        ))
+        .id();
+
+    for (edge, segment) in occluder.iter_edges() {
+        // If the shadow can't be projected then it starts out empty, and is updated when the
+        // viewer or a camera moves.
+        let segment_shadow = if occluder.edge_blocks_from(edge, &segment, viewer_position) {
+            let reach = occluder.shadow_reach(viewer_position.z);
+            SegmentShadow::project(viewer_position, segment, bounds, reach)
+                .unwrap_or_else(|_| SegmentShadow::empty(segment))
+        } else {
+            SegmentShadow::empty(segment)
+        };
+
+        commands
+            .entity(player_shadow_entity)
+            .with_children(|parent| {
+                parent.spawn((
+                    MaterialMesh2dBundle {
+                        mesh: meshes
+                            .add(segment_shadow.mesh(occluder, viewer_position))
+                            .into(),
+                        material: shadow_material.clone(),
+                        transform: Transform::from_xyz(0.0, 0.0, SHADOW_Z),
+                        ..default()
+                    },
+                    segment_shadow,
+                    ShadowEdge(edge),
+                    render_layers,
+                ));
+            });
+    }
+}
+
+/// The materials that have been added for each colour, so that everything drawn in the same
+/// colour, such as shadows, lights and vision polygons, shares one material.
+#[derive(Resource, Default)]
+pub(crate) struct ColorMaterials(Vec<(Color, Handle<ColorMaterial>)>);
+
+impl ColorMaterials {
+    /// The material for `color`, which is added to `materials` the first time that it's needed.
+    pub(crate) fn get_or_add(
+        &mut self,
+        color: Color,
+        materials: &mut Assets<ColorMaterial>,
+    ) -> Handle<ColorMaterial> {
+        if let Some((_, material)) = self
+            .0
+            .iter()
+            .find(|(material_color, _)| *material_color == color)
+        {
+            return material.clone();
+        }
+        let material = materials.add(ColorMaterial::from(color));
+        self.0.push((color, material.clone()));
+        material
+    }
+}
 
+/// Spawns shadows for each viewer's view of each occluder that blocks light and doesn't have one.
+///
+/// Shadows are spawned for every unshadowed occluder, rather than just the newly added ones, so
+/// that occluders added before there's a camera still get shadows later.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn add_player_shadows(
-    windows: Query<&Window>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    player_query: Query<&Transform, With<Player>>,
-    occluders: Query<(Entity, &Occluder), Added<Occluder>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    viewers: Query<(Entity, &Transform, Option<&ViewLayer>, Option<&Team>), ViewerFilter>,
+    cameras: PlayerCameraQuery,
+    occluders: Query<(Entity, &Occluder)>,
+    player_shadows: Query<&PlayerShadow>,
 ) {
-    if !occluders.is_empty() {
-        let window = windows.get_single().unwrap();
-
-        let player_transform = player_query.get_single().unwrap();
-
-        let material_red = materials.add(ColorMaterial::from(Color::RED));
-        let material_dark_gray = materials.add(ColorMaterial::from(Color::DARK_GRAY));
-
-        for (entity, occluder) in occluders.iter() {
-            let player_shadow_entity = commands
-                .spawn((PlayerShadow { occluder: entity }, SpatialBundle::default()))
-                .id();
-
-            for segment in occluder.iter_segments() {
-                let ray_1_end = project_points_to_window_edge(
-                    window.width(),
-                    window.height(),
-                    &player_transform.translation,
-                    &segment.0,
-                );
-
-                let ray_2_end = project_points_to_window_edge(
-                    window.width(),
-                    window.height(),
-                    &player_transform.translation,
-                    &segment.1,
-                );
-
-                let v1 = segment.0;
-                let v2 = ray_1_end;
-                let v3 = ray_2_end;
-                let v4 = segment.1;
-
-                commands
-                    .entity(player_shadow_entity)
-                    .with_children(|parent| {
-                        parent
-                            .spawn((
-                                SegmentShadow {
-                                    segment,
-                                    shadow_edge_1: Segment(segment.0, ray_1_end),
-                                    shadow_edge_2: Segment(ray_1_end, ray_2_end),
-                                    shadow_edge_3: Segment(ray_2_end, segment.1),
-                                },
-                                MaterialMesh2dBundle {
-                                    mesh: meshes.add(Quad(v1, v2, v3, v4).into()).into(),
-                                    material: material_dark_gray.clone(),
-                                    ..default()
-                                },
-                            ))
-                            .with_children(|parent| {
-                                if DEBUG {
-                                    parent.spawn((
-                                        ShadowBarycentre,
-                                        MaterialMesh2dBundle {
-                                            mesh: meshes
-                                                .add(
-                                                    shape::Circle {
-                                                        radius: 5.0,
-                                                        ..default()
-                                                    }
-                                                    .into(),
-                                                )
-                                                .into(),
-                                            material: material_red.clone(),
-                                            transform: Transform::from_translation(
-                                                (v1 + v2 + v3 + v4) / 4.0 + Vec3::Z,
-                                            ),
-                                            ..default()
-                                        },
-                                    ));
-                                }
-                            });
-                    });
-
-                if DEBUG {
-                    commands.spawn((
-                        PlayerRay {
-                            occluder: entity,
-                            through: segment.0,
-                            end: ray_1_end,
-                        },
-                        MaterialMesh2dBundle {
-                            mesh: meshes
-                                .add(
-                                    Line {
-                                        start: player_transform.translation,
-                                        end: ray_1_end,
-                                    }
-                                    .into(),
-                                )
-                                .into(),
-                            material: material_red.clone(),
-                            transform: Transform::from_translation(Vec3::Z),
-                            ..default()
-                        },
-                    ));
-
-                    commands.spawn((
-                        PlayerRay {
-                            occluder: entity,
-                            through: segment.1,
-                            end: ray_2_end,
-                        },
-                        MaterialMesh2dBundle {
-                            mesh: meshes
-                                .add(
-                                    Line {
-                                        start: player_transform.translation,
-                                        end: ray_2_end,
-                                    }
-                                    .into(),
-                                )
-                                .into(),
-                            material: material_red.clone(),
-                            transform: Transform::from_translation(Vec3::Z),
-                            ..default()
-                        },
-                    ));
-                }
+    let shadowed: HashSet<(Entity, Entity)> = player_shadows
+        .iter()
+        .map(|player_shadow| (player_shadow.viewer, player_shadow.occluder))
+        .collect();
+
+    let Some(bounds) = all_view_bounds(&cameras) else {
+        return;
+    };
+
+    for (viewer, viewer_transform, view_layer, team) in viewers.iter() {
+        for (occluder_entity, occluder) in occluders.iter() {
+            if shadowed.contains(&(viewer, occluder_entity))
+                || !occluder.blocks(OcclusionLayers::LIGHT)
+            {
+                continue;
             }
+
+            let shadow_material =
+                color_materials.get_or_add(shadow_color(occluder), &mut materials);
+
+            spawn_player_shadow(
+                &mut commands,
+                &mut meshes,
+                &shadow_material,
+                viewer,
+                shadow_render_layers(view_layer, team),
+                viewer_transform.translation,
+                bounds,
+                occluder_entity,
+                occluder,
+            );
         }
     }
 }
 
+/// Re-projects the shadows of viewers who have moved and of occluders that have changed, or every
+/// shadow when a camera has changed.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn update_player_shadows(
-    windows: Query<&Window>,
     mut meshes: ResMut<Assets<Mesh>>,
-    player_query: Query<&Transform, (With<Player>, Changed<Transform>)>,
-    mut player_rays: Query<(&mut PlayerRay, &Mesh2dHandle)>,
-    player_shadows: Query<&PlayerShadow>,
-    mut segment_shadows: Query<(&Parent, &mut SegmentShadow, &Mesh2dHandle, &Children)>,
-    mut shadow_barycentres: Query<(&ShadowBarycentre, &mut Transform), Without<Player>>,
+    viewers: Query<&Transform, ViewerFilter>,
+    moved_viewers: Query<Entity, (ViewerFilter, Changed<Transform>)>,
+    cameras: PlayerCameraQuery,
+    changed_cameras: Query<
+        (),
+        (
+            With<PlayerCamera>,
+            Or<(Changed<Transform>, Changed<OrthographicProjection>)>,
+        ),
+    >,
+    occluders: Query<&Occluder>,
+    changed_occluders: Query<Entity, Changed<Occluder>>,
+    player_shadows: Query<(&PlayerShadow, &Children)>,
+    mut segment_shadows: Query<(&ShadowEdge, &mut SegmentShadow, &Mesh2dHandle)>,
 ) {
-    let window = windows.get_single().unwrap();
-
-    let player_transform = player_query.get_single().unwrap();
+    let cameras_changed = !changed_cameras.is_empty();
+    let moved_viewers: HashSet<Entity> = moved_viewers.iter().collect();
+    let changed_occluders: HashSet<Entity> = changed_occluders.iter().collect();
 
-    for (mut player_ray, mesh_handle) in player_rays.iter_mut() {
-        let end = project_points_to_window_edge(
-            window.width(),
-            window.height(),
-            &player_transform.translation,
-            &player_ray.through,
-        );
-
-        let new_line = Line {
-            start: player_transform.translation,
-            end,
-        };
-
-        *meshes.get_mut(&mesh_handle.0).unwrap() = new_line.into();
-        player_ray.end = end;
+    if !cameras_changed && moved_viewers.is_empty() && changed_occluders.is_empty() {
+        return;
     }
 
-    for (segment_shadow_parent, mut segment_shadow, segment_shadow_mesh_handle, children) in
-        segment_shadows.iter_mut()
-    {
-        if player_shadows.contains(segment_shadow_parent.get()) {
-            let ray_1_end = project_points_to_window_edge(
-                window.width(),
-                window.height(),
-                &player_transform.translation,
-                &segment_shadow.segment.0,
-            );
-
-            let ray_2_end = project_points_to_window_edge(
-                window.width(),
-                window.height(),
-                &player_transform.translation,
-                &segment_shadow.segment.1,
-            );
-
-            let v1 = segment_shadow.segment.0;
-            let v2 = ray_1_end;
-            let v3 = ray_2_end;
-            let v4 = segment_shadow.segment.1;
-
-            segment_shadow.shadow_edge_1.1 = ray_1_end;
-            segment_shadow.shadow_edge_2 = Segment(ray_1_end, ray_2_end);
-            segment_shadow.shadow_edge_3.0 = ray_2_end;
+    let Some(bounds) = all_view_bounds(&cameras) else {
+        return;
+    };
 
-            *meshes.get_mut(&segment_shadow_mesh_handle.0).unwrap() = Quad(v1, v2, v3, v4).into();
+    for (player_shadow, children) in player_shadows.iter() {
+        if !cameras_changed
+            && !moved_viewers.contains(&player_shadow.viewer)
+            && !changed_occluders.contains(&player_shadow.occluder)
+        {
+            continue;
+        }
 
-            for child in children {
-                let (ShadowBarycentre, mut transform) = shadow_barycentres.get_mut(*child).unwrap();
-                transform.translation = (v1 + v2 + v3 + v4) / 4.0 + Vec3::Z;
-            }
+        let (Ok(viewer_transform), Ok(occluder)) = (
+            viewers.get(player_shadow.viewer),
+            occluders.get(player_shadow.occluder),
+        ) else {
+            continue;
+        };
+        let viewer_position = viewer_transform.translation;
+
+        // The shadows of one-way edges that don't block light from the viewer's side are emptied.
+        for child in children.iter() {
+            let Ok((&ShadowEdge(edge), mut segment_shadow, segment_shadow_mesh_handle)) =
+                segment_shadows.get_mut(*child)
+            else {
+                continue;
+            };
+            let Some((_, segment)) = occluder
+                .iter_edges()
+                .find(|(occluder_edge, _)| *occluder_edge == edge)
+            else {
+                continue;
+            };
+
+            let new_segment_shadow = if occluder.edge_blocks_from(edge, &segment, viewer_position) {
+                let reach = occluder.shadow_reach(viewer_position.z);
+                let Ok(new_segment_shadow) =
+                    SegmentShadow::project(viewer_position, segment, bounds, reach)
+                else {
+                    continue;
+                };
+                new_segment_shadow
+            } else {
+                SegmentShadow::empty(segment)
+            };
+
+            *meshes.get_mut(&segment_shadow_mesh_handle.0).unwrap() =
+                new_segment_shadow.mesh(occluder, viewer_position);
+            *segment_shadow = new_segment_shadow;
         }
     }
 }
 
+/// Despawns the shadows of removed occluders, of occluders that no longer block light and of
+/// entities that are no longer viewers.
+///
+/// Shadows whose occluder's opacity or tint has changed are also despawned, to be respawned in
+/// the new colour.
+///
+/// Shadows that are rendered on the wrong layers, because their viewer has joined or left a team
+/// or has been given a camera, are also despawned so that [`add_player_shadows`] respawns them.
+#[allow(clippy::type_complexity)]
 fn remove_player_shadows(
     mut commands: Commands,
-    player_rays: Query<(Entity, &PlayerRay)>,
-    player_shadows: Query<(Entity, &PlayerShadow)>,
+    viewers: Query<(Option<&ViewLayer>, Option<&Team>), ViewerFilter>,
+    player_shadows: Query<(Entity, &PlayerShadow, &RenderLayers)>,
+    occluders: Query<&Occluder>,
     mut removed_occluders: RemovedComponents<Occluder>,
     mut removed_occluders_set: Local<HashSet<Entity>>,
 ) {
     removed_occluders_set.clear();
     removed_occluders_set.extend(removed_occluders.iter());
 
-    for (entity, player_ray) in player_rays.iter() {
-        if removed_occluders_set.contains(&player_ray.occluder) {
+    for (entity, player_shadow, render_layers) in player_shadows.iter() {
+        let stale = match viewers.get(player_shadow.viewer) {
+            Ok((view_layer, team)) => *render_layers != shadow_render_layers(view_layer, team),
+            Err(_) => true,
+        };
+
+        let current = occluders.get(player_shadow.occluder).is_ok_and(|occluder| {
+            occluder.blocks(OcclusionLayers::LIGHT) && player_shadow.color == shadow_color(occluder)
+        });
+
+        if stale || !current || removed_occluders_set.contains(&player_shadow.occluder) {
             commands.entity(entity).despawn_recursive();
         }
     }
+}
 
-    for (entity, player_shadow) in player_shadows.iter() {
-        if removed_occluders_set.contains(&player_shadow.occluder) {
-            commands.entity(entity).despawn_recursive();
+/// The light that a viewer sees indirectly, in a mirror or through a portal, which lights up its
+/// shadows again.
+#[derive(Component)]
+pub struct IndirectLight {
+    pub viewer: Entity,
+    pub region: IndirectRegion,
+}
+
+/// The layers on which a viewer's indirect light is rendered: those of its shadows, or of its
+/// team's players if it's in a team, like its team's vision polygons.
+fn indirect_light_render_layers(
+    view_layer: Option<&ViewLayer>,
+    team: Option<&Team>,
+    players: &Query<(&Team, &ViewLayer), With<Player>>,
+) -> RenderLayers {
+    match team {
+        Some(team) => players
+            .iter()
+            .filter(|(player_team, _)| *player_team == team)
+            .fold(RenderLayers::none(), |render_layers, (_, view_layer)| {
+                render_layers.with(view_layer.0)
+            }),
+        None => shadow_render_layers(view_layer, team),
+    }
+}
+
+/// Respawns the indirect light of viewers who have moved or whose view is shown to different
+/// players, or of every viewer when the cameras, the occluders, the portals or the [`SightConfig`]
+/// have changed.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn update_indirect_light(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    config: Res<SightConfig>,
+    viewers: Query<(Entity, &Transform, Option<&ViewLayer>, Option<&Team>), ViewerFilter>,
+    moved_viewers: Query<Entity, (ViewerFilter, Changed<Transform>)>,
+    players: Query<(&Team, &ViewLayer), With<Player>>,
+    cameras: PlayerCameraQuery,
+    changed_cameras: Query<
+        (),
+        (
+            With<PlayerCamera>,
+            Or<(Changed<Transform>, Changed<OrthographicProjection>)>,
+        ),
+    >,
+    occluders: Query<&Occluder>,
+    portals: Query<&Portal>,
+    changed_occluders: Query<(), Or<(Changed<Occluder>, Changed<Portal>)>>,
+    mut removed_occluders: RemovedComponents<Occluder>,
+    mut removed_portals: RemovedComponents<Portal>,
+    indirect_lights: Query<(Entity, &IndirectLight, &RenderLayers)>,
+) {
+    let world_changed = config.is_changed()
+        || !changed_cameras.is_empty()
+        || !changed_occluders.is_empty()
+        || removed_occluders.iter().next().is_some()
+        || removed_portals.iter().next().is_some();
+
+    let mut stale: HashSet<Entity> = moved_viewers.iter().collect();
+    for (entity, indirect_light, render_layers) in indirect_lights.iter() {
+        let current = viewers
+            .get(indirect_light.viewer)
+            .is_ok_and(|(_, _, view_layer, team)| {
+                *render_layers == indirect_light_render_layers(view_layer, team, &players)
+            });
+        if world_changed || !current || stale.contains(&indirect_light.viewer) {
+            commands.entity(entity).despawn();
+            stale.insert(indirect_light.viewer);
+        }
+    }
+
+    let Some(bounds) = all_view_bounds(&cameras) else {
+        return;
+    };
+
+    // Semi-transparent occluders don't cut the indirect light, like vision polygons.
+    let occluders: Vec<&Occluder> = occluders
+        .iter()
+        .filter(|occluder| occluder.blocks(OcclusionLayers::LIGHT))
+        .collect();
+    let opaque: Vec<&Occluder> = occluders
+        .iter()
+        .copied()
+        .filter(|occluder| occluder.is_opaque())
+        .collect();
+    let portals: Vec<&Portal> = portals.iter().collect();
+
+    for (viewer, transform, view_layer, team) in viewers.iter() {
+        if !world_changed && !stale.contains(&viewer) {
+            continue;
+        }
+
+        // Indirect light lies on the ground, like shadows.
+        let origin = transform.translation.truncate().extend(0.0);
+        for view in indirect_views(
+            origin,
+            &occluders,
+            &portals,
+            config.max_reflections,
+            config.max_portals,
+        ) {
+            let region = view.region(origin, &opaque, bounds);
+            if region.rays.is_empty() {
+                continue;
+            }
+
+            let material = color_materials.get_or_add(Color::WHITE, &mut materials);
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: meshes.add(Mesh::from(&region)).into(),
+                    material,
+                    transform: Transform::from_xyz(0.0, 0.0, INDIRECT_LIGHT_Z),
+                    ..default()
+                },
+                IndirectLight { viewer, region },
+                indirect_light_render_layers(view_layer, team, &players),
+            ));
         }
     }
 }
@@ -469,18 +827,21 @@ pub struct LightPlugin;
 
 impl Plugin for LightPlugin {
     fn build(&self, app: &mut App) {
-        app.configure_set(LightSet.after(MovementSet));
+        app.init_resource::<ColorMaterials>();
+
+        app.configure_set(LightSet.after(MovementSet).after(ViewportSet));
 
         app.add_system(
             add_player_shadows
                 .in_set(LightSet)
                 .after(update_player_shadows),
         )
-        .add_system(update_player_shadows.in_set(LightSet).run_if(
-            |player_query: Query<Entity, (Changed<Transform>, With<Player>)>| {
-                !player_query.is_empty()
-            },
-        ));
+        .add_system(update_player_shadows.in_set(LightSet))
+        .add_system(
+            update_indirect_light
+                .in_set(LightSet)
+                .after(add_player_shadows),
+        );
 
         app.add_system(remove_player_shadows.in_base_set(CoreSet::PostUpdate));
     }
@@ -1,15 +1,33 @@
 use bevy::prelude::*;
-use boxybox::GamePlugin;
+use boxybox::{
+    level::{Level, LevelFile},
+    GamePlugin,
+};
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                present_mode: bevy::window::PresentMode::AutoVsync,
-                ..default()
-            }),
+    let mut app = App::new();
+
+    // The level file is the first argument, if there is one.
+    if let Some(path) = std::env::args().nth(1) {
+        match Level::load(&path) {
+            Ok(level) => {
+                app.insert_resource(level)
+                    .insert_resource(LevelFile::new(path));
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            present_mode: bevy::window::PresentMode::AutoVsync,
             ..default()
-        }))
-        .add_plugin(GamePlugin)
-        .run();
+        }),
+        ..default()
+    }))
+    .add_plugin(GamePlugin)
+    .run();
 }
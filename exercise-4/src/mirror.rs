@@ -0,0 +1,578 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+use crate::sight::{
+    polygon_contains_point, ray_intersection, segment_intersection, unobstructed_fraction,
+    Occluder, Portal, Segment, Sighted,
+};
+
+/// How far the points where a sight line bounces off a mirror or passes through a portal are pulled
+/// back along it, so that the mirror, or the wall that the portal is on, doesn't block it.
+const WINDOW_MARGIN: f32 = 0.01;
+
+/// The reflection of `point` in the line through `mirror`.
+pub fn reflect(point: Vec3, mirror: &Segment) -> Vec3 {
+    let direction = (mirror.1 - mirror.0).truncate();
+    let offset = (point - mirror.0).truncate();
+    let along = direction * offset.dot(direction) / direction.length_squared();
+    (mirror.0.truncate() + 2.0 * along - offset).extend(point.z)
+}
+
+#[test]
+fn reflect_test_1() {
+    let mirror = Segment(Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+    assert_eq!(
+        reflect(Vec3::new(-2.0, 3.0, 0.0), &mirror),
+        Vec3::new(4.0, 3.0, 0.0)
+    );
+}
+
+/// Where `point` ends up when `from` is turned, scaled and moved onto `to`, start to start and end
+/// to end, such as when it's seen through a [`Portal`].
+pub fn carry(point: Vec3, from: &Segment, to: &Segment) -> Vec3 {
+    // Treating the points as complex numbers, this is `to.0 + (point - from.0) * k`, where `k` is
+    // `(to.1 - to.0) / (from.1 - from.0)`.
+    let from_direction = (from.1 - from.0).truncate();
+    let to_direction = (to.1 - to.0).truncate();
+    let k = Vec2::new(
+        from_direction.dot(to_direction),
+        from_direction.perp_dot(to_direction),
+    ) / from_direction.length_squared();
+    (to.0.truncate() + k.rotate((point - from.0).truncate())).extend(point.z)
+}
+
+#[test]
+fn carry_test_1() {
+    let from = Segment(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 10.0, 0.0));
+    let to = Segment(Vec3::new(100.0, 0.0, 0.0), Vec3::new(120.0, 0.0, 0.0));
+    // A quarter turn clockwise, twice the size.
+    let carried = carry(Vec3::new(-5.0, 5.0, 0.0), &from, &to);
+    assert!(carried.distance(Vec3::new(110.0, 10.0, 0.0)) < 0.001);
+    let back = carry(carried, &to, &from);
+    assert!(back.distance(Vec3::new(-5.0, 5.0, 0.0)) < 0.001);
+}
+
+/// Which side of the line through `segment` that `point` is on.
+fn side(segment: &Segment, point: Vec3) -> f32 {
+    (segment.1 - segment.0)
+        .truncate()
+        .perp_dot((point - segment.0).truncate())
+}
+
+/// Something that sight passes through to carry on elsewhere: a mirror, which it goes into and
+/// comes back out of, or one way through a portal.
+#[derive(Debug, Clone, Copy)]
+enum Window {
+    Mirror(Segment),
+    Portal { entrance: Segment, exit: Segment },
+}
+
+impl Window {
+    fn entrance(&self) -> Segment {
+        match self {
+            Window::Mirror(mirror) => *mirror,
+            Window::Portal { entrance, .. } => *entrance,
+        }
+    }
+
+    fn exit(&self) -> Segment {
+        match self {
+            Window::Mirror(mirror) => *mirror,
+            Window::Portal { exit, .. } => *exit,
+        }
+    }
+
+    /// Where a point seen through the window would be if the window weren't there.
+    fn carry(&self, point: Vec3) -> Vec3 {
+        match self {
+            Window::Mirror(mirror) => reflect(point, mirror),
+            Window::Portal { entrance, exit } => carry(point, entrance, exit),
+        }
+    }
+
+    /// The opposite of [`Window::carry`].
+    fn carry_back(&self, point: Vec3) -> Vec3 {
+        match self {
+            Window::Mirror(mirror) => reflect(point, mirror),
+            Window::Portal { entrance, exit } => carry(point, exit, entrance),
+        }
+    }
+}
+
+/// What a viewer sees in one or more mirrors and portals, one after the other.
+///
+/// What a viewer sees in a mirror is what its image behind the mirror would see through it, so
+/// the view is found by reflecting the viewer in each mirror in turn. Portals work the same way,
+/// with the viewer's image carried from one edge of the portal to the other.
+#[derive(Debug, Clone)]
+pub struct IndirectView {
+    windows: Vec<Window>,
+}
+
+impl IndirectView {
+    /// The images of `origin` after each mirror or portal.
+    fn images(&self, origin: Vec3) -> Vec<Vec3> {
+        self.windows
+            .iter()
+            .scan(origin, |image, window| {
+                *image = window.carry(*image);
+                Some(*image)
+            })
+            .collect()
+    }
+
+    /// The legs of the path of a sight line from `origin` to `target` by way of each mirror and
+    /// portal, from `origin` to `target`, if the target can be seen through them from there.
+    ///
+    /// A leg ends where it meets a mirror or goes into a portal, and the next starts where it
+    /// leaves the mirror or comes out of the portal.
+    pub fn path(&self, origin: Vec3, target: Vec3) -> Option<Vec<(Vec3, Vec3)>> {
+        let mut legs = Vec::new();
+        let mut current = target;
+        for (image, window) in self.images(origin).iter().zip(&self.windows).rev() {
+            let (s, _) = segment_intersection(&Segment(*image, current), &window.exit())?;
+            let exit_point = image.lerp(current, s);
+            legs.push((exit_point, current));
+            current = window.carry_back(exit_point);
+        }
+        legs.push((origin, current));
+        legs.reverse();
+        Some(legs)
+    }
+
+    /// The region that `origin` sees in the mirrors and through the portals, within `bounds`, up
+    /// to the first of the `occluders` in the way.
+    pub fn region(&self, origin: Vec3, occluders: &[&Occluder], bounds: Rect) -> IndirectRegion {
+        const EPSILON: f32 = 0.0001;
+
+        let (Some(image), Some(window)) =
+            (self.images(origin).last().copied(), self.windows.last())
+        else {
+            return IndirectRegion::default();
+        };
+        let exit = window.exit();
+        let preceding = IndirectView {
+            windows: self.windows[..self.windows.len() - 1].to_vec(),
+        };
+
+        let corner = |x: f32, y: f32| Vec3::new(x, y, origin.z);
+        let corners = [
+            corner(bounds.min.x, bounds.max.y),
+            corner(bounds.max.x, bounds.max.y),
+            corner(bounds.max.x, bounds.min.y),
+            corner(bounds.min.x, bounds.min.y),
+        ];
+        let mut segments: Vec<Segment> = occluders
+            .iter()
+            .flat_map(|occluder| occluder.iter_segments_from((exit.0 + exit.1) / 2.0))
+            .collect();
+        segments.extend((0..4).map(|index| Segment(corners[index], corners[(index + 1) % 4])));
+
+        // The region's edges are along the rays towards the ends of segments, as seen through each
+        // of the windows: towards where they're carried by the windows that come after them on the
+        // path.
+        let points: Vec<Vec3> = segments
+            .iter()
+            .flat_map(|segment| [segment.0, segment.1])
+            .chain([exit.0, exit.1])
+            .collect();
+        let mut images = points.clone();
+        for index in 0..self.windows.len() {
+            images.extend(points.iter().map(|point| {
+                self.windows[index..]
+                    .iter()
+                    .fold(*point, |point, window| window.carry(point))
+            }));
+        }
+
+        let towards = (exit.0 + exit.1) / 2.0 - image;
+        let base_angle = towards.y.atan2(towards.x);
+        let mut angles: Vec<f32> = images
+            .iter()
+            .flat_map(|point| {
+                let offset = *point - image;
+                let angle = (offset.y.atan2(offset.x) - base_angle + std::f32::consts::PI)
+                    .rem_euclid(std::f32::consts::TAU)
+                    - std::f32::consts::PI;
+                [angle - EPSILON, angle, angle + EPSILON]
+            })
+            .collect();
+        angles.sort_by(f32::total_cmp);
+        angles.dedup();
+
+        let rays = angles
+            .into_iter()
+            .filter_map(|angle| {
+                let angle = base_angle + angle;
+                let ray = Ray {
+                    origin: image,
+                    direction: Vec3::new(angle.cos(), angle.sin(), 0.0),
+                };
+                let (s, _) = ray_intersection(&ray, &exit)?;
+                let near = ray.get_point(s);
+
+                let back_path = preceding.path(origin, window.carry_back(near))?;
+                if path_transmittance(&back_path, occluders) == 0.0 {
+                    return Some((near, near));
+                }
+
+                // The edge that the window is on doesn't stop the ray as it leaves.
+                let far = segments
+                    .iter()
+                    .filter_map(|segment| ray_intersection(&ray, segment).map(|(s, _)| s))
+                    .filter(|far| *far > s + WINDOW_MARGIN)
+                    .min_by(f32::total_cmp)
+                    .map_or(near, |far| ray.get_point(far));
+                Some((near, far))
+            })
+            .collect();
+
+        IndirectRegion { rays }
+    }
+}
+
+/// How much of the sight or light along the legs of a path gets through the `occluders`, as for
+/// [`unobstructed_fraction`], where the legs after the first start on mirrors or portals.
+fn path_transmittance(legs: &[(Vec3, Vec3)], occluders: &[&Occluder]) -> f32 {
+    legs.iter()
+        .enumerate()
+        .map(|(index, (start, end))| {
+            let margin = (*end - *start).normalize_or_zero() * WINDOW_MARGIN;
+            let start = if index == 0 { *start } else { *start + margin };
+            unobstructed_fraction(&[start], &[*end - margin], occluders)
+        })
+        .product()
+}
+
+/// The views of `origin` in the mirrors among the `occluders` and through the `portals`, after up
+/// to `max_reflections` reflections and `max_portals` portals.
+pub fn indirect_views(
+    origin: Vec3,
+    occluders: &[&Occluder],
+    portals: &[&Portal],
+    max_reflections: u32,
+    max_portals: u32,
+) -> Vec<IndirectView> {
+    let mut views = Vec::new();
+    let mut frontier = vec![(
+        IndirectView {
+            windows: Vec::new(),
+        },
+        origin,
+        0,
+        0,
+    )];
+
+    while !frontier.is_empty() {
+        let mut next = Vec::new();
+        for (view, image, reflections, portals_passed) in &frontier {
+            let mirrors = occluders
+                .iter()
+                .filter(|_| *reflections < max_reflections)
+                .flat_map(|occluder| occluder.iter_mirror_segments_from(*image))
+                .map(Window::Mirror);
+            let ways_through = portals
+                .iter()
+                .filter(|_| *portals_passed < max_portals)
+                .flat_map(|portal| portal.ways_through())
+                .filter(|(entrance, _)| side(entrance, *image) != 0.0)
+                .map(|(entrance, exit)| Window::Portal { entrance, exit });
+
+            for window in mirrors.chain(ways_through) {
+                // A window can only be seen through the previous one if some of it is in front.
+                if let Some(previous) = view.windows.last() {
+                    let (previous, entrance) = (previous.exit(), window.entrance());
+                    let image_side = side(&previous, *image).signum();
+                    if image_side * side(&previous, entrance.0) >= 0.0
+                        && image_side * side(&previous, entrance.1) >= 0.0
+                    {
+                        continue;
+                    }
+                }
+
+                let mut windows = view.windows.clone();
+                windows.push(window);
+                let (reflections, portals_passed) = match window {
+                    Window::Mirror(_) => (reflections + 1, *portals_passed),
+                    Window::Portal { .. } => (*reflections, portals_passed + 1),
+                };
+                next.push((
+                    IndirectView { windows },
+                    window.carry(*image),
+                    reflections,
+                    portals_passed,
+                ));
+            }
+        }
+        views.extend(next.iter().map(|(view, ..)| view.clone()));
+        frontier = next;
+    }
+
+    views
+}
+
+/// The mean of the most of each sight line from `eye_points` to `target_points` that gets
+/// through the `occluders` by way of any of the `views`, and where the first such sight line first
+/// meets a mirror or portal.
+///
+/// An indirect sight line is in range if its whole path is, and in the field of view of a viewer
+/// with `transform` if it leaves in a direction that the viewer can see.
+pub fn indirect_fraction(
+    sighted: &Sighted,
+    transform: &Transform,
+    eye_points: &[Vec3],
+    target_points: &[Vec3],
+    occluders: &[&Occluder],
+    views: &[IndirectView],
+) -> (f32, Option<Vec3>) {
+    let line_count = eye_points.len() * target_points.len();
+    if line_count == 0 || views.is_empty() {
+        return (0.0, None);
+    }
+
+    let facing = transform.rotation * Vec3::X;
+    let mut first_point = None;
+    let transmitted: f32 = eye_points
+        .iter()
+        .flat_map(|eye_point| {
+            target_points
+                .iter()
+                .map(move |target_point| (*eye_point, *target_point))
+        })
+        .map(|(eye_point, target_point)| {
+            views
+                .iter()
+                .filter_map(|view| view.path(eye_point, target_point))
+                .filter(|legs| {
+                    let length: f32 = legs.iter().map(|(start, end)| start.distance(*end)).sum();
+                    let (start, end) = legs[0];
+                    sighted.in_range(Vec3::X * length)
+                        && sighted.in_field_of_view(facing, end - start)
+                })
+                .map(|legs| (path_transmittance(&legs, occluders), legs[0].1))
+                .filter(|(transmittance, _)| *transmittance > 0.0)
+                .max_by(|(a, _), (b, _)| a.total_cmp(b))
+                .map_or(0.0, |(transmittance, point)| {
+                    first_point.get_or_insert(point);
+                    transmittance
+                })
+        })
+        .sum();
+
+    (transmitted / line_count as f32, first_point)
+}
+
+/// The region seen in a mirror or through a portal, as the rays that it's seen along: where each
+/// ray leaves the mirror or portal, and where it's stopped by an occluder or the edge of the view.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndirectRegion {
+    pub rays: Vec<(Vec3, Vec3)>,
+}
+
+impl IndirectRegion {
+    /// The outline of the region: along the mirror or portal, and back along the far ends of its
+    /// rays.
+    pub fn outline(&self) -> Vec<Vec3> {
+        self.rays
+            .iter()
+            .map(|(near, _)| *near)
+            .chain(self.rays.iter().rev().map(|(_, far)| *far))
+            .collect()
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        !self.rays.is_empty() && polygon_contains_point(&self.outline(), point)
+    }
+
+    /// The triangles that the region is drawn with: two between each pair of neighbouring rays.
+    pub fn triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        self.rays.windows(2).flat_map(|pair| {
+            let [(near_1, far_1), (near_2, far_2)] = [pair[0], pair[1]];
+            [[near_1, far_1, far_2], [near_1, far_2, near_2]]
+        })
+    }
+}
+
+impl From<&IndirectRegion> for Mesh {
+    fn from(value: &IndirectRegion) -> Self {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let positions: Vec<Vec3> = value.triangles().flatten().collect();
+        let indices = (0..positions.len() as u32).collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+#[cfg(test)]
+fn mirror() -> Occluder {
+    Occluder {
+        top_left: Vec3::new(50.0, 20.0, 0.0),
+        bottom_right: Vec3::new(55.0, -20.0, 0.0),
+        mirror: true,
+        ..default()
+    }
+}
+
+#[test]
+fn indirect_fraction_test_1() {
+    // A viewer facing a mirror sees a target behind it, around a wall, but not one behind the
+    // wall's other side.
+    let mirror = mirror();
+    let wall = Occluder {
+        top_left: Vec3::new(-5.0, 10.0, 0.0),
+        bottom_right: Vec3::new(5.0, -10.0, 0.0),
+        ..default()
+    };
+    let occluders = [&mirror, &wall];
+    let viewer = Transform::from_xyz(20.0, 0.0, 0.0);
+    let sighted = Sighted {
+        field_of_view: Some(std::f32::consts::FRAC_PI_2),
+        ..default()
+    };
+    let behind = Vec3::new(-20.0, 15.0, 0.0);
+
+    let views = indirect_views(viewer.translation, &occluders, &[], 1, 0);
+    assert_eq!(views.len(), 1);
+    let path = views[0].path(viewer.translation, behind).unwrap();
+    assert_eq!(path.len(), 2);
+    assert!((path[0].1.x - 50.0).abs() < 0.001);
+
+    let (fraction, point) = indirect_fraction(
+        &sighted,
+        &viewer,
+        &[viewer.translation],
+        &[behind],
+        &occluders,
+        &views,
+    );
+    assert_eq!(fraction, 1.0);
+    assert_eq!(point, Some(path[0].1));
+
+    // Behind the wall, the reflected sight line is blocked by it.
+    let hidden = Vec3::new(-20.0, 0.0, 0.0);
+    assert_eq!(
+        indirect_fraction(
+            &sighted,
+            &viewer,
+            &[viewer.translation],
+            &[hidden],
+            &occluders,
+            &views
+        )
+        .0,
+        0.0
+    );
+
+    // Facing away from the mirror, nothing is seen in it.
+    let facing_away = viewer.with_rotation(Quat::from_rotation_z(std::f32::consts::PI));
+    assert_eq!(
+        indirect_fraction(
+            &sighted,
+            &facing_away,
+            &[viewer.translation],
+            &[behind],
+            &occluders,
+            &views
+        )
+        .0,
+        0.0
+    );
+}
+
+#[test]
+fn indirect_views_test_1() {
+    // Two mirrors facing each other show each other, up to the number of reflections.
+    let left = Occluder {
+        top_left: Vec3::new(-55.0, 20.0, 0.0),
+        bottom_right: Vec3::new(-50.0, -20.0, 0.0),
+        mirror: true,
+        ..default()
+    };
+    let right = mirror();
+    let occluders = [&left, &right];
+
+    assert_eq!(indirect_views(Vec3::ZERO, &occluders, &[], 0, 0).len(), 0);
+    assert_eq!(indirect_views(Vec3::ZERO, &occluders, &[], 1, 0).len(), 2);
+    assert_eq!(indirect_views(Vec3::ZERO, &occluders, &[], 3, 0).len(), 6);
+}
+
+#[test]
+fn indirect_region_test_1() {
+    let mirror = mirror();
+    let occluders = [&mirror];
+    let bounds = Rect::new(-100.0, -100.0, 100.0, 100.0);
+    let origin = Vec3::new(20.0, 0.0, 0.0);
+
+    let views = indirect_views(origin, &occluders, &[], 1, 0);
+    let region = views[0].region(origin, &occluders, bounds);
+
+    // Everything in front of the mirror is seen in it, within the wedge from the viewer's image.
+    assert!(region.contains_point(&Vec3::new(0.0, 0.0, 0.0)));
+    assert!(region.contains_point(&Vec3::new(-90.0, 40.0, 0.0)));
+    assert!(!region.contains_point(&Vec3::new(0.0, 80.0, 0.0)));
+    assert!(!region.contains_point(&Vec3::new(70.0, 0.0, 0.0)));
+    assert_eq!(region.triangles().count(), 2 * (region.rays.len() - 1));
+}
+
+#[test]
+fn portal_test_1() {
+    // A viewer facing into a portal on one wall sees out of its other end, on another wall, what's
+    // behind the viewer.
+    let walls = [
+        Occluder {
+            top_left: Vec3::new(-5.0, 20.0, 0.0),
+            bottom_right: Vec3::new(5.0, -20.0, 0.0),
+            ..default()
+        },
+        Occluder {
+            top_left: Vec3::new(95.0, 20.0, 0.0),
+            bottom_right: Vec3::new(105.0, -20.0, 0.0),
+            ..default()
+        },
+    ];
+    let occluders = [&walls[0], &walls[1]];
+    let portal = Portal {
+        a_start: Vec3::new(5.0, -10.0, 0.0),
+        a_end: Vec3::new(5.0, 10.0, 0.0),
+        b_start: Vec3::new(95.0, -10.0, 0.0),
+        b_end: Vec3::new(95.0, 10.0, 0.0),
+    };
+    let viewer = Transform::from_xyz(20.0, 0.0, 0.0)
+        .with_rotation(Quat::from_rotation_z(std::f32::consts::PI));
+    let sighted = Sighted {
+        field_of_view: Some(std::f32::consts::FRAC_PI_2),
+        ..default()
+    };
+
+    assert!(indirect_views(viewer.translation, &occluders, &[&portal], 2, 0).is_empty());
+    let views = indirect_views(viewer.translation, &occluders, &[&portal], 2, 1);
+    assert_eq!(views.len(), 2);
+
+    let fraction = |target: Vec3| {
+        indirect_fraction(
+            &sighted,
+            &viewer,
+            &[viewer.translation],
+            &[target],
+            &occluders,
+            &views,
+        )
+        .0
+    };
+    assert_eq!(fraction(Vec3::new(60.0, 0.0, 0.0)), 1.0);
+    assert_eq!(fraction(Vec3::new(60.0, 40.0, 0.0)), 0.0);
+
+    // The view out of the portal's other end reaches back to the first wall.
+    let region = views[0].region(
+        viewer.translation,
+        &occluders,
+        Rect::new(-100.0, -100.0, 200.0, 100.0),
+    );
+    assert!(region.contains_point(&Vec3::new(60.0, 0.0, 0.0)));
+    assert!(region.contains_point(&Vec3::new(10.0, 0.0, 0.0)));
+    assert!(!region.contains_point(&Vec3::new(0.0, 0.0, 0.0)));
+    assert!(!region.contains_point(&Vec3::new(60.0, 40.0, 0.0)));
+}
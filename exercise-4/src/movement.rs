@@ -1,11 +1,19 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+use crate::{
+    sight::{Occluder, OcclusionLayers},
+    spotlight::Spotlight,
+};
+
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Speed {
     pub value: f32,
 }
 
-#[derive(Component)]
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Direction {
     pub value: Vec2,
 }
@@ -16,16 +24,143 @@ impl From<Vec2> for Direction {
     }
 }
 
-fn update_position(mut query: Query<(&mut Transform, &Speed, &Direction)>, time: Res<Time>) {
-    for (mut transform, speed, direction) in query.iter_mut() {
+/// Whether moving a `size` box from `from` to `to` would run into an occluder that blocks movement.
+///
+/// Occluders that the box already overlaps don't block it, so that anything spawned inside one can
+/// still move out.
+pub fn movement_blocked(from: Vec2, to: Vec2, size: Vec2, occluders: &[&Occluder]) -> bool {
+    let (from, to) = (
+        Rect::from_center_size(from, size),
+        Rect::from_center_size(to, size),
+    );
+    let overlaps = |rect: Rect, occluder: &Occluder| !occluder.rect().intersect(rect).is_empty();
+    occluders.iter().any(|occluder| {
+        occluder.blocks(OcclusionLayers::MOVEMENT)
+            && overlaps(to, occluder)
+            && !overlaps(from, occluder)
+    })
+}
+
+#[test]
+fn movement_blocked_test_1() {
+    let wall = Occluder {
+        top_left: Vec3::new(10.0, 10.0, 0.0),
+        bottom_right: Vec3::new(20.0, -10.0, 0.0),
+        ..default()
+    };
+    let window = Occluder {
+        layers: OcclusionLayers::MOVEMENT,
+        ..wall.clone()
+    };
+    let curtain = Occluder {
+        layers: OcclusionLayers::SIGHT,
+        ..wall.clone()
+    };
+    let size = Vec2::splat(4.0);
+
+    assert!(!movement_blocked(
+        Vec2::ZERO,
+        Vec2::new(7.0, 0.0),
+        size,
+        &[&wall]
+    ));
+    assert!(movement_blocked(
+        Vec2::ZERO,
+        Vec2::new(9.0, 0.0),
+        size,
+        &[&wall]
+    ));
+    assert!(movement_blocked(
+        Vec2::ZERO,
+        Vec2::new(9.0, 0.0),
+        size,
+        &[&window]
+    ));
+    assert!(!movement_blocked(
+        Vec2::ZERO,
+        Vec2::new(9.0, 0.0),
+        size,
+        &[&curtain]
+    ));
+    // Already inside.
+    assert!(!movement_blocked(
+        Vec2::new(15.0, 0.0),
+        Vec2::new(16.0, 0.0),
+        size,
+        &[&wall]
+    ));
+}
+
+/// The entities that move, with the sprites that give their size and the spotlights that make them
+/// turn.
+type MovingQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Transform,
+        &'static Speed,
+        &'static Direction,
+        Option<&'static Sprite>,
+        Option<&'static Spotlight>,
+    ),
+>;
+
+/// Moves entities in their direction at their speed, one axis at a time so that they slide along
+/// occluders that block their movement rather than sticking to them. Entities carrying a
+/// [`Spotlight`] also turn to face the way they're going, so that their beam follows them.
+fn update_position(mut query: MovingQuery, occluders: Query<&Occluder>, time: Res<Time>) {
+    let occluders: Vec<&Occluder> = occluders.iter().collect();
+    for (mut transform, speed, direction, sprite, spotlight) in query.iter_mut() {
         if direction.value != Vec2::ZERO {
             let normalized_direction = direction.value.normalize_or_zero();
-            transform.translation.x += speed.value * normalized_direction.x * time.delta_seconds();
-            transform.translation.y += speed.value * normalized_direction.y * time.delta_seconds();
+            if spotlight.is_some() {
+                let rotation =
+                    Quat::from_rotation_z(normalized_direction.y.atan2(normalized_direction.x));
+                if transform.rotation != rotation {
+                    transform.rotation = rotation;
+                }
+            }
+            let step = speed.value * normalized_direction * time.delta_seconds();
+            let size = sprite
+                .and_then(|sprite| sprite.custom_size)
+                .unwrap_or(Vec2::ZERO);
+
+            for axis_step in [Vec2::new(step.x, 0.0), Vec2::new(0.0, step.y)] {
+                let from = transform.translation.truncate();
+                if !movement_blocked(from, from + axis_step, size, &occluders) {
+                    transform.translation += axis_step.extend(0.0);
+                }
+            }
         }
     }
 }
 
+#[test]
+fn update_position_test_1() {
+    use crate::player::Player;
+
+    let mut app = crate::headless::app(crate::level::Level::default());
+    app.update();
+    let player = app
+        .world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&app.world);
+
+    // Without a flashlight, the player keeps its orientation as it walks diagonally.
+    app.world.get_mut::<Direction>(player).unwrap().value = Vec2::new(1.0, -1.0);
+    app.update();
+    assert_eq!(
+        app.world.get::<Transform>(player).unwrap().rotation,
+        Quat::IDENTITY
+    );
+
+    // With one, it turns to face the way it's walking.
+    app.world.entity_mut(player).insert(Spotlight::default());
+    app.update();
+    let facing = app.world.get::<Transform>(player).unwrap().rotation * Vec3::X;
+    assert!(facing.abs_diff_eq(Vec3::new(1.0, -1.0, 0.0).normalize(), 1e-5));
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash, SystemSet)]
 pub struct MovementSet;
 
@@ -33,6 +168,8 @@ pub struct MovementPlugin;
 
 impl Plugin for MovementPlugin {
     fn build(&self, app: &mut App) {
+        app.register_type::<Speed>().register_type::<Direction>();
+
         app.add_system(update_position.in_set(MovementSet));
     }
 }
@@ -1,20 +1,81 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    controls, light, movement,
+    controls, light,
+    movement::{self, MovementSet, Speed},
     player::Player,
     sight::{CheckVisibility, Sighted, Visible},
 };
 
-#[derive(Component)]
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Npc;
 
+/// How fast NPCs walk their patrol routes.
+pub const NPC_SPEED: f32 = 50.0;
+
+/// A closed route that an NPC walks around, facing the way it's going.
+#[derive(
+    Component, Debug, Default, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize,
+)]
+#[reflect(Component, Default)]
+pub struct Patrol {
+    pub waypoints: Vec<Vec2>,
+    /// The index of the waypoint that the NPC is walking towards.
+    pub next: usize,
+}
+
+impl Patrol {
+    /// A route through `waypoints`, starting towards the second one.
+    pub fn new(waypoints: Vec<Vec2>) -> Self {
+        Self { waypoints, next: 1 }
+    }
+
+    /// Points spaced `spacing` apart around the route, and the direction of travel at each.
+    pub fn poses(&self, spacing: f32) -> Vec<(Vec2, Vec2)> {
+        let mut poses = Vec::new();
+        for (index, start) in self.waypoints.iter().enumerate() {
+            let end = self.waypoints[(index + 1) % self.waypoints.len()];
+            let (offset, length) = (end - *start, start.distance(end));
+            if length == 0.0 {
+                continue;
+            }
+
+            let steps = (length / spacing).ceil() as usize;
+            poses.extend((0..steps).map(|step| {
+                let position = *start + offset * step as f32 / steps as f32;
+                (position, offset / length)
+            }));
+        }
+        poses
+    }
+}
+
+#[test]
+fn patrol_poses_test_1() {
+    let patrol = Patrol::new(vec![Vec2::ZERO, Vec2::new(10.0, 0.0)]);
+    assert_eq!(
+        patrol.poses(5.0),
+        vec![
+            (Vec2::ZERO, Vec2::X),
+            (Vec2::new(5.0, 0.0), Vec2::X),
+            (Vec2::new(10.0, 0.0), -Vec2::X),
+            (Vec2::new(5.0, 0.0), -Vec2::X),
+        ]
+    );
+    assert!(Patrol::new(vec![Vec2::ZERO]).poses(5.0).is_empty());
+}
+
 #[derive(Bundle)]
 pub struct NpcBundle {
     npc: Npc,
     sprite: SpriteBundle,
     sighted: Sighted,
     visible: Visible,
+    speed: Speed,
+    direction: movement::Direction,
+    patrol: Patrol,
 }
 
 impl NpcBundle {
@@ -29,8 +90,11 @@ impl NpcBundle {
                 },
                 ..default()
             },
-            sighted: Sighted,
+            sighted: Sighted::default(),
             visible: Visible,
+            speed: Speed { value: NPC_SPEED },
+            direction: movement::Direction { value: Vec2::ZERO },
+            patrol: Patrol::default(),
         }
     }
 
@@ -38,6 +102,16 @@ impl NpcBundle {
         self.sprite.transform = transform;
         self
     }
+
+    pub fn with_sighted(mut self, sighted: Sighted) -> Self {
+        self.sighted = sighted;
+        self
+    }
+
+    pub fn with_patrol(mut self, patrol: Patrol) -> Self {
+        self.patrol = patrol;
+        self
+    }
 }
 
 impl Default for NpcBundle {
@@ -46,15 +120,63 @@ impl Default for NpcBundle {
     }
 }
 
+/// Points patrolling NPCs towards their next waypoint, and turns them to face it.
+fn patrol(
+    time: Res<Time>,
+    mut npcs: Query<(
+        &mut Patrol,
+        &mut Transform,
+        &mut movement::Direction,
+        &Speed,
+    )>,
+) {
+    for (mut patrol, mut transform, mut direction, speed) in npcs.iter_mut() {
+        if patrol.waypoints.len() < 2 {
+            continue;
+        }
+
+        let position = transform.translation.truncate();
+        let mut target = patrol.waypoints[patrol.next % patrol.waypoints.len()];
+        // Turn towards the next waypoint once this one would be reached in this frame.
+        if position.distance(target) <= speed.value * time.delta_seconds() {
+            patrol.next = (patrol.next + 1) % patrol.waypoints.len();
+            target = patrol.waypoints[patrol.next];
+        }
+
+        let new_direction = (target - position).normalize_or_zero();
+        if direction.value != new_direction {
+            direction.value = new_direction;
+            if new_direction != Vec2::ZERO {
+                transform.rotation = Quat::from_rotation_z(new_direction.y.atan2(new_direction.x));
+            }
+        }
+    }
+}
+
+/// The sprites of the NPCs that can see, which show whether they see a player.
+type SightedNpcSpriteQuery<'w, 's> =
+    Query<'w, 's, (Entity, &'static mut Sprite), (With<Npc>, With<Sighted>)>;
+
 fn see_player(
     check_visibility: CheckVisibility,
     player_query: Query<Entity, With<Player>>,
-    mut sprite_query: Query<(Entity, &mut Sprite), (With<Npc>, With<Sighted>)>,
+    mut sprite_query: SightedNpcSpriteQuery,
 ) {
-    let player_entity = player_query.get_single().unwrap();
-
     for (npc_entity, mut npc_sprite) in sprite_query.iter_mut() {
-        if check_visibility.sees(npc_entity, player_entity) {
+        let mut sees_player = false;
+
+        for player_entity in player_query.iter() {
+            match check_visibility.sees(npc_entity, player_entity) {
+                Ok(sees) => {
+                    sees_player |= sees;
+                }
+                Err(err) => {
+                    debug!("skipping sight check for {:?}: {}", npc_entity, err);
+                }
+            }
+        }
+
+        if sees_player {
             npc_sprite.color = Color::GREEN;
         } else {
             npc_sprite.color = Color::GRAY;
@@ -69,13 +191,18 @@ pub struct NpcPlugin;
 
 impl Plugin for NpcPlugin {
     fn build(&self, app: &mut App) {
+        app.register_type::<Npc>()
+            .register_type::<Patrol>()
+            .register_type::<Vec<Vec2>>();
+
         app.configure_set(
             NpcSet
                 .before(controls::ControlsSet)
-                .after(movement::MovementSet)
+                .after(MovementSet)
                 .after(light::LightSet),
         );
 
-        app.add_system(see_player.in_set(NpcSet));
+        app.add_system(patrol.before(MovementSet))
+            .add_system(see_player.in_set(NpcSet));
     }
 }
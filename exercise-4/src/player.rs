@@ -1,15 +1,21 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, render::view::RenderLayers};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     controls::Controlled,
-    light::{LightSet, PlayerShadow, SegmentShadow},
+    light::{IndirectLight, LightSet, PlayerShadow, SegmentShadow},
     movement::{self, MovementSet, Speed},
-    sight::{Sighted, Visible},
+    sight::{Sighted, Team, VisibilityShape, Visible},
+    viewport::ViewLayer,
 };
 
-#[derive(Component)]
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Player;
 
+/// The size of a player's sprite, which is also the shape that others see.
+pub const PLAYER_SIZE: Vec2 = Vec2::new(10.0, 10.0);
+
 #[derive(Bundle)]
 pub struct PlayerBundle {
     player: Player,
@@ -28,18 +34,33 @@ impl PlayerBundle {
             sprite_bundle: SpriteBundle {
                 sprite: Sprite {
                     color: Color::BLUE,
-                    custom_size: Some(Vec2 { x: 10.0, y: 10.0 }),
+                    custom_size: Some(PLAYER_SIZE),
                     ..default()
                 },
                 ..default()
             },
             speed: Speed { value: 100.0 },
             direction: movement::Direction { value: Vec2::ZERO },
-            controlled: Controlled,
-            sighted: Sighted,
+            controlled: Controlled::default(),
+            sighted: Sighted::default(),
             visible: Visible,
         }
     }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.sprite_bundle.transform = transform;
+        self
+    }
+
+    pub fn with_controls(mut self, controlled: Controlled) -> Self {
+        self.controlled = controlled;
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.sprite_bundle.sprite.color = color;
+        self
+    }
 }
 
 impl Default for PlayerBundle {
@@ -48,58 +69,69 @@ impl Default for PlayerBundle {
     }
 }
 
+/// Shows each visible entity in the viewports of the players who can see it.
+///
+/// A player in a [`Team`] sees whatever any [`Sighted`] member of their team can see. Otherwise,
+/// a player sees only what they can see themselves. A viewer can see an entity that's within its
+/// range and field of view, unless the entity is entirely within one of the shadows cast from that
+/// viewer's point of view. Either way, it can see an entity that's partly in the light that the
+/// viewer sees indirectly, in a mirror or through a portal.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn object_visibility(
+    mut commands: Commands,
     mut visible_entities: Query<
         (
             Entity,
             &GlobalTransform,
-            &Sprite,
+            &VisibilityShape,
             &mut bevy::render::view::Visibility,
+            Option<&RenderLayers>,
         ),
-        (With<Visible>, Without<Player>),
+        With<Visible>,
     >,
+    players: Query<(Entity, &ViewLayer, Option<&Team>), With<Player>>,
+    team_members: Query<(Entity, &Team), With<Sighted>>,
+    sighted_viewers: Query<(&GlobalTransform, &Sighted)>,
     player_shadows: Query<(&PlayerShadow, &Children)>,
     segment_shadows: Query<&SegmentShadow>,
+    indirect_lights: Query<&IndirectLight>,
 ) {
+    fn entity_in_view(
+        viewer: Entity,
+        global_transform: &GlobalTransform,
+        shape: &VisibilityShape,
+        sighted_viewers: &Query<(&GlobalTransform, &Sighted)>,
+    ) -> bool {
+        let Ok((viewer_transform, sighted)) = sighted_viewers.get(viewer) else {
+            return true;
+        };
+
+        let offset = global_transform.transform_point(shape.centre().extend(0.0))
+            - viewer_transform.translation();
+        sighted.in_range(offset) && sighted.in_field_of_view(viewer_transform.right(), offset)
+    }
+
     fn entity_in_player_shadows(
         entity: Entity,
+        viewer: Entity,
         global_transform: &GlobalTransform,
-        size: Vec2,
+        shape: &VisibilityShape,
         player_shadows: &Query<(&PlayerShadow, &Children)>,
         segment_shadows: &Query<&SegmentShadow>,
     ) -> bool {
+        let outline = shape.outline_points();
+
         player_shadows
             .iter()
             .any(|(player_shadow, player_shadow_children)| {
-                entity != player_shadow.occluder
+                player_shadow.viewer == viewer
+                    && entity != player_shadow.occluder
                     && player_shadow_children.iter().any(|player_shadow_child| {
                         if let Ok(segment_shadow) = segment_shadows.get(*player_shadow_child) {
-                            [
-                                Vec3 {
-                                    x: -size.x / 2.0,
-                                    y: size.y / 2.0,
-                                    z: 0.0,
-                                },
-                                Vec3 {
-                                    x: size.x / 2.0,
-                                    y: size.y / 2.0,
-                                    z: 0.0,
-                                },
-                                Vec3 {
-                                    x: size.x / 2.0,
-                                    y: -size.y / 2.0,
-                                    z: 0.0,
-                                },
-                                Vec3 {
-                                    x: -size.x / 2.0,
-                                    y: -size.y / 2.0,
-                                    z: 0.0,
-                                },
-                            ]
-                            .into_iter()
-                            .all(|location| {
-                                segment_shadow
-                                    .contains_point(&global_transform.transform_point(location))
+                            outline.iter().all(|location| {
+                                segment_shadow.contains_point(
+                                    &global_transform.transform_point(location.extend(0.0)),
+                                )
                             })
                         } else {
                             false
@@ -108,14 +140,74 @@ fn object_visibility(
             })
     }
 
-    for (entity, global_transform, sprite, mut visibility) in visible_entities.iter_mut() {
-        if entity_in_player_shadows(
-            entity,
-            global_transform,
-            sprite.custom_size.unwrap(),
-            &player_shadows,
-            &segment_shadows,
-        ) {
+    fn entity_in_indirect_light(
+        viewer: Entity,
+        global_transform: &GlobalTransform,
+        shape: &VisibilityShape,
+        indirect_lights: &Query<&IndirectLight>,
+    ) -> bool {
+        let outline = shape.outline_points();
+
+        indirect_lights.iter().any(|indirect_light| {
+            indirect_light.viewer == viewer
+                && outline.iter().any(|location| {
+                    indirect_light
+                        .region
+                        .contains_point(&global_transform.transform_point(location.extend(0.0)))
+                })
+        })
+    }
+
+    // The viewers whose vision is shared by each player.
+    let player_viewers: Vec<(ViewLayer, Vec<Entity>)> = players
+        .iter()
+        .map(|(player, view_layer, team)| {
+            let viewers = match team {
+                Some(team) => team_members
+                    .iter()
+                    .filter(|(_, member_team)| *member_team == team)
+                    .map(|(member, _)| member)
+                    .collect(),
+                None => vec![player],
+            };
+            (*view_layer, viewers)
+        })
+        .collect();
+
+    for (entity, global_transform, shape, mut visibility, render_layers) in
+        visible_entities.iter_mut()
+    {
+        let seen_by = player_viewers
+            .iter()
+            .filter(|(_, viewers)| {
+                viewers.iter().any(|viewer| {
+                    *viewer == entity
+                        || entity_in_view(*viewer, global_transform, shape, &sighted_viewers)
+                            && !entity_in_player_shadows(
+                                entity,
+                                *viewer,
+                                global_transform,
+                                shape,
+                                &player_shadows,
+                                &segment_shadows,
+                            )
+                        || entity_in_indirect_light(
+                            *viewer,
+                            global_transform,
+                            shape,
+                            &indirect_lights,
+                        )
+                })
+            })
+            .fold(RenderLayers::none(), |render_layers, (view_layer, _)| {
+                render_layers.with(view_layer.0)
+            });
+
+        if render_layers != Some(&seen_by) {
+            commands.entity(entity).insert(seen_by);
+        }
+
+        if seen_by == RenderLayers::none() {
             *visibility = Visibility::Hidden;
         } else {
             *visibility = Visibility::Visible;
@@ -126,10 +218,58 @@ fn object_visibility(
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
 pub struct PlayerSet;
 
+#[test]
+fn player_despawn_test_1() {
+    use crate::level::{Level, PlayerSpawn};
+
+    // A second player, who plays with the arrow keys.
+    let mut level = Level::default();
+    level.players.push(PlayerSpawn {
+        position: Vec2::new(0.0, -60.0),
+        controls: crate::level::ControlScheme::Arrows,
+        ..level.players[0].clone()
+    });
+    let mut app = crate::headless::app(level);
+    for _ in 0..3 {
+        app.update();
+    }
+
+    let players = |app: &mut App| -> Vec<Entity> {
+        app.world
+            .query_filtered::<Entity, With<Player>>()
+            .iter(&app.world)
+            .collect()
+    };
+    let shadow_viewers = |app: &mut App| -> Vec<Entity> {
+        app.world
+            .query::<&PlayerShadow>()
+            .iter(&app.world)
+            .map(|player_shadow| player_shadow.viewer)
+            .collect()
+    };
+    let both = players(&mut app);
+    assert_eq!(both.len(), 2);
+    assert!(both
+        .iter()
+        .all(|player| shadow_viewers(&mut app).contains(player)));
+
+    // The game carries on without each player in turn, rather than panicking.
+    for player in both {
+        app.world.entity_mut(player).despawn_recursive();
+        for _ in 0..3 {
+            app.update();
+        }
+        assert!(!players(&mut app).contains(&player));
+        assert!(!shadow_viewers(&mut app).contains(&player));
+    }
+}
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
+        app.register_type::<Player>();
+
         app.configure_set(PlayerSet.after(MovementSet).after(LightSet));
 
         app.add_system(object_visibility.in_set(PlayerSet));
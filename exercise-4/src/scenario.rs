@@ -0,0 +1,254 @@
+use std::path::Path;
+
+use bevy::{
+    ecs::system::SystemState,
+    input::{keyboard::KeyboardInput, ButtonState},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    headless,
+    level::{Level, LevelError, LevelObject},
+    sight::CheckVisibility,
+};
+
+/// A level to run headless, the keys to press while it runs, and what should be seen when.
+///
+/// Frames are numbered from 0. The inputs for a frame are sent before it's updated, and its
+/// expectations are checked after. See `scenarios/` for examples.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub level: Level,
+    #[serde(default)]
+    pub inputs: Vec<ScenarioInput>,
+    pub expect: Vec<Expectation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioInput {
+    pub frame: u32,
+    #[serde(default)]
+    pub press: Vec<KeyCode>,
+    #[serde(default)]
+    pub release: Vec<KeyCode>,
+}
+
+/// That `viewer` does or doesn't see `viewee` at `frame`, as checked by [`CheckVisibility`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Expectation {
+    pub frame: u32,
+    pub viewer: LevelObject,
+    pub viewee: LevelObject,
+    pub sees: bool,
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame {}: {} {} {}",
+            self.frame,
+            self.viewer,
+            if self.sees { "sees" } else { "doesn't see" },
+            self.viewee
+        )
+    }
+}
+
+/// The outcome of checking an [`Expectation`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationResult {
+    pub expectation: Expectation,
+    /// Whether the viewer saw the viewee, or why that couldn't be checked.
+    pub actual: Result<bool, String>,
+}
+
+impl ExpectationResult {
+    pub fn passed(&self) -> bool {
+        self.actual == Ok(self.expectation.sees)
+    }
+}
+
+impl std::fmt::Display for ExpectationResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.actual {
+            _ if self.passed() => write!(f, "pass: {}", self.expectation),
+            Ok(_) => write!(f, "FAIL: {}", self.expectation),
+            Err(err) => write!(f, "FAIL: {} ({})", self.expectation, err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioReport {
+    pub results: Vec<ExpectationResult>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(ExpectationResult::passed)
+    }
+}
+
+impl std::fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+        let passed = self.results.iter().filter(|result| result.passed()).count();
+        write!(
+            f,
+            "{}: {} of {} expectations passed",
+            if self.passed() { "passed" } else { "FAILED" },
+            passed,
+            self.results.len()
+        )
+    }
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, LevelError> {
+        let scenario: Scenario = ron::from_str(source).map_err(|err| LevelError::Parse {
+            line: err.position.line,
+            column: err.position.col,
+            message: err.code.to_string(),
+        })?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), LevelError> {
+        self.level.validate().map_err(|err| match err {
+            LevelError::Invalid { field, message } => LevelError::Invalid {
+                field: format!("level.{}", field),
+                message,
+            },
+            err => err,
+        })?;
+
+        for (index, expectation) in self.expect.iter().enumerate() {
+            for (name, object) in [
+                ("viewer", expectation.viewer),
+                ("viewee", expectation.viewee),
+            ] {
+                if !self.level.contains(object) {
+                    return Err(LevelError::Invalid {
+                        field: format!("expect[{}].{}", index, name),
+                        message: format!("the level has no {}", object),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The last frame with an input or an expectation.
+    fn last_frame(&self) -> u32 {
+        self.inputs
+            .iter()
+            .map(|input| input.frame)
+            .chain(self.expect.iter().map(|expectation| expectation.frame))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Runs the scenario in a headless app.
+    pub fn run(&self) -> ScenarioReport {
+        let mut app = headless::app(self.level.clone());
+
+        let mut results = Vec::new();
+        for frame in 0..=self.last_frame() {
+            for input in self.inputs.iter().filter(|input| input.frame == frame) {
+                send_keys(&mut app.world, &input.press, ButtonState::Pressed);
+                send_keys(&mut app.world, &input.release, ButtonState::Released);
+            }
+
+            app.update();
+
+            for expectation in self
+                .expect
+                .iter()
+                .filter(|expectation| expectation.frame == frame)
+            {
+                results.push(ExpectationResult {
+                    expectation: *expectation,
+                    actual: check_expectation(&mut app.world, expectation),
+                });
+            }
+        }
+
+        ScenarioReport { results }
+    }
+}
+
+fn send_keys(world: &mut World, key_codes: &[KeyCode], state: ButtonState) {
+    for key_code in key_codes {
+        world.send_event(KeyboardInput {
+            scan_code: 0,
+            key_code: Some(*key_code),
+            state,
+        });
+    }
+}
+
+fn level_object_entity(world: &mut World, object: LevelObject) -> Result<Entity, String> {
+    world
+        .query::<(Entity, &LevelObject)>()
+        .iter(world)
+        .find(|(_, level_object)| **level_object == object)
+        .map(|(entity, _)| entity)
+        .ok_or_else(|| format!("{} hasn't been spawned", object))
+}
+
+fn check_expectation(world: &mut World, expectation: &Expectation) -> Result<bool, String> {
+    let viewer = level_object_entity(world, expectation.viewer)?;
+    let viewee = level_object_entity(world, expectation.viewee)?;
+
+    let mut system_state: SystemState<CheckVisibility> = SystemState::new(world);
+    let check_visibility = system_state.get(world);
+    check_visibility
+        .sees(viewer, viewee)
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn scenario_parse_test_1() {
+    let source = "(\n    level: (players: [(position: (0.0, 0.0))]),\n    expect: [\n        (frame: 1, viewer: (kind: Npc, index: 0), viewee: (kind: Player, index: 0), sees: true),\n    ],\n)";
+    match Scenario::parse(source) {
+        Err(LevelError::Invalid { field, .. }) => assert_eq!(field, "expect[0].viewer"),
+        result => panic!("expected an invalid field, got {:?}", result),
+    }
+}
+
+#[test]
+fn scenario_run_test_1() {
+    let scenario = Scenario::parse(include_str!("../scenarios/guard_sees_player.ron")).unwrap();
+    let report = scenario.run();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.results.len(), scenario.expect.len());
+}
+
+#[test]
+fn scenario_run_test_2() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let scenario = Scenario::load(&path)
+            .unwrap_or_else(|err| panic!("couldn't load {}: {}", path.display(), err));
+        let report = scenario.run();
+        assert!(report.passed(), "{}:\n{}", path.display(), report);
+    }
+}
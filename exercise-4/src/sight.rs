@@ -1,68 +1,704 @@
-use bevy::{ecs::system::SystemParam, prelude::*};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Component)]
-pub struct Sighted;
+use bevy::{
+    ecs::{query::WorldQuery, system::SystemParam},
+    prelude::*,
+    sprite::{Anchor, Mesh2dHandle},
+    text::TextLayoutInfo,
+};
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+use crate::mirror::{indirect_fraction, indirect_views};
+
+#[derive(Component, Debug, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct Sighted {
+    pub sampling: SightSampling,
+    /// The fraction of sight lines that must be unobstructed for the viewer to see a viewee.
+    pub threshold: f32,
+    /// How far the viewer can see. `None` means there is no limit.
+    pub range: Option<f32>,
+    /// The angle (in radians) of the viewer's field of view, centred on its transform's local X
+    /// axis and measured across the ground, so that a viewer can see anything below or above it
+    /// that's in front of it. `None` means the viewer can see in every direction.
+    pub field_of_view: Option<f32>,
+    /// The layers of the occluders that block the viewer's sight.
+    pub blocked_by: OcclusionLayers,
+}
+
+impl Default for Sighted {
+    fn default() -> Self {
+        Self {
+            sampling: SightSampling::Centre,
+            threshold: 0.5,
+            range: None,
+            field_of_view: None,
+            blocked_by: OcclusionLayers::SIGHT,
+        }
+    }
+}
+
+impl Sighted {
+    /// Whether a point at `offset` from the viewer is within its range.
+    pub fn in_range(&self, offset: Vec3) -> bool {
+        self.range.map_or(true, |range| offset.length() <= range)
+    }
+
+    /// Whether a point at `offset` from the viewer is within its field of view, when the viewer is
+    /// facing `facing`. Differences in height are ignored.
+    pub fn in_field_of_view(&self, facing: Vec3, offset: Vec3) -> bool {
+        let (facing, offset) = (facing.truncate(), offset.truncate());
+        self.field_of_view.map_or(true, |field_of_view| {
+            offset == Vec2::ZERO || facing.angle_between(offset).abs() <= field_of_view / 2.0
+        })
+    }
+}
+
+#[test]
+fn sighted_in_field_of_view_test_1() {
+    let sighted = Sighted {
+        field_of_view: Some(std::f32::consts::FRAC_PI_2),
+        ..default()
+    };
+
+    assert!(sighted.in_field_of_view(Vec3::X, Vec3::X));
+    assert!(sighted.in_field_of_view(Vec3::X, Vec3::X + 0.5 * Vec3::Y));
+    assert!(!sighted.in_field_of_view(Vec3::X, Vec3::Y));
+    assert!(!sighted.in_field_of_view(Vec3::X, -Vec3::X));
+
+    assert!(Sighted::default().in_field_of_view(Vec3::X, -Vec3::X));
+
+    // A viewer can look straight down from a height.
+    assert!(sighted.in_field_of_view(Vec3::X, Vec3::X - 10.0 * Vec3::Z));
+}
+
+/// How sight lines are drawn from a [`Sighted`] entity to a [`Visible`] entity.
+#[derive(Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+pub enum SightSampling {
+    /// A single sight line, from the viewer's centre to the viewee's centre.
+    Centre,
+    /// Sight lines from each point sampled across the viewer's `eye` to each point sampled across
+    /// the viewee's [`VisibilityShape`].
+    Bounds { eye: VisibilityShape },
+}
+
+/// Membership of a team, whose [`Sighted`] members share their vision.
+#[derive(
+    Component,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Reflect,
+    FromReflect,
+    Serialize,
+    Deserialize,
+)]
+#[reflect(Component, Default)]
+pub struct Team(pub u32);
+
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Visible;
 
-#[derive(Component)]
+/// The area that a [`Visible`] entity occupies, relative to its transform.
+///
+/// Sight and shadow tests are performed against this shape. When a [`Visible`] entity doesn't have
+/// a `VisibilityShape`, one is derived from its sprite, texture atlas sprite, 2D mesh or text.
+#[derive(
+    Component, Debug, Default, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize,
+)]
+#[reflect(Component, Default)]
+pub enum VisibilityShape {
+    #[default]
+    Point,
+    Circle {
+        radius: f32,
+    },
+    /// A rectangle centred on the entity.
+    Rectangle {
+        size: Vec2,
+    },
+    Polygon {
+        vertices: Vec<Vec2>,
+    },
+}
+
+impl VisibilityShape {
+    /// The number of points used to approximate a circle's outline.
+    const CIRCLE_SAMPLES: usize = 8;
+
+    /// A `size`d rectangle, positioned relative to the entity according to `anchor`.
+    pub fn anchored_rectangle(size: Vec2, anchor: &Anchor) -> Self {
+        Self::rectangle_at(-anchor.as_vec() * size, size)
+    }
+
+    /// A `size`d rectangle centred on `centre`.
+    pub fn rectangle_at(centre: Vec2, size: Vec2) -> Self {
+        if centre == Vec2::ZERO {
+            VisibilityShape::Rectangle { size }
+        } else {
+            let half_size = size / 2.0;
+            VisibilityShape::Polygon {
+                vertices: vec![
+                    centre + Vec2::new(-half_size.x, half_size.y),
+                    centre + Vec2::new(half_size.x, half_size.y),
+                    centre + Vec2::new(half_size.x, -half_size.y),
+                    centre + Vec2::new(-half_size.x, -half_size.y),
+                ],
+            }
+        }
+    }
+
+    /// The centre of the shape.
+    pub fn centre(&self) -> Vec2 {
+        match self {
+            VisibilityShape::Polygon { vertices } if !vertices.is_empty() => {
+                vertices.iter().sum::<Vec2>() / vertices.len() as f32
+            }
+            _ => Vec2::ZERO,
+        }
+    }
+
+    /// Points on the outline of the shape.
+    pub fn outline_points(&self) -> Vec<Vec2> {
+        match self {
+            VisibilityShape::Point => vec![Vec2::ZERO],
+            VisibilityShape::Circle { radius } => (0..Self::CIRCLE_SAMPLES)
+                .map(|index| {
+                    let angle = index as f32 * std::f32::consts::TAU / Self::CIRCLE_SAMPLES as f32;
+                    *radius * Vec2::new(angle.cos(), angle.sin())
+                })
+                .collect(),
+            VisibilityShape::Rectangle { size } => {
+                let half_size = *size / 2.0;
+                vec![
+                    Vec2::new(-half_size.x, half_size.y),
+                    Vec2::new(half_size.x, half_size.y),
+                    Vec2::new(half_size.x, -half_size.y),
+                    Vec2::new(-half_size.x, -half_size.y),
+                ]
+            }
+            VisibilityShape::Polygon { vertices } if vertices.is_empty() => vec![Vec2::ZERO],
+            VisibilityShape::Polygon { vertices } => vertices.clone(),
+        }
+    }
+
+    /// Points spread across the shape: its centre and its outline.
+    pub fn sample_points(&self) -> Vec<Vec2> {
+        match self {
+            VisibilityShape::Point => vec![Vec2::ZERO],
+            _ => {
+                let mut points = vec![self.centre()];
+                points.extend(self.outline_points());
+                points
+            }
+        }
+    }
+}
+
+#[test]
+fn visibility_shape_anchored_rectangle_test_1() {
+    let size = Vec2::new(4.0, 2.0);
+
+    assert_eq!(
+        VisibilityShape::anchored_rectangle(size, &Anchor::Center),
+        VisibilityShape::Rectangle { size }
+    );
+
+    let shape = VisibilityShape::anchored_rectangle(size, &Anchor::BottomLeft);
+    assert_eq!(shape.centre(), Vec2::new(2.0, 1.0));
+    assert_eq!(
+        shape.outline_points(),
+        vec![
+            Vec2::new(0.0, 2.0),
+            Vec2::new(4.0, 2.0),
+            Vec2::new(4.0, 0.0),
+            Vec2::new(0.0, 0.0),
+        ]
+    );
+}
+
+/// Marks a [`VisibilityShape`] that was derived from the entity's renderable, so that it can be
+/// kept up to date when the renderable changes.
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct DerivedVisibilityShape;
+
+#[derive(WorldQuery)]
+struct VisibilityShapeSource {
+    entity: Entity,
+    shape: Option<&'static VisibilityShape>,
+    sprite: Option<&'static Sprite>,
+    image: Option<&'static Handle<Image>>,
+    atlas_sprite: Option<&'static TextureAtlasSprite>,
+    atlas: Option<&'static Handle<TextureAtlas>>,
+    mesh: Option<&'static Mesh2dHandle>,
+    text_layout: Option<&'static TextLayoutInfo>,
+    text_anchor: Option<&'static Anchor>,
+}
+
+impl VisibilityShapeSourceItem<'_> {
+    fn derive_shape(
+        &self,
+        images: &Assets<Image>,
+        atlases: &Assets<TextureAtlas>,
+        meshes: &Assets<Mesh>,
+    ) -> Option<VisibilityShape> {
+        if let Some(sprite) = self.sprite {
+            let size = match sprite.custom_size {
+                Some(size) => size,
+                None => {
+                    let image = images.get(self.image?)?;
+                    match sprite.rect {
+                        Some(rect) => rect.size(),
+                        None => image.size(),
+                    }
+                }
+            };
+            return Some(VisibilityShape::anchored_rectangle(size, &sprite.anchor));
+        }
+
+        if let Some(atlas_sprite) = self.atlas_sprite {
+            let size = match atlas_sprite.custom_size {
+                Some(size) => size,
+                None => atlases
+                    .get(self.atlas?)?
+                    .textures
+                    .get(atlas_sprite.index)?
+                    .size(),
+            };
+            return Some(VisibilityShape::anchored_rectangle(
+                size,
+                &atlas_sprite.anchor,
+            ));
+        }
+
+        if let Some(mesh) = self.mesh {
+            let aabb = meshes.get(&mesh.0)?.compute_aabb()?;
+            return Some(VisibilityShape::rectangle_at(
+                Vec2::new(aabb.center.x, aabb.center.y),
+                2.0 * Vec2::new(aabb.half_extents.x, aabb.half_extents.y),
+            ));
+        }
+
+        if let Some(text_layout) = self.text_layout {
+            return Some(VisibilityShape::anchored_rectangle(
+                text_layout.size,
+                self.text_anchor.unwrap_or(&Anchor::Center),
+            ));
+        }
+
+        None
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn derive_visibility_shapes(
+    mut commands: Commands,
+    images: Res<Assets<Image>>,
+    atlases: Res<Assets<TextureAtlas>>,
+    meshes: Res<Assets<Mesh>>,
+    query: Query<
+        VisibilityShapeSource,
+        (
+            With<Visible>,
+            Or<(Without<VisibilityShape>, With<DerivedVisibilityShape>)>,
+        ),
+    >,
+) {
+    for source in query.iter() {
+        let shape = source
+            .derive_shape(&images, &atlases, &meshes)
+            .unwrap_or(VisibilityShape::Point);
+
+        if source.shape != Some(&shape) {
+            commands
+                .entity(source.entity)
+                .insert((shape, DerivedVisibilityShape));
+        }
+    }
+}
+
+#[test]
+fn derive_visibility_shapes_test_1() {
+    use bevy::render::{
+        mesh::Indices,
+        render_resource::{Extent3d, PrimitiveTopology, TextureDimension, TextureFormat},
+    };
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Image>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<Mesh>()
+        .add_system(derive_visibility_shapes);
+
+    let image = app
+        .world
+        .resource_mut::<Assets<Image>>()
+        .add(Image::new_fill(
+            Extent3d {
+                width: 8,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+    let mut atlas = TextureAtlas::new_empty(image.clone(), Vec2::new(8.0, 4.0));
+    atlas.add_texture(Rect::new(0.0, 0.0, 3.0, 2.0));
+    let atlas = app.world.resource_mut::<Assets<TextureAtlas>>().add(atlas);
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vec![[0.0, 0.0, 0.0], [4.0, 0.0, 0.0], [4.0, 2.0, 0.0]],
+    );
+    mesh.set_indices(Some(Indices::U32(vec![0, 1, 2])));
+    let mesh = app.world.resource_mut::<Assets<Mesh>>().add(mesh);
+
+    // A sprite without a custom size is the size of its image, or of the part of it that it shows.
+    let sprite = app
+        .world
+        .spawn((Sprite::default(), image.clone(), Visible))
+        .id();
+    let cropped_sprite = app
+        .world
+        .spawn((
+            Sprite {
+                rect: Some(Rect::new(0.0, 0.0, 2.0, 3.0)),
+                anchor: Anchor::BottomLeft,
+                ..default()
+            },
+            image,
+            Visible,
+        ))
+        .id();
+    // A texture atlas sprite is the size of its texture in the atlas.
+    let atlas_sprite = app
+        .world
+        .spawn((TextureAtlasSprite::new(0), atlas, Visible))
+        .id();
+    // A mesh is the size of its bounding box, which needn't be centred on the entity.
+    let mesh = app.world.spawn((Mesh2dHandle(mesh), Visible)).id();
+    // A sprite whose image isn't loaded is a point until it is.
+    let unloaded_sprite = app
+        .world
+        .spawn((Sprite::default(), Handle::<Image>::default(), Visible))
+        .id();
+
+    app.update();
+
+    let shape = |entity: Entity| app.world.get::<VisibilityShape>(entity).cloned();
+    assert_eq!(
+        shape(sprite),
+        Some(VisibilityShape::Rectangle {
+            size: Vec2::new(8.0, 4.0)
+        })
+    );
+    assert_eq!(
+        shape(cropped_sprite),
+        Some(VisibilityShape::anchored_rectangle(
+            Vec2::new(2.0, 3.0),
+            &Anchor::BottomLeft
+        ))
+    );
+    assert_eq!(
+        shape(atlas_sprite),
+        Some(VisibilityShape::Rectangle {
+            size: Vec2::new(3.0, 2.0)
+        })
+    );
+    assert_eq!(
+        shape(mesh),
+        Some(VisibilityShape::rectangle_at(
+            Vec2::new(2.0, 1.0),
+            Vec2::new(4.0, 2.0)
+        ))
+    );
+    assert_eq!(shape(unloaded_sprite), Some(VisibilityShape::Point));
+    assert!(app.world.get::<DerivedVisibilityShape>(mesh).is_some());
+}
+
+/// A mask of the layers that an [`Occluder`] is on, which decide what it blocks.
+///
+/// The game uses the first three layers: [`OcclusionLayers::SIGHT`] blocks viewers' sight,
+/// [`OcclusionLayers::LIGHT`] casts shadows and [`OcclusionLayers::MOVEMENT`] stops entities
+/// moving. The others are free for levels to use with [`Sighted::blocked_by`], such as for walls
+/// that are low enough for guards to see over but not players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, FromReflect, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OcclusionLayers(pub u32);
+
+impl OcclusionLayers {
+    pub const NONE: Self = Self(0);
+    pub const SIGHT: Self = Self(1 << 0);
+    pub const LIGHT: Self = Self(1 << 1);
+    pub const MOVEMENT: Self = Self(1 << 2);
+    pub const ALL: Self = Self(u32::MAX);
+
+    /// Whether any layer is in both masks.
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// Every layer, so that occluders block everything unless they're told otherwise.
+impl Default for OcclusionLayers {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl std::ops::BitOr for OcclusionLayers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Occluder {
     pub top_left: Vec3,
     pub bottom_right: Vec3,
+    #[serde(default)]
+    pub layers: OcclusionLayers,
+    /// How much of the sight and light that passes through the occluder it stops, from 0 for
+    /// clear glass to 1 for a solid wall. Several semi-transparent occluders in a row stop more.
+    #[serde(default = "fully_opaque")]
+    pub opacity: f32,
+    /// The colour of the light that gets through a semi-transparent occluder.
+    #[serde(default)]
+    pub tint: Color,
+    /// Which side each edge blocks sight and light from, for one-way occluders.
+    #[serde(default)]
+    pub facing: EdgeFacings,
+    /// Whether the occluder's edges reflect sight and light, up to
+    /// [`SightConfig::max_reflections`] times.
+    #[serde(default)]
+    pub mirror: bool,
+    /// How tall the occluder is, from the ground. Sight and light pass over it where they're
+    /// higher than this, as measured along Z like the translations of viewers and viewees. `None`
+    /// for an occluder that can't be seen over.
+    #[serde(default)]
+    pub height: Option<f32>,
+}
+
+pub(crate) fn fully_opaque() -> f32 {
+    1.0
+}
+
+impl Default for Occluder {
+    fn default() -> Self {
+        Self {
+            top_left: Vec3::ZERO,
+            bottom_right: Vec3::ZERO,
+            layers: OcclusionLayers::default(),
+            opacity: fully_opaque(),
+            tint: Color::WHITE,
+            facing: EdgeFacings::default(),
+            mirror: false,
+            height: None,
+        }
+    }
+}
+
+/// One of the four edges of an [`Occluder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OccluderEdge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Which side of an [`Occluder`]'s edge it blocks sight and light from, going by where the viewer
+/// or light is.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub enum EdgeFacing {
+    #[default]
+    Both,
+    /// Only from outside the occluder, like the mirrored side of a one-way mirror.
+    Outside,
+    /// Only from inside the occluder.
+    Inside,
+    /// From neither side, like the top of a cliff that can be seen down from.
+    Neither,
+}
+
+/// The [`EdgeFacing`] of each of an [`Occluder`]'s edges.
+///
+/// A one-way occluder blocks a sight line if any edge that it crosses blocks it from the viewer's
+/// side. For example, a cliff edge that can be seen down from above but not up from below is an
+/// occluder whose bottom edge faces `Outside`, and whose other edges face `Neither`.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, FromReflect, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct EdgeFacings {
+    pub top: EdgeFacing,
+    pub bottom: EdgeFacing,
+    pub left: EdgeFacing,
+    pub right: EdgeFacing,
+}
+
+impl EdgeFacings {
+    pub fn get(&self, edge: OccluderEdge) -> EdgeFacing {
+        match edge {
+            OccluderEdge::Top => self.top,
+            OccluderEdge::Bottom => self.bottom,
+            OccluderEdge::Left => self.left,
+            OccluderEdge::Right => self.right,
+        }
+    }
 }
 
 impl Occluder {
+    /// Whether the occluder is on any of `layers`.
+    pub fn blocks(&self, layers: OcclusionLayers) -> bool {
+        self.layers.intersects(layers)
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.opacity >= 1.0
+    }
+
+    /// The fraction of the sight or light passing through the occluder that gets through.
+    pub fn transmittance(&self) -> f32 {
+        1.0 - self.opacity.clamp(0.0, 1.0)
+    }
+
+    /// Whether a sight line or ray of light at `height` is blocked by the occluder, rather than
+    /// passing over it.
+    pub fn blocks_at_height(&self, height: f32) -> bool {
+        self.height.map_or(true, |top| height < top)
+    }
+
+    /// How far the occluder's shadow reaches across the ground past it, from a light at
+    /// `light_height`, as a multiple of the distance from the light to the occluder. `None` if the
+    /// shadow is unlimited, because the occluder is at least as tall as the light.
+    pub fn shadow_reach(&self, light_height: f32) -> Option<f32> {
+        let height = self.height?;
+        (height < light_height).then(|| height.max(0.0) / (light_height - height))
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::from_corners(self.top_left.truncate(), self.bottom_right.truncate())
+    }
+
     pub fn iter_segments(&self) -> impl Iterator<Item = Segment> + '_ {
-        #[derive(Clone, Copy)]
-        enum Side {
-            Top,
-            Bottom,
-            Left,
-            Right,
+        self.iter_edges().map(|(_, segment)| segment)
+    }
+
+    /// Whether `point` is outside `segment`, one of the occluder's edges: on the side of the
+    /// edge's line away from the occluder's centre.
+    pub fn is_outside(&self, segment: &Segment, point: Vec3) -> bool {
+        let midpoint = (segment.0 + segment.1) / 2.0;
+        let centre = (self.top_left + self.bottom_right) / 2.0;
+        let direction = segment.1 - segment.0;
+        let normal = Vec3::new(-direction.y, direction.x, 0.0);
+        let outward = if normal.dot(midpoint - centre) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        outward.dot(point - midpoint) > 0.0
+    }
+
+    /// Whether `edge`, whose segment is `segment`, blocks sight and light coming from `point`.
+    pub fn edge_blocks_from(&self, edge: OccluderEdge, segment: &Segment, point: Vec3) -> bool {
+        match self.facing.get(edge) {
+            EdgeFacing::Both => true,
+            EdgeFacing::Outside => self.is_outside(segment, point),
+            EdgeFacing::Inside => !self.is_outside(segment, point),
+            EdgeFacing::Neither => false,
         }
+    }
+
+    /// The segments of the edges that block sight and light coming from `point`, which are all of
+    /// them unless the occluder is one-way.
+    pub fn iter_segments_from(&self, point: Vec3) -> impl Iterator<Item = Segment> + '_ {
+        self.iter_edges()
+            .filter(move |(edge, segment)| self.edge_blocks_from(*edge, segment, point))
+            .map(|(_, segment)| segment)
+    }
+
+    /// The segments of the edges that reflect sight and light coming from `point`: none, unless
+    /// the occluder is a mirror, and otherwise those that `point` is outside of and that block
+    /// from there.
+    pub fn iter_mirror_segments_from(&self, point: Vec3) -> impl Iterator<Item = Segment> + '_ {
+        self.iter_edges()
+            .filter(move |(edge, segment)| {
+                self.mirror
+                    && self.is_outside(segment, point)
+                    && self.edge_blocks_from(*edge, segment, point)
+            })
+            .map(|(_, segment)| segment)
+    }
 
-        let mut next_side = Some(Side::Top);
+    pub fn iter_edges(&self) -> impl Iterator<Item = (OccluderEdge, Segment)> + '_ {
+        let mut next_edge = Some(OccluderEdge::Top);
         std::iter::from_fn(move || {
-            next_side.map(|side| match side {
-                Side::Top => {
-                    next_side = Some(Side::Bottom);
-                    Segment(
-                        self.top_left,
-                        Vec3 {
-                            x: self.bottom_right.x,
-                            ..self.top_left
-                        },
+            next_edge.map(|edge| match edge {
+                OccluderEdge::Top => {
+                    next_edge = Some(OccluderEdge::Bottom);
+                    (
+                        edge,
+                        Segment(
+                            self.top_left,
+                            Vec3 {
+                                x: self.bottom_right.x,
+                                ..self.top_left
+                            },
+                        ),
                     )
                 }
-                Side::Bottom => {
-                    next_side = Some(Side::Left);
-                    Segment(
-                        Vec3 {
-                            x: self.top_left.x,
-                            ..self.bottom_right
-                        },
-                        self.bottom_right,
+                OccluderEdge::Bottom => {
+                    next_edge = Some(OccluderEdge::Left);
+                    (
+                        edge,
+                        Segment(
+                            Vec3 {
+                                x: self.top_left.x,
+                                ..self.bottom_right
+                            },
+                            self.bottom_right,
+                        ),
                     )
                 }
-                Side::Left => {
-                    next_side = Some(Side::Right);
-                    Segment(
-                        self.top_left,
-                        Vec3 {
-                            y: self.bottom_right.y,
-                            ..self.top_left
-                        },
+                OccluderEdge::Left => {
+                    next_edge = Some(OccluderEdge::Right);
+                    (
+                        edge,
+                        Segment(
+                            self.top_left,
+                            Vec3 {
+                                y: self.bottom_right.y,
+                                ..self.top_left
+                            },
+                        ),
                     )
                 }
-                Side::Right => {
-                    next_side = None;
-                    Segment(
-                        Vec3 {
-                            y: self.top_left.y,
-                            ..self.bottom_right
-                        },
-                        self.bottom_right,
+                OccluderEdge::Right => {
+                    next_edge = None;
+                    (
+                        edge,
+                        Segment(
+                            Vec3 {
+                                y: self.top_left.y,
+                                ..self.bottom_right
+                            },
+                            self.bottom_right,
+                        ),
                     )
                 }
             })
@@ -70,6 +706,39 @@ impl Occluder {
     }
 }
 
+/// A pair of edges that sight and light pass through as if they were joined, like the two ends of
+/// a teleporter. What's seen looking into one edge is what's beyond the other, turned and scaled
+/// to match, up to [`SightConfig::max_portals`] times.
+///
+/// The edges are joined start to start and end to end, so looking into one edge from its left,
+/// facing from its start to its end, shows what's on the right of the other. Portals don't block
+/// anything themselves, so they're usually put along the edges of walls.
+#[derive(
+    Component, Debug, Default, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize,
+)]
+#[reflect(Component, Default)]
+pub struct Portal {
+    pub a_start: Vec3,
+    pub a_end: Vec3,
+    pub b_start: Vec3,
+    pub b_end: Vec3,
+}
+
+impl Portal {
+    pub fn a(&self) -> Segment {
+        Segment(self.a_start, self.a_end)
+    }
+
+    pub fn b(&self) -> Segment {
+        Segment(self.b_start, self.b_end)
+    }
+
+    /// The ways through the portal, as the edge that sight goes into and the edge it comes out of.
+    pub fn ways_through(&self) -> [(Segment, Segment); 2] {
+        [(self.a(), self.b()), (self.b(), self.a())]
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Segment(pub Vec3, pub Vec3);
 
@@ -117,25 +786,34 @@ t =
 
 See also: https://en.wikipedia.org/wiki/Intersection_(geometry)#Two_line_segments
 */
-fn segment_intersects_segment(a: &Segment, b: &Segment) -> bool {
+pub(crate) fn segment_intersection(a: &Segment, b: &Segment) -> Option<(f32, f32)> {
     let s_numerator = (b.1.y - b.0.y) * (a.0.x - b.0.x) - (b.1.x - b.0.x) * (a.0.y - b.0.y);
     let s_denominator = (a.1 - a.0).y * (b.1.x - b.0.x) - (a.1 - a.0).x * (b.1.y - b.0.y);
 
     if s_denominator == 0.0 {
-        return false;
+        return None;
     }
 
     let t_numerator = (a.1 - a.0).y * (b.0.x - a.0.x) + (a.1 - a.0).x * (a.0.y - b.0.y);
     let t_denominator = (a.1 - a.0).x * (b.1.y - b.0.y) - (a.1 - a.0).y * (b.1.x - b.0.x);
 
     if t_denominator == 0.0 {
-        return false;
+        return None;
     }
 
     let s = s_numerator / s_denominator;
     let t = t_numerator / t_denominator;
 
-    (0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t)
+    if (0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t) {
+        Some((s, t))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+fn segment_intersects_segment(a: &Segment, b: &Segment) -> bool {
+    segment_intersection(a, b).is_some()
 }
 
 #[test]
@@ -174,207 +852,1360 @@ fn segment_intersects_segment_test_4() {
     assert!(segment_intersects_segment(&a, &b))
 }
 
-fn segment_intersects_occluder(segment: &Segment, occluder: &Occluder) -> bool {
-    let top = Segment(
-        occluder.top_left,
-        Vec3 {
-            x: occluder.bottom_right.x,
-            ..occluder.top_left
-        },
-    );
-    if segment_intersects_segment(segment, &top) {
-        return true;
+/// Whether a sight line from `segment.0` to `segment.1` is blocked by `occluder`, going by which
+/// side of any one-way edges it starts on, and whether it passes over the occluder where it
+/// crosses it.
+///
+/// Adds the number of occluder segments that were tested to `segments_tested`.
+fn segment_intersects_occluder(
+    segment: &Segment,
+    occluder: &Occluder,
+    segments_tested: &mut usize,
+) -> bool {
+    occluder
+        .iter_segments_from(segment.0)
+        .any(|occluder_segment| {
+            *segments_tested += 1;
+            segment_intersection(segment, &occluder_segment)
+                .is_some_and(|(s, _)| occluder.blocks_at_height(segment.0.lerp(segment.1, s).z))
+        })
+}
+
+/// Like `segment_intersection`, but `s` is unbounded above: the intersection point is
+/// `a.origin + s * a.direction`.
+pub fn ray_intersection(a: &Ray, b: &Segment) -> Option<(f32, f32)> {
+    let s_numerator =
+        (b.1.y - b.0.y) * (a.origin.x - b.0.x) - (b.1.x - b.0.x) * (a.origin.y - b.0.y);
+    let s_denominator = a.direction.y * (b.1.x - b.0.x) - a.direction.x * (b.1.y - b.0.y);
+
+    if s_denominator == 0.0 {
+        return None;
     }
 
-    let left = Segment(
-        occluder.top_left,
-        Vec3 {
-            y: occluder.bottom_right.y,
-            ..occluder.top_left
-        },
-    );
-    if segment_intersects_segment(segment, &left) {
-        return true;
+    let t_numerator = a.direction.y * (b.0.x - a.origin.x) + a.direction.x * (a.origin.y - b.0.y);
+    let t_denominator = a.direction.x * (b.1.y - b.0.y) - a.direction.y * (b.1.x - b.0.x);
+
+    if t_denominator == 0.0 {
+        return None;
     }
 
-    let bottom = Segment(
-        Vec3 {
-            x: occluder.top_left.x,
-            ..occluder.bottom_right
-        },
-        occluder.bottom_right,
+    let s = s_numerator / s_denominator;
+    let t = t_numerator / t_denominator;
+
+    if s >= 0.0 && (0.0..=1.0).contains(&t) {
+        Some((s, t))
+    } else {
+        None
+    }
+}
+
+pub fn ray_intersects_segment(a: &Ray, b: &Segment) -> bool {
+    ray_intersection(a, b).is_some()
+}
+
+/// An intersection between a ray and an [`Occluder`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub occluder: Entity,
+    pub edge: OccluderEdge,
+    pub point: Vec3,
+    /// The distance from the ray's origin to `point`.
+    pub distance: f32,
+    /// The unit normal of the hit edge, on the side facing the ray's origin.
+    pub normal: Vec3,
+}
+
+/// Every intersection between `ray` and the `occluders` within `max_distance` of the ray's origin,
+/// nearest first. One-way edges are only hit from the side that they block, and occluders aren't
+/// hit where the ray passes over them.
+pub fn raycast_all<'a>(
+    ray: &Ray,
+    max_distance: f32,
+    occluders: impl IntoIterator<Item = (Entity, &'a Occluder)>,
+) -> Vec<RaycastHit> {
+    raycast_all_counted(ray, max_distance, occluders, &mut 0)
+}
+
+/// Like [`raycast_all`], but adds the number of occluder segments that were tested to
+/// `segments_tested`.
+fn raycast_all_counted<'a>(
+    ray: &Ray,
+    max_distance: f32,
+    occluders: impl IntoIterator<Item = (Entity, &'a Occluder)>,
+    segments_tested: &mut usize,
+) -> Vec<RaycastHit> {
+    let direction_length = ray.direction.length();
+
+    let mut hits: Vec<RaycastHit> = occluders
+        .into_iter()
+        .flat_map(|(entity, occluder)| {
+            occluder
+                .iter_edges()
+                .filter(move |(edge, segment)| {
+                    occluder.edge_blocks_from(*edge, segment, ray.origin)
+                })
+                .map(move |(edge, segment)| (entity, occluder, edge, segment))
+        })
+        .filter_map(|(entity, occluder, edge, segment)| {
+            *segments_tested += 1;
+            let (s, _) = ray_intersection(ray, &segment)?;
+
+            let distance = s * direction_length;
+            if distance > max_distance || !occluder.blocks_at_height(ray.get_point(s).z) {
+                return None;
+            }
+
+            let edge_direction = segment.1 - segment.0;
+            let normal = Vec3::new(-edge_direction.y, edge_direction.x, 0.0).normalize();
+
+            Some(RaycastHit {
+                occluder: entity,
+                edge,
+                point: ray.get_point(s),
+                distance,
+                normal: if normal.dot(ray.direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                },
+            })
+        })
+        .collect();
+
+    hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    hits
+}
+
+/// The nearest intersection between `ray` and the `occluders` within `max_distance` of the ray's
+/// origin.
+pub fn raycast<'a>(
+    ray: &Ray,
+    max_distance: f32,
+    occluders: impl IntoIterator<Item = (Entity, &'a Occluder)>,
+) -> Option<RaycastHit> {
+    raycast_all(ray, max_distance, occluders).into_iter().next()
+}
+
+#[test]
+fn raycast_test_1() {
+    let near = Entity::from_raw(0);
+    let near_occluder = Occluder {
+        top_left: Vec3::new(2.0, 1.0, 0.0),
+        bottom_right: Vec3::new(3.0, -1.0, 0.0),
+        ..default()
+    };
+
+    let far = Entity::from_raw(1);
+    let far_occluder = Occluder {
+        top_left: Vec3::new(5.0, 1.0, 0.0),
+        bottom_right: Vec3::new(6.0, -1.0, 0.0),
+        ..default()
+    };
+
+    let ray = Ray {
+        origin: Vec3::ZERO,
+        direction: 2.0 * Vec3::X,
+    };
+    let occluders = [(far, &far_occluder), (near, &near_occluder)];
+
+    let hit = raycast(&ray, f32::INFINITY, occluders).unwrap();
+    assert_eq!(hit.occluder, near);
+    assert_eq!(hit.edge, OccluderEdge::Left);
+    assert_eq!(hit.point, 2.0 * Vec3::X);
+    assert_eq!(hit.distance, 2.0);
+    assert_eq!(hit.normal, -Vec3::X);
+
+    let hits = raycast_all(&ray, f32::INFINITY, occluders);
+    assert_eq!(
+        hits.iter()
+            .map(|hit| (hit.occluder, hit.edge, hit.distance))
+            .collect::<Vec<_>>(),
+        vec![
+            (near, OccluderEdge::Left, 2.0),
+            (near, OccluderEdge::Right, 3.0),
+            (far, OccluderEdge::Left, 5.0),
+            (far, OccluderEdge::Right, 6.0),
+        ]
     );
-    if segment_intersects_segment(segment, &bottom) {
-        return true;
+
+    assert_eq!(raycast_all(&ray, 4.0, occluders).len(), 2);
+    assert!(raycast(&ray, 1.0, occluders).is_none());
+}
+
+/// The region visible from `origin` within `bounds`, given the occluding `segments`.
+///
+/// The region is returned as a polygon whose vertices are in anticlockwise order around `origin`.
+/// A ray is cast towards each segment endpoint, and to either side of it, so that the polygon
+/// follows the edges of the occluders and extends past their corners.
+pub fn visibility_polygon(origin: Vec3, segments: &[Segment], bounds: Rect) -> Vec<Vec3> {
+    const EPSILON: f32 = 0.0001;
+
+    let corner = |x: f32, y: f32| Vec3::new(x, y, origin.z);
+    let bounds_segments = [
+        Segment(
+            corner(bounds.min.x, bounds.max.y),
+            corner(bounds.max.x, bounds.max.y),
+        ),
+        Segment(
+            corner(bounds.max.x, bounds.max.y),
+            corner(bounds.max.x, bounds.min.y),
+        ),
+        Segment(
+            corner(bounds.max.x, bounds.min.y),
+            corner(bounds.min.x, bounds.min.y),
+        ),
+        Segment(
+            corner(bounds.min.x, bounds.min.y),
+            corner(bounds.min.x, bounds.max.y),
+        ),
+    ];
+
+    let segments: Vec<Segment> = segments.iter().copied().chain(bounds_segments).collect();
+
+    let mut angles: Vec<f32> = segments
+        .iter()
+        .flat_map(|segment| [segment.0, segment.1])
+        .flat_map(|point| {
+            let angle = (point.y - origin.y).atan2(point.x - origin.x);
+            [angle - EPSILON, angle, angle + EPSILON]
+        })
+        .collect();
+    angles.sort_by(f32::total_cmp);
+    angles.dedup();
+
+    angles
+        .into_iter()
+        .filter_map(|angle| {
+            let ray = Ray {
+                origin,
+                direction: Vec3::new(angle.cos(), angle.sin(), 0.0),
+            };
+
+            segments
+                .iter()
+                .filter_map(|segment| ray_intersection(&ray, segment).map(|(s, _)| s))
+                .min_by(f32::total_cmp)
+                .map(|s| ray.get_point(s))
+        })
+        .collect()
+}
+
+/// Whether `point` is inside the polygon with the given `vertices`, using the even-odd rule.
+pub fn polygon_contains_point(vertices: &[Vec3], point: &Vec3) -> bool {
+    let mut inside = false;
+
+    for (index, a) in vertices.iter().enumerate() {
+        let b = &vertices[(index + 1) % vertices.len()];
+
+        if (a.y > point.y) != (b.y > point.y) {
+            let crossing_x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < crossing_x {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+#[test]
+fn visibility_polygon_test_1() {
+    let bounds = Rect::new(-10.0, -10.0, 10.0, 10.0);
+
+    let occluder = Occluder {
+        top_left: Vec3::new(2.0, 1.0, 0.0),
+        bottom_right: Vec3::new(3.0, -1.0, 0.0),
+        ..default()
+    };
+    let segments: Vec<Segment> = occluder.iter_segments().collect();
+
+    let polygon = visibility_polygon(Vec3::ZERO, &segments, bounds);
+
+    assert!(polygon_contains_point(&polygon, &Vec3::new(1.0, 0.0, 0.0)));
+    assert!(polygon_contains_point(&polygon, &Vec3::new(-9.0, 9.0, 0.0)));
+    assert!(polygon_contains_point(&polygon, &Vec3::new(9.0, 9.0, 0.0)));
+    assert!(!polygon_contains_point(&polygon, &Vec3::new(5.0, 0.0, 0.0)));
+    assert!(!polygon_contains_point(&polygon, &Vec3::new(9.0, 2.0, 0.0)));
+    assert!(!polygon_contains_point(
+        &polygon,
+        &Vec3::new(11.0, 0.0, 0.0)
+    ));
+}
+
+/// The region that a viewer at `origin`, facing `facing`, can see within `bounds`: the
+/// [`visibility_polygon`] cut to the viewer's range and field of view.
+///
+/// The vertices are in anticlockwise order around `origin`, and when the field of view is limited
+/// the polygon starts at `origin`, the point of its cone.
+pub fn view_polygon(
+    origin: Vec3,
+    facing: Vec3,
+    sighted: &Sighted,
+    segments: &[Segment],
+    bounds: Rect,
+) -> Vec<Vec3> {
+    use std::f32::consts::{PI, TAU};
+
+    // Enough steps across the field of view for the edge of the range to look round.
+    const ARC_STEPS: usize = 64;
+
+    let polygon = visibility_polygon(origin, segments, bounds);
+    if sighted.range.is_none() && sighted.field_of_view.is_none() {
+        return polygon;
     }
 
-    let right = Segment(
-        Vec3 {
-            y: occluder.top_left.y,
-            ..occluder.bottom_right
+    let facing_angle = facing.y.atan2(facing.x);
+    let half_width = sighted
+        .field_of_view
+        .map_or(PI, |field_of_view| field_of_view / 2.0);
+    // Angles relative to `facing`, from -PI up to PI.
+    let relative_angle = |point: &Vec3| {
+        ((point.y - origin.y).atan2(point.x - origin.x) - facing_angle + PI).rem_euclid(TAU) - PI
+    };
+    // Without a field of view, the last step would be the same as the first.
+    let arc_steps = ARC_STEPS + usize::from(sighted.field_of_view.is_some());
+
+    let mut angles: Vec<f32> = polygon
+        .iter()
+        .map(relative_angle)
+        .chain(
+            (0..arc_steps)
+                .map(|step| -half_width + 2.0 * half_width * step as f32 / ARC_STEPS as f32),
+        )
+        .filter(|angle| angle.abs() <= half_width)
+        .collect();
+    angles.sort_by(f32::total_cmp);
+    angles.dedup();
+
+    let edges: Vec<Segment> = polygon
+        .iter()
+        .enumerate()
+        .map(|(index, a)| Segment(*a, polygon[(index + 1) % polygon.len()]))
+        .collect();
+
+    let rim = angles.into_iter().filter_map(|angle| {
+        let angle = angle + facing_angle;
+        let ray = Ray {
+            origin,
+            direction: Vec3::new(angle.cos(), angle.sin(), 0.0),
+        };
+
+        edges
+            .iter()
+            .filter_map(|edge| ray_intersection(&ray, edge).map(|(s, _)| s))
+            .min_by(f32::total_cmp)
+            .map(|s| ray.get_point(sighted.range.map_or(s, |range| s.min(range))))
+    });
+
+    match sighted.field_of_view {
+        Some(_) => std::iter::once(origin).chain(rim).collect(),
+        None => rim.collect(),
+    }
+}
+
+#[test]
+fn view_polygon_test_1() {
+    let bounds = Rect::new(-100.0, -100.0, 100.0, 100.0);
+    let occluder = Occluder {
+        top_left: Vec3::new(20.0, 10.0, 0.0),
+        bottom_right: Vec3::new(30.0, 0.0, 0.0),
+        ..default()
+    };
+    let segments: Vec<Segment> = occluder.iter_segments().collect();
+    let contains =
+        |polygon: &[Vec3], x: f32, y: f32| polygon_contains_point(polygon, &Vec3::new(x, y, 0.0));
+
+    // Without a range or field of view, the whole visibility polygon can be seen.
+    let polygon = view_polygon(Vec3::ZERO, Vec3::X, &Sighted::default(), &segments, bounds);
+    assert_eq!(polygon, visibility_polygon(Vec3::ZERO, &segments, bounds));
+
+    let sighted = Sighted {
+        range: Some(50.0),
+        ..default()
+    };
+    let polygon = view_polygon(Vec3::ZERO, Vec3::X, &sighted, &segments, bounds);
+    assert!(contains(&polygon, 40.0, -10.0));
+    assert!(contains(&polygon, -40.0, 20.0));
+    assert!(!contains(&polygon, 40.0, 5.0));
+    assert!(!contains(&polygon, 60.0, -10.0));
+    assert!(!contains(&polygon, -40.0, 40.0));
+
+    // Facing up, with a quarter turn field of view.
+    let sighted = Sighted {
+        field_of_view: Some(std::f32::consts::FRAC_PI_2),
+        ..sighted
+    };
+    let polygon = view_polygon(Vec3::ZERO, Vec3::Y, &sighted, &segments, bounds);
+    assert_eq!(polygon[0], Vec3::ZERO);
+    assert!(contains(&polygon, 0.0, 40.0));
+    assert!(contains(&polygon, 20.0, 30.0));
+    assert!(!contains(&polygon, 0.0, 60.0));
+    assert!(!contains(&polygon, 30.0, 20.0));
+    assert!(!contains(&polygon, 0.0, -20.0));
+}
+
+/// Casts rays against every [`Occluder`] in the world.
+#[derive(SystemParam)]
+pub struct Raycast<'w, 's> {
+    occluders: Query<'w, 's, (Entity, &'static Occluder)>,
+}
+
+impl<'w, 's> Raycast<'w, 's> {
+    /// See [`raycast`].
+    pub fn first_hit(&self, ray: &Ray, max_distance: f32) -> Option<RaycastHit> {
+        raycast(ray, max_distance, self.occluders.iter())
+    }
+
+    /// See [`raycast_all`].
+    pub fn all_hits(&self, ray: &Ray, max_distance: f32) -> Vec<RaycastHit> {
+        raycast_all(ray, max_distance, self.occluders.iter())
+    }
+}
+
+/// The mean fraction of each sight line from `eye_points` to `target_points` that gets through
+/// the `occluders`.
+///
+/// A sight line that crosses an opaque occluder is blocked, and one that crosses semi-transparent
+/// occluders is dimmed by each of them in turn, so that with only opaque occluders this is the
+/// fraction of sight lines that are unobstructed.
+pub fn unobstructed_fraction(
+    eye_points: &[Vec3],
+    target_points: &[Vec3],
+    occluders: &[&Occluder],
+) -> f32 {
+    unobstructed_fraction_counted(eye_points, target_points, occluders, &mut 0)
+}
+
+/// Like [`unobstructed_fraction`], but adds the number of occluder segments that were tested to
+/// `segments_tested`.
+fn unobstructed_fraction_counted(
+    eye_points: &[Vec3],
+    target_points: &[Vec3],
+    occluders: &[&Occluder],
+    segments_tested: &mut usize,
+) -> f32 {
+    let line_count = eye_points.len() * target_points.len();
+    if line_count == 0 {
+        return 0.0;
+    }
+
+    let transmitted: f32 = eye_points
+        .iter()
+        .flat_map(|eye_point| {
+            target_points
+                .iter()
+                .map(|target_point| Segment(*eye_point, *target_point))
+        })
+        .map(|line_of_sight| {
+            let mut transmittance = 1.0;
+            for occluder in occluders {
+                if segment_intersects_occluder(&line_of_sight, occluder, segments_tested) {
+                    transmittance *= occluder.transmittance();
+                    if transmittance == 0.0 {
+                        break;
+                    }
+                }
+            }
+            transmittance
+        })
+        .sum();
+
+    transmitted / line_count as f32
+}
+
+#[test]
+fn unobstructed_fraction_test_1() {
+    // a post that only blocks the line to the target's centre
+    let post = Occluder {
+        top_left: Vec3::new(4.9, 0.1, 0.0),
+        bottom_right: Vec3::new(5.1, -0.1, 0.0),
+        ..default()
+    };
+
+    let eye_points = [Vec3::ZERO];
+    let target_points = [
+        10.0 * Vec3::X,
+        10.0 * Vec3::X + Vec3::Y,
+        10.0 * Vec3::X - Vec3::Y,
+        11.0 * Vec3::X + Vec3::Y,
+    ];
+
+    assert_eq!(
+        unobstructed_fraction(&eye_points, &target_points[..1], &[&post]),
+        0.0
+    );
+    assert_eq!(
+        unobstructed_fraction(&eye_points, &target_points, &[&post]),
+        0.75
+    );
+    assert_eq!(unobstructed_fraction(&eye_points, &target_points, &[]), 1.0);
+}
+
+#[test]
+fn unobstructed_fraction_test_2() {
+    // two panes of frosted glass, each stopping half of what passes through
+    let pane = |x: f32| Occluder {
+        top_left: Vec3::new(x, 1.0, 0.0),
+        bottom_right: Vec3::new(x + 0.1, -1.0, 0.0),
+        opacity: 0.5,
+        ..default()
+    };
+    let (near_pane, far_pane) = (pane(2.0), pane(4.0));
+
+    let eye_points = [Vec3::ZERO];
+    assert_eq!(
+        unobstructed_fraction(&eye_points, &[3.0 * Vec3::X], &[&near_pane, &far_pane]),
+        0.5
+    );
+    assert_eq!(
+        unobstructed_fraction(&eye_points, &[10.0 * Vec3::X], &[&near_pane, &far_pane]),
+        0.25
+    );
+    // one of two sight lines passes above the panes
+    assert_eq!(
+        unobstructed_fraction(
+            &eye_points,
+            &[10.0 * Vec3::X, 10.0 * Vec3::X + 10.0 * Vec3::Y],
+            &[&near_pane, &far_pane]
+        ),
+        0.625
+    );
+}
+
+#[test]
+fn unobstructed_fraction_test_3() {
+    // a cliff edge that can be seen down from above, but not up from below
+    let cliff = Occluder {
+        top_left: Vec3::new(-10.0, 1.0, 0.0),
+        bottom_right: Vec3::new(10.0, -1.0, 0.0),
+        facing: EdgeFacings {
+            top: EdgeFacing::Neither,
+            bottom: EdgeFacing::Outside,
+            left: EdgeFacing::Neither,
+            right: EdgeFacing::Neither,
         },
-        occluder.bottom_right,
+        ..default()
+    };
+    let (above, below) = (Vec3::new(0.0, 5.0, 0.0), Vec3::new(1.0, -5.0, 0.0));
+
+    assert_eq!(unobstructed_fraction(&[above], &[below], &[&cliff]), 1.0);
+    assert_eq!(unobstructed_fraction(&[below], &[above], &[&cliff]), 0.0);
+
+    let ray = Ray {
+        origin: below,
+        direction: above - below,
+    };
+    let mut segments_tested = 0;
+    let hits = raycast_all_counted(
+        &ray,
+        f32::INFINITY,
+        [(Entity::PLACEHOLDER, &cliff)],
+        &mut segments_tested,
+    );
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].edge, OccluderEdge::Bottom);
+    // Only the one edge that blocks sight from below is tested.
+    assert_eq!(segments_tested, 1);
+}
+
+#[test]
+fn unobstructed_fraction_test_4() {
+    // a low wall, which can be seen over from a watchtower but not from the ground
+    let wall = Occluder {
+        top_left: Vec3::new(8.0, 5.0, 0.0),
+        bottom_right: Vec3::new(9.0, -5.0, 0.0),
+        height: Some(2.0),
+        ..default()
+    };
+    let target = Vec3::new(10.0, 0.0, 0.0);
+
+    assert_eq!(
+        unobstructed_fraction(&[Vec3::ZERO], &[target], &[&wall]),
+        0.0
+    );
+    assert_eq!(
+        unobstructed_fraction(&[Vec3::new(0.0, 0.0, 30.0)], &[target], &[&wall]),
+        1.0
     );
-    if segment_intersects_segment(segment, &right) {
-        return true;
+    // right behind the wall, the sight line from the watchtower is still below its top
+    assert_eq!(
+        unobstructed_fraction(
+            &[Vec3::new(0.0, 0.0, 30.0)],
+            &[Vec3::new(9.5, 0.0, 0.0)],
+            &[&wall]
+        ),
+        0.0
+    );
+    assert_eq!(
+        unobstructed_fraction(&[Vec3::new(-20.0, 0.0, 3.0)], &[target], &[&wall]),
+        0.0
+    );
+
+    assert_eq!(wall.shadow_reach(0.0), None);
+    assert_eq!(wall.shadow_reach(4.0), Some(1.0));
+    assert_eq!(Occluder::default().shadow_reach(4.0), None);
+}
+
+#[test]
+fn occluder_is_outside_test_1() {
+    let occluder = Occluder {
+        top_left: Vec3::new(-1.0, 1.0, 0.0),
+        bottom_right: Vec3::new(1.0, -1.0, 0.0),
+        ..default()
+    };
+    let point = Vec3::new(-5.0, 0.0, 0.0);
+    let outside: Vec<bool> = occluder
+        .iter_segments()
+        .map(|segment| occluder.is_outside(&segment, point))
+        .collect();
+
+    // Top, bottom, left and right.
+    assert_eq!(outside, [false, false, true, false]);
+}
+
+/// The result of checking whether a viewer can see a viewee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SightCheck {
+    /// How much of the viewee the viewer can see, from 0 to 1, as found by
+    /// [`unobstructed_fraction`].
+    pub fraction: f32,
+    /// Whether `fraction` meets the viewer's [`Sighted::threshold`].
+    pub seen: bool,
+}
+
+/// Why a viewer can or can't see a viewee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SightDiagnosis {
+    Seen {
+        fraction: f32,
+    },
+    /// Seen indirectly, in a mirror or through a portal, when it couldn't be seen directly. `point`
+    /// is where the first sight line that it's seen along bounces off a mirror or goes into a
+    /// portal.
+    Indirect {
+        fraction: f32,
+        point: Vec3,
+    },
+    /// Too many sight lines were obstructed. `occluder` is the first occluder along the first
+    /// obstructed sight line, and `point` is where that sight line hits it, if a ray cast along the
+    /// sight lines finds one.
+    Blocked {
+        fraction: f32,
+        occluder: Option<Entity>,
+        point: Option<Vec3>,
+    },
+    OutsideFieldOfView,
+    OutOfRange,
+    VieweeNotVisible,
+    ViewerNotSighted,
+}
+
+impl SightDiagnosis {
+    pub fn check(&self) -> SightCheck {
+        match self {
+            SightDiagnosis::Seen { fraction } | SightDiagnosis::Indirect { fraction, .. } => {
+                SightCheck {
+                    fraction: *fraction,
+                    seen: true,
+                }
+            }
+            SightDiagnosis::Blocked { fraction, .. } => SightCheck {
+                fraction: *fraction,
+                seen: false,
+            },
+            SightDiagnosis::OutsideFieldOfView
+            | SightDiagnosis::OutOfRange
+            | SightDiagnosis::VieweeNotVisible
+            | SightDiagnosis::ViewerNotSighted => SightCheck {
+                fraction: 0.0,
+                seen: false,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for SightDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SightDiagnosis::Seen { fraction } => {
+                write!(f, "seen ({:.0}% visible)", fraction * 100.0)
+            }
+            SightDiagnosis::Indirect { fraction, point } => write!(
+                f,
+                "seen indirectly at ({}, {}) ({:.0}% visible)",
+                point.x,
+                point.y,
+                fraction * 100.0
+            ),
+            SightDiagnosis::Blocked {
+                fraction,
+                occluder: Some(occluder),
+                point: Some(point),
+            } => write!(
+                f,
+                "blocked by {:?} at ({}, {}) ({:.0}% visible)",
+                occluder,
+                point.x,
+                point.y,
+                fraction * 100.0
+            ),
+            SightDiagnosis::Blocked { fraction, .. } => {
+                write!(f, "blocked ({:.0}% visible)", fraction * 100.0)
+            }
+            SightDiagnosis::OutsideFieldOfView => write!(f, "outside field of view"),
+            SightDiagnosis::OutOfRange => write!(f, "out of range"),
+            SightDiagnosis::VieweeNotVisible => write!(f, "viewee is not Visible"),
+            SightDiagnosis::ViewerNotSighted => write!(f, "viewer is not Sighted"),
+        }
     }
+}
 
-    false
+/// The ways in which a visibility computation can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisibilityError {
+    ViewerNotSighted(Entity),
+    MissingTransform(Entity),
+    /// A ray has no direction.
+    DegenerateRay,
+    /// A shadow was projected from a point outside the view.
+    OutsideView,
 }
 
-pub fn ray_intersects_segment(a: &Ray, b: &Segment) -> bool {
-    let s_numerator =
-        (b.1.y - b.0.y) * (a.origin.x - b.0.x) - (b.1.x - b.0.x) * (a.origin.y - b.0.y);
-    let s_denominator = a.direction.y * (b.1.x - b.0.x) - a.direction.x * (b.1.y - b.0.y);
+impl std::fmt::Display for VisibilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VisibilityError::ViewerNotSighted(entity) => {
+                write!(f, "viewer {:?} is not Sighted", entity)
+            }
+            VisibilityError::MissingTransform(entity) => {
+                write!(f, "{:?} doesn't have a Transform", entity)
+            }
+            VisibilityError::DegenerateRay => write!(f, "ray has no direction"),
+            VisibilityError::OutsideView => write!(f, "point is outside the view"),
+        }
+    }
+}
 
-    if s_denominator == 0.0 {
-        return false;
+impl std::error::Error for VisibilityError {}
+
+/// How much sight checking was done this frame.
+///
+/// [`CheckVisibility`] only has shared access to this, so that systems that check visibility
+/// can still run in parallel.
+#[derive(Resource, Debug, Default)]
+pub struct SightStats {
+    checks: AtomicUsize,
+    segments_tested: AtomicUsize,
+}
+
+impl SightStats {
+    /// The number of sight checks made between a viewer and a viewee.
+    pub fn checks(&self) -> usize {
+        self.checks.load(Ordering::Relaxed)
     }
 
-    let t_numerator = a.direction.y * (b.0.x - a.origin.x) + a.direction.x * (a.origin.y - b.0.y);
-    let t_denominator = a.direction.x * (b.1.y - b.0.y) - a.direction.y * (b.1.x - b.0.x);
+    /// The number of occluder segments tested against sight lines.
+    pub fn segments_tested(&self) -> usize {
+        self.segments_tested.load(Ordering::Relaxed)
+    }
 
-    if t_denominator == 0.0 {
-        return false;
+    fn record(&self, segments_tested: usize) {
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        self.segments_tested
+            .fetch_add(segments_tested, Ordering::Relaxed);
     }
+}
 
-    let s = s_numerator / s_denominator;
-    let t = t_numerator / t_denominator;
+#[test]
+fn explain_test_1() {
+    use bevy::ecs::system::SystemState;
+
+    let mut world = World::new();
+    world.init_resource::<SightStats>();
+    world.init_resource::<SightConfig>();
+    let wall = world
+        .spawn(Occluder {
+            top_left: Vec3::new(20.0, 10.0, 0.0),
+            bottom_right: Vec3::new(25.0, -10.0, 0.0),
+            ..default()
+        })
+        .id();
+    world.spawn(Occluder {
+        top_left: Vec3::new(0.0, 55.0, 0.0),
+        bottom_right: Vec3::new(60.0, 50.0, 0.0),
+        mirror: true,
+        ..default()
+    });
+
+    // A guard facing along the X axis with a narrow field of view, which can't see into the
+    // mirror, and a watcher that can see all around.
+    let guard = world
+        .spawn((
+            Transform::default(),
+            Sighted {
+                range: Some(100.0),
+                field_of_view: Some(std::f32::consts::FRAC_PI_2),
+                ..default()
+            },
+        ))
+        .id();
+    let watcher = world.spawn((Transform::default(), Sighted::default())).id();
+    let unsighted = world.spawn(Transform::default()).id();
+    let viewee = |world: &mut World, x: f32, y: f32| {
+        world.spawn((Transform::from_xyz(x, y, 0.0), Visible)).id()
+    };
+    let behind = viewee(&mut world, -30.0, 0.0);
+    let far = viewee(&mut world, 150.0, 40.0);
+    let hidden = viewee(&mut world, 40.0, 0.0);
+    let invisible = world.spawn(Transform::from_xyz(10.0, 0.0, 0.0)).id();
 
-    s >= 0.0 && (0.0..=1.0).contains(&t)
+    let mut system_state: SystemState<CheckVisibility> = SystemState::new(&mut world);
+    let check_visibility = system_state.get(&world);
+    let explain = |viewer, viewee| check_visibility.explain(viewer, viewee).unwrap();
+
+    assert_eq!(explain(guard, behind), SightDiagnosis::OutsideFieldOfView);
+    assert_eq!(explain(guard, far), SightDiagnosis::OutOfRange);
+    assert_eq!(explain(guard, invisible), SightDiagnosis::VieweeNotVisible);
+    assert_eq!(explain(unsighted, hidden), SightDiagnosis::ViewerNotSighted);
+    assert_eq!(
+        explain(guard, hidden),
+        SightDiagnosis::Blocked {
+            fraction: 0.0,
+            occluder: Some(wall),
+            point: Some(Vec3::new(20.0, 0.0, 0.0)),
+        }
+    );
+    assert_eq!(
+        explain(watcher, behind),
+        SightDiagnosis::Seen { fraction: 1.0 }
+    );
+
+    // The watcher sees the hidden viewee in the mirror, where the sight line from it to the
+    // viewee's reflection meets the mirror's bottom edge.
+    match explain(watcher, hidden) {
+        SightDiagnosis::Indirect { fraction, point } => {
+            assert_eq!(fraction, 1.0);
+            assert!(point.abs_diff_eq(Vec3::new(20.0, 50.0, 0.0), 0.1));
+        }
+        diagnosis => panic!("expected to be seen indirectly, got {:?}", diagnosis),
+    }
+}
+
+#[test]
+fn check_visibility_test_1() {
+    use bevy::ecs::system::SystemState;
+
+    use crate::{npc::Npc, player::Player};
+
+    let mut app = crate::headless::app(crate::level::Level::default());
+    app.update();
+    let world = &mut app.world;
+    let npc = world
+        .query_filtered::<Entity, With<Npc>>()
+        .iter(world)
+        .next()
+        .unwrap();
+    let player = world.query_filtered::<Entity, With<Player>>().single(world);
+    let unplaced_viewer = world.spawn(Sighted::default()).id();
+    let unplaced_viewee = world.spawn(Visible).id();
+    let despawned = world.spawn(Sighted::default()).id();
+    world.despawn(despawned);
+
+    // Missing or broken entities are errors rather than panics.
+    let mut system_state: SystemState<CheckVisibility> = SystemState::new(world);
+    let check_visibility = system_state.get(world);
+    assert_eq!(
+        check_visibility.check(unplaced_viewer, player),
+        Err(VisibilityError::MissingTransform(unplaced_viewer))
+    );
+    assert_eq!(
+        check_visibility.check(npc, unplaced_viewee),
+        Err(VisibilityError::MissingTransform(unplaced_viewee))
+    );
+    assert_eq!(
+        check_visibility.sees(despawned, player),
+        Err(VisibilityError::ViewerNotSighted(despawned))
+    );
+    assert_eq!(check_visibility.sees(npc, despawned), Ok(false));
+}
+
+#[test]
+fn sight_stats_test_1() {
+    use bevy::ecs::system::SystemState;
+
+    use crate::{npc::Npc, player::Player};
+
+    let mut app = crate::headless::app(crate::level::Level::default());
+    app.update();
+    let world = &mut app.world;
+    let npc = world
+        .query_filtered::<Entity, With<Npc>>()
+        .iter(world)
+        .next()
+        .unwrap();
+    let player = world.query_filtered::<Entity, With<Player>>().single(world);
+    let stats = |world: &World| {
+        let stats = world.resource::<SightStats>();
+        (stats.checks(), stats.segments_tested())
+    };
+    let before = stats(world);
+
+    // Explaining a sight check is a diagnostic, which isn't counted.
+    let mut system_state: SystemState<CheckVisibility> = SystemState::new(world);
+    let diagnosis = system_state.get(world).explain(npc, player).unwrap();
+    assert!(matches!(diagnosis, SightDiagnosis::Blocked { .. }));
+    assert_eq!(stats(world), before);
+
+    system_state.get(world).check(npc, player).unwrap();
+    let (checks, segments_tested) = stats(world);
+    assert_eq!(checks, before.0 + 1);
+    assert!(segments_tested > before.1);
+}
+
+fn reset_sight_stats(sight_stats: Res<SightStats>) {
+    sight_stats.checks.store(0, Ordering::Relaxed);
+    sight_stats.segments_tested.store(0, Ordering::Relaxed);
 }
 
 #[derive(SystemParam)]
 pub struct CheckVisibility<'w, 's> {
+    config: Res<'w, SightConfig>,
+    stats: Res<'w, SightStats>,
     sighteds: Query<'w, 's, &'static Sighted>,
-    visibles: Query<'w, 's, &'static Visible>,
-    occluders: Query<'w, 's, &'static Occluder>,
+    team_members: Query<'w, 's, (Entity, &'static Team), With<Sighted>>,
+    visibles: Query<'w, 's, Option<&'static VisibilityShape>, With<Visible>>,
+    occluders: Query<'w, 's, (Entity, &'static Occluder)>,
+    portals: Query<'w, 's, &'static Portal>,
     transforms: Query<'w, 's, &'static Transform>,
 }
 
 impl<'w, 's> CheckVisibility<'w, 's> {
-    pub fn sees(&self, viewer: Entity, viewee: Entity) -> bool {
-        assert!(self.sighteds.get(viewer).is_ok(), "viewer is not Sighted");
+    pub fn sees(&self, viewer: Entity, viewee: Entity) -> Result<bool, VisibilityError> {
+        Ok(self.check(viewer, viewee)?.seen)
+    }
 
-        match self.visibles.get(viewee) {
-            Err(_) => false,
-            Ok(_) => {
-                let viewer_transform = self.transforms.get(viewer).unwrap();
-                let viewee_transform = self.transforms.get(viewee).unwrap();
+    /// How much of `viewee` that `viewer` can see, from 0 when it's hidden or out of sight to 1
+    /// when nothing is in the way.
+    pub fn visibility(&self, viewer: Entity, viewee: Entity) -> Result<f32, VisibilityError> {
+        Ok(self.check(viewer, viewee)?.fraction)
+    }
 
-                let line_of_sight =
-                    Segment(viewer_transform.translation, viewee_transform.translation);
+    /// Whether any [`Sighted`] member of `team` sees `viewee`.
+    pub fn team_sees(&self, team: Team, viewee: Entity) -> Result<bool, VisibilityError> {
+        for (member, member_team) in self.team_members.iter() {
+            if *member_team == team && self.sees(member, viewee)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
-                !self
-                    .occluders
-                    .iter()
-                    .any(|occluder| segment_intersects_occluder(&line_of_sight, occluder))
+    pub fn check(&self, viewer: Entity, viewee: Entity) -> Result<SightCheck, VisibilityError> {
+        let mut segments_tested = 0;
+        let diagnosis = self.explain_counted(viewer, viewee, &mut segments_tested)?;
+        self.stats.record(segments_tested);
+        match diagnosis {
+            SightDiagnosis::ViewerNotSighted => Err(VisibilityError::ViewerNotSighted(viewer)),
+            diagnosis => Ok(diagnosis.check()),
+        }
+    }
+
+    /// The nearest point to `position` that's hidden from all of the `viewers`, as found by
+    /// [`crate::cover::nearest_cover`].
+    pub fn nearest_cover(
+        &self,
+        position: Vec3,
+        viewers: &[Entity],
+        bounds: Rect,
+        max_distance: Option<f32>,
+    ) -> Result<Option<Vec3>, VisibilityError> {
+        let viewers = viewers
+            .iter()
+            .map(|viewer| {
+                let sighted = self
+                    .sighteds
+                    .get(*viewer)
+                    .map_err(|_| VisibilityError::ViewerNotSighted(*viewer))?;
+                let transform = self
+                    .transforms
+                    .get(*viewer)
+                    .map_err(|_| VisibilityError::MissingTransform(*viewer))?;
+                Ok((transform, sighted))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let occluders: Vec<Occluder> = self
+            .occluders
+            .iter()
+            .map(|(_, occluder)| occluder.clone())
+            .collect();
+
+        Ok(crate::cover::nearest_cover(
+            position,
+            &viewers,
+            &occluders,
+            bounds,
+            max_distance,
+        ))
+    }
+
+    /// Like [`CheckVisibility::check`], but also reports why the viewee was or wasn't seen.
+    ///
+    /// This is for diagnostics, such as the debug overlay's sight lines, so it isn't counted in
+    /// the [`SightStats`].
+    pub fn explain(
+        &self,
+        viewer: Entity,
+        viewee: Entity,
+    ) -> Result<SightDiagnosis, VisibilityError> {
+        self.explain_counted(viewer, viewee, &mut 0)
+    }
+
+    /// Like [`CheckVisibility::explain`], but adds the number of occluder segments that were
+    /// tested to `segments_tested`.
+    fn explain_counted(
+        &self,
+        viewer: Entity,
+        viewee: Entity,
+        segments_tested: &mut usize,
+    ) -> Result<SightDiagnosis, VisibilityError> {
+        let Ok(sighted) = self.sighteds.get(viewer) else {
+            return Ok(SightDiagnosis::ViewerNotSighted);
+        };
+
+        let Ok(viewee_shape) = self.visibles.get(viewee) else {
+            return Ok(SightDiagnosis::VieweeNotVisible);
+        };
+
+        let viewer_transform = self
+            .transforms
+            .get(viewer)
+            .map_err(|_| VisibilityError::MissingTransform(viewer))?;
+        let viewee_transform = self
+            .transforms
+            .get(viewee)
+            .map_err(|_| VisibilityError::MissingTransform(viewee))?;
+
+        let viewee_shape = viewee_shape.unwrap_or(&VisibilityShape::Point);
+
+        let (eye_points, target_points) = match &sighted.sampling {
+            SightSampling::Centre => (vec![Vec2::ZERO], vec![viewee_shape.centre()]),
+            SightSampling::Bounds { eye } => (eye.sample_points(), viewee_shape.sample_points()),
+        };
+
+        let eye_points: Vec<Vec3> = eye_points
+            .into_iter()
+            .map(|point| viewer_transform.transform_point(point.extend(0.0)))
+            .collect();
+        let target_points: Vec<Vec3> = target_points
+            .into_iter()
+            .map(|point| viewee_transform.transform_point(point.extend(0.0)))
+            .collect();
+        let blocking_occluders: Vec<(Entity, &Occluder)> = self
+            .occluders
+            .iter()
+            .filter(|(_, occluder)| occluder.blocks(sighted.blocked_by))
+            .collect();
+        let occluders: Vec<&Occluder> = blocking_occluders
+            .iter()
+            .map(|(_, occluder)| *occluder)
+            .collect();
+
+        // The viewee might be seen in a mirror or through a portal when it can't be seen directly.
+        let indirect = || {
+            let portals: Vec<&Portal> = self.portals.iter().collect();
+            let views = indirect_views(
+                viewer_transform.translation,
+                &occluders,
+                &portals,
+                self.config.max_reflections,
+                self.config.max_portals,
+            );
+            match indirect_fraction(
+                sighted,
+                viewer_transform,
+                &eye_points,
+                &target_points,
+                &occluders,
+                &views,
+            ) {
+                (fraction, Some(point)) if fraction >= sighted.threshold => {
+                    Some(SightDiagnosis::Indirect { fraction, point })
+                }
+                _ => None,
             }
+        };
+
+        let offset = viewee_transform.transform_point(viewee_shape.centre().extend(0.0))
+            - viewer_transform.translation;
+        if !sighted.in_range(offset) {
+            return Ok(indirect().unwrap_or(SightDiagnosis::OutOfRange));
+        }
+        if !sighted.in_field_of_view(viewer_transform.rotation * Vec3::X, offset) {
+            return Ok(indirect().unwrap_or(SightDiagnosis::OutsideFieldOfView));
+        }
+
+        let fraction =
+            unobstructed_fraction_counted(&eye_points, &target_points, &occluders, segments_tested);
+
+        if fraction >= sighted.threshold {
+            return Ok(SightDiagnosis::Seen { fraction });
         }
+        if let Some(diagnosis) = indirect() {
+            return Ok(diagnosis);
+        }
+
+        // Whether the viewee is seen only depends on the fraction; the ray cast only finds what's
+        // in the way, which it might miss when a sight line only grazes an occluder.
+        let hit = eye_points
+            .iter()
+            .flat_map(|eye_point| {
+                target_points
+                    .iter()
+                    .map(move |target_point| (eye_point, target_point))
+            })
+            .find_map(|(eye_point, target_point)| {
+                let ray = Ray {
+                    origin: *eye_point,
+                    direction: *target_point - *eye_point,
+                };
+                raycast_all_counted(
+                    &ray,
+                    ray.direction.length(),
+                    blocking_occluders.iter().copied(),
+                    segments_tested,
+                )
+                .into_iter()
+                .next()
+            });
+
+        Ok(SightDiagnosis::Blocked {
+            fraction,
+            occluder: hit.map(|hit| hit.occluder),
+            point: hit.map(|hit| hit.point),
+        })
     }
 }
 
 #[derive(Component)]
 struct DisplayOccluder;
 
-fn display_occluders(mut commands: Commands, query: Query<(Entity, &Occluder)>) {
-    for (entity, occluder) in query.iter() {
-        commands.entity(entity).with_children(|parent| {
-            let width = occluder.bottom_right.x - occluder.top_left.x;
-            let height = occluder.top_left.y - occluder.bottom_right.y;
+/// Marks an occluder whose outline is being displayed.
+#[derive(Component)]
+struct OccluderDisplayed;
 
-            let color = Color::ORANGE;
-            let thickness = 2.0;
+/// Adds handles to the corners of an occluder's displayed outline, such as to show that it's
+/// selected.
+#[derive(Component)]
+pub struct OccluderHandles;
 
-            parent.spawn((
-                DisplayOccluder,
-                SpriteBundle {
-                    sprite: Sprite {
-                        color,
-                        custom_size: Some(Vec2 {
-                            x: thickness,
-                            y: height + 2.0 * thickness,
-                        }),
-                        ..default()
-                    },
-                    transform: Transform::from_xyz(-width / 2.0 - thickness / 2.0, 0.0, 0.0),
-                    ..default()
-                },
-            ));
+fn spawn_occluder_display(parent: &mut ChildBuilder, occluder: &Occluder, handles: bool) {
+    let width = occluder.bottom_right.x - occluder.top_left.x;
+    let height = occluder.top_left.y - occluder.bottom_right.y;
 
-            parent.spawn((
-                DisplayOccluder,
-                SpriteBundle {
-                    sprite: Sprite {
-                        color,
-                        custom_size: Some(Vec2 {
-                            x: thickness,
-                            y: height + 2.0 * thickness,
-                        }),
-                        ..default()
-                    },
-                    transform: Transform::from_xyz(width / 2.0 + thickness / 2.0, 0.0, 0.0),
-                    ..default()
-                },
-            ));
+    let color = Color::ORANGE;
+    let thickness = 2.0;
 
-            parent.spawn((
-                DisplayOccluder,
-                SpriteBundle {
-                    sprite: Sprite {
-                        color,
-                        custom_size: Some(Vec2 {
-                            x: width + 2.0 * thickness,
-                            y: thickness,
-                        }),
-                        ..default()
-                    },
-                    transform: Transform::from_xyz(0.0, -height / 2.0 - thickness / 2.0, 0.0),
-                    ..default()
-                },
-            ));
+    parent.spawn((
+        DisplayOccluder,
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2 {
+                    x: thickness,
+                    y: height + 2.0 * thickness,
+                }),
+                ..default()
+            },
+            transform: Transform::from_xyz(-width / 2.0 - thickness / 2.0, 0.0, 0.0),
+            ..default()
+        },
+    ));
+
+    parent.spawn((
+        DisplayOccluder,
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2 {
+                    x: thickness,
+                    y: height + 2.0 * thickness,
+                }),
+                ..default()
+            },
+            transform: Transform::from_xyz(width / 2.0 + thickness / 2.0, 0.0, 0.0),
+            ..default()
+        },
+    ));
+
+    parent.spawn((
+        DisplayOccluder,
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2 {
+                    x: width + 2.0 * thickness,
+                    y: thickness,
+                }),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, -height / 2.0 - thickness / 2.0, 0.0),
+            ..default()
+        },
+    ));
 
+    parent.spawn((
+        DisplayOccluder,
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2 {
+                    x: width + 2.0 * thickness,
+                    y: thickness,
+                }),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, height / 2.0 + thickness / 2.0, 0.0),
+            ..default()
+        },
+    ));
+
+    if handles {
+        let handle_size = 4.0 * thickness;
+        for (x, y) in [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)] {
             parent.spawn((
                 DisplayOccluder,
                 SpriteBundle {
                     sprite: Sprite {
-                        color,
-                        custom_size: Some(Vec2 {
-                            x: width + 2.0 * thickness,
-                            y: thickness,
-                        }),
+                        color: Color::YELLOW,
+                        custom_size: Some(Vec2::splat(handle_size)),
                         ..default()
                     },
-                    transform: Transform::from_xyz(0.0, height / 2.0 + thickness / 2.0, 0.0),
+                    transform: Transform::from_xyz(
+                        x * (width / 2.0 + thickness / 2.0),
+                        y * (height / 2.0 + thickness / 2.0),
+                        0.1,
+                    ),
                     ..default()
                 },
             ));
-        });
+        }
+    }
+}
+
+/// Displays the outlines of occluders that aren't displayed yet, and redisplays the outlines of
+/// occluders that have changed.
+#[allow(clippy::type_complexity)]
+fn display_occluders(
+    mut commands: Commands,
+    query: Query<(
+        Entity,
+        &Occluder,
+        Option<&Children>,
+        Option<&OccluderHandles>,
+        Option<&OccluderDisplayed>,
+    )>,
+    changed: Query<
+        (),
+        Or<(
+            Without<OccluderDisplayed>,
+            Changed<Occluder>,
+            Changed<OccluderHandles>,
+        )>,
+    >,
+    mut removed_handles: RemovedComponents<OccluderHandles>,
+    displays: Query<(), With<DisplayOccluder>>,
+) {
+    let removed_handles: Vec<Entity> = removed_handles.iter().collect();
+
+    for (entity, occluder, children, handles, displayed) in query.iter() {
+        if !changed.contains(entity) && !removed_handles.contains(&entity) {
+            continue;
+        }
+
+        if displayed.is_some() {
+            for child in children.into_iter().flatten() {
+                if displays.contains(*child) {
+                    let mut child_commands = commands.entity(*child);
+                    child_commands.remove_parent();
+                    child_commands.despawn_recursive();
+                }
+            }
+        }
+
+        commands
+            .entity(entity)
+            .insert(OccluderDisplayed)
+            .with_children(|parent| spawn_occluder_display(parent, occluder, handles.is_some()));
     }
 }
 
-fn hide_occluders(mut commands: Commands, query: Query<Entity, With<DisplayOccluder>>) {
+fn hide_occluders(
+    mut commands: Commands,
+    query: Query<Entity, With<DisplayOccluder>>,
+    displayed: Query<Entity, With<OccluderDisplayed>>,
+) {
     for entity in query.iter() {
         let mut entity_commands = commands.entity(entity);
         entity_commands.remove_parent();
         entity_commands.despawn_recursive();
     }
+
+    for entity in displayed.iter() {
+        commands.entity(entity).remove::<OccluderDisplayed>();
+    }
 }
 
-#[derive(Default, Resource)]
+#[derive(Resource, Debug, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
 pub struct SightConfig {
     pub display_occluders: bool,
+    /// How many times sight and light can bounce off mirrors, one after the other.
+    pub max_reflections: u32,
+    /// How many portals sight and light can pass through, one after the other.
+    pub max_portals: u32,
+}
+
+impl Default for SightConfig {
+    fn default() -> Self {
+        Self {
+            display_occluders: false,
+            max_reflections: 2,
+            max_portals: 2,
+        }
+    }
 }
 
 pub struct SightPlugin;
 
 impl Plugin for SightPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<SightConfig>();
+        app.init_resource::<SightConfig>()
+            .init_resource::<SightStats>()
+            .register_type::<Sighted>()
+            .register_type::<SightSampling>()
+            .register_type::<Team>()
+            .register_type::<Visible>()
+            .register_type::<VisibilityShape>()
+            .register_type::<DerivedVisibilityShape>()
+            .register_type::<Occluder>()
+            .register_type::<OcclusionLayers>()
+            .register_type::<EdgeFacings>()
+            .register_type::<EdgeFacing>()
+            .register_type::<Portal>()
+            // The tint of `Occluder`, which is otherwise registered by rendering.
+            .register_type::<Color>()
+            .register_type::<SightConfig>()
+            // The range and field of view of `Sighted`.
+            .register_type::<Option<f32>>();
+
+        app.add_system(reset_sight_stats.in_base_set(CoreSet::First));
+
+        app.add_system(derive_visibility_shapes.in_base_set(CoreSet::PreUpdate));
 
+        // Runs every frame while enabled, so that occluders spawned later are displayed too.
         app.add_system(
             display_occluders.run_if(
-                resource_changed::<SightConfig>()
+                resource_exists::<SightConfig>()
                     .and_then(|sight_config: Res<SightConfig>| sight_config.display_occluders),
             ),
         )
@@ -0,0 +1,467 @@
+use std::f32::consts::{FRAC_PI_3, TAU};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::MaterialMesh2dBundle,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    light::{ColorMaterials, LightSet},
+    sight::{polygon_contains_point, raycast, Occluder, OcclusionLayers},
+    sun::update_sun_shadows,
+    viewport::BACKGROUND_Z,
+};
+
+/// Above the sun shadows, which it lights up, and below the shadows of each player's view, so
+/// that a player only sees the light where they can see the ground.
+const SPOTLIGHT_BEAM_Z: f32 = BACKGROUND_Z + 0.0075;
+
+/// The most that neighbouring rays of a beam are apart, so that its rounded end and its soft
+/// edges are smooth.
+const MAX_RAY_SPACING: f32 = TAU / 180.0;
+
+/// How far to either side of an occluder's corner the rays that pass it are cast.
+const CORNER_EPSILON: f32 = 0.0001;
+
+/// A cone of light, such as a flashlight or a search light, that lights up the ground in front of
+/// its entity and turns as the entity does.
+///
+/// A player or an NPC carries a spotlight by having one as a component. The beam is stopped by
+/// opaque occluders that block light, like shadows are, and passes over occluders lower than the
+/// entity.
+#[derive(Component, Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct Spotlight {
+    /// Where the light points, in radians anticlockwise from the way its entity faces.
+    pub direction: f32,
+    /// How wide the cone is, in radians.
+    pub angle: f32,
+    /// How far the light reaches.
+    pub range: f32,
+    /// How much of the cone fades out towards its sides and its end, from 0 for hard edges to 1
+    /// for light that fades all the way from the middle of the beam.
+    pub softness: f32,
+    pub color: Color,
+}
+
+impl Default for Spotlight {
+    fn default() -> Self {
+        Self {
+            direction: 0.0,
+            angle: FRAC_PI_3,
+            range: 150.0,
+            softness: 0.25,
+            color: Color::rgba(1.0, 1.0, 0.8, 0.5),
+        }
+    }
+}
+
+impl Spotlight {
+    /// The way the light points when its entity is transformed by `transform`.
+    pub fn aim(&self, transform: &Transform) -> Vec2 {
+        let facing = (transform.rotation * Vec3::X)
+            .truncate()
+            .normalize_or_zero();
+        Vec2::from_angle(self.direction).rotate(facing)
+    }
+
+    /// How brightly the light shines `angle` radians away from where it points, from 1 in the
+    /// middle of the beam to 0 outside it.
+    fn brightness_across(&self, angle: f32) -> f32 {
+        let half_angle = self.angle / 2.0;
+        fade(half_angle - angle.abs(), half_angle * self.softness)
+    }
+
+    /// How brightly the light shines `distance` away from it, from 1 near it to 0 past its range.
+    fn brightness_along(&self, distance: f32) -> f32 {
+        fade(self.range - distance, self.range * self.softness)
+    }
+
+    /// How brightly the light shines on `point`, from 0 if it's outside the beam or in an
+    /// occluder's shadow to 1 in the middle of the beam.
+    pub fn illumination<'a>(
+        &self,
+        transform: &Transform,
+        point: Vec3,
+        occluders: impl IntoIterator<Item = (Entity, &'a Occluder)>,
+    ) -> f32 {
+        let origin = transform.translation;
+        let offset = (point - origin).truncate();
+        if offset == Vec2::ZERO {
+            return 1.0;
+        }
+
+        let distance = offset.length();
+        let brightness = self.brightness_across(self.aim(transform).angle_between(offset))
+            * self.brightness_along(distance);
+        if brightness == 0.0 {
+            return 0.0;
+        }
+
+        let ray = Ray {
+            origin,
+            direction: offset.normalize().extend(0.0),
+        };
+        match raycast(&ray, distance, beam_occluders(occluders)) {
+            Some(_) => 0.0,
+            None => brightness,
+        }
+    }
+
+    /// The ground that the light shines on, as seen from above.
+    pub fn beam(&self, transform: &Transform, occluders: &[(Entity, &Occluder)]) -> BeamRegion {
+        let origin = transform.translation;
+        let aim_angle = Vec2::X.angle_between(self.aim(transform));
+        let angle = self.angle.clamp(0.0, TAU);
+        let half_angle = angle / 2.0;
+
+        // Rays evenly across the cone, and to either side of every occluder corner in it so that
+        // the beam follows the occluders' edges.
+        let steps = (angle / MAX_RAY_SPACING).ceil().max(1.0) as usize;
+        let mut angles: Vec<f32> = (0..=steps)
+            .map(|step| -half_angle + angle * step as f32 / steps as f32)
+            .collect();
+        for (_, occluder) in beam_occluders(occluders.iter().copied()) {
+            for segment in occluder.iter_segments() {
+                for corner in [segment.0, segment.1] {
+                    let offset = (corner - origin).truncate();
+                    if offset == Vec2::ZERO || offset.length() > self.range {
+                        continue;
+                    }
+                    let corner_angle = self.aim(transform).angle_between(offset);
+                    angles.extend(
+                        [
+                            corner_angle - CORNER_EPSILON,
+                            corner_angle,
+                            corner_angle + CORNER_EPSILON,
+                        ]
+                        .into_iter()
+                        .filter(|angle| angle.abs() < half_angle),
+                    );
+                }
+            }
+        }
+        angles.sort_by(f32::total_cmp);
+        angles.dedup();
+
+        // The beam is drawn across the ground, whatever the height of the spotlight.
+        let flatten = |point: Vec3| point.truncate().extend(0.0);
+        let rays = angles
+            .into_iter()
+            .map(|angle| {
+                let ray = Ray {
+                    origin,
+                    direction: Vec2::from_angle(aim_angle + angle).extend(0.0),
+                };
+                let distance = raycast(&ray, self.range, beam_occluders(occluders.iter().copied()))
+                    .map_or(self.range, |hit| hit.distance);
+                BeamRay {
+                    end: flatten(ray.get_point(distance)),
+                    brightness: self.brightness_across(angle),
+                    end_brightness: self.brightness_across(angle) * self.brightness_along(distance),
+                }
+            })
+            .collect();
+
+        BeamRegion {
+            origin: flatten(origin),
+            fade_distance: self.range * (1.0 - self.softness.clamp(0.0, 1.0)),
+            rays,
+        }
+    }
+}
+
+/// How much of the light is left `inside` its edge, fading out over `width`.
+fn fade(inside: f32, width: f32) -> f32 {
+    if inside < 0.0 {
+        0.0
+    } else if width > 0.0 {
+        (inside / width).min(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// The occluders that stop a spotlight's beam: opaque ones that block light. Semi-transparent
+/// occluders let the beam through, so that it lights up what's behind them.
+fn beam_occluders<'a>(
+    occluders: impl IntoIterator<Item = (Entity, &'a Occluder)>,
+) -> impl Iterator<Item = (Entity, &'a Occluder)> {
+    occluders
+        .into_iter()
+        .filter(|(_, occluder)| occluder.blocks(OcclusionLayers::LIGHT) && occluder.is_opaque())
+}
+
+/// A ray out of a spotlight, which ends where it's stopped by an occluder or the light runs out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeamRay {
+    pub end: Vec3,
+    /// How brightly the light shines along the ray before it starts to fade.
+    pub brightness: f32,
+    /// How brightly the light shines at the end of the ray.
+    pub end_brightness: f32,
+}
+
+/// The ground that a [`Spotlight`] lights up: a fan of rays from the light, anticlockwise.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BeamRegion {
+    pub origin: Vec3,
+    /// How far from the light the rays start to fade.
+    pub fade_distance: f32,
+    pub rays: Vec<BeamRay>,
+}
+
+impl BeamRegion {
+    /// The outline of the beam: out to the end of each ray, from the light.
+    pub fn outline(&self) -> Vec<Vec3> {
+        std::iter::once(self.origin)
+            .chain(self.rays.iter().map(|ray| ray.end))
+            .collect()
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        !self.rays.is_empty() && polygon_contains_point(&self.outline(), point)
+    }
+
+    /// Where each ray starts to fade, which is its end if it's stopped before then.
+    fn fade_start(&self, ray: &BeamRay) -> Vec3 {
+        let offset = ray.end - self.origin;
+        self.origin + offset.clamp_length_max(self.fade_distance)
+    }
+}
+
+/// The beam is drawn as a fan from the light out to where each ray starts to fade, with a strip
+/// around it out to the ends of the rays. Each vertex's alpha is how brightly the light shines
+/// there, so that the edges of the beam are soft.
+impl From<&BeamRegion> for Mesh {
+    fn from(value: &BeamRegion) -> Self {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut colors: Vec<[f32; 4]> = Vec::new();
+        let mut vertex = |position: Vec3, brightness: f32| {
+            positions.push(position);
+            colors.push([1.0, 1.0, 1.0, brightness]);
+        };
+
+        for pair in value.rays.windows(2) {
+            let [a, b] = [&pair[0], &pair[1]];
+            let (a_fade, b_fade) = (value.fade_start(a), value.fade_start(b));
+
+            vertex(value.origin, 1.0);
+            vertex(a_fade, a.brightness);
+            vertex(b_fade, b.brightness);
+
+            vertex(a_fade, a.brightness);
+            vertex(a.end, a.end_brightness);
+            vertex(b.end, b.end_brightness);
+
+            vertex(a_fade, a.brightness);
+            vertex(b.end, b.end_brightness);
+            vertex(b_fade, b.brightness);
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        let indices = (0..positions.len() as u32).collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+#[test]
+fn spotlight_illumination_test_1() {
+    let spotlight = Spotlight {
+        angle: std::f32::consts::FRAC_PI_2,
+        range: 100.0,
+        softness: 0.5,
+        ..default()
+    };
+    // Facing up.
+    let transform = Transform::from_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+    let lit = |point: Vec3| spotlight.illumination(&transform, point, []);
+
+    assert_eq!(lit(Vec3::new(0.0, 20.0, 0.0)), 1.0);
+    assert_eq!(lit(Vec3::new(0.0, -20.0, 0.0)), 0.0);
+    assert_eq!(lit(Vec3::new(0.0, 120.0, 0.0)), 0.0);
+    // The light fades towards the end of its range, and towards the sides of the cone.
+    assert!((lit(Vec3::new(0.0, 75.0, 0.0)) - 0.5).abs() < 0.001);
+    let side = Vec2::from_angle(std::f32::consts::FRAC_PI_2 - 0.6) * 20.0;
+    let side = lit(side.extend(0.0));
+    assert!(side > 0.0 && side < 1.0);
+
+    // Occluders cast shadows in the beam, unless they're lower than the light.
+    let mut wall = Occluder {
+        top_left: Vec3::new(-10.0, 35.0, 0.0),
+        bottom_right: Vec3::new(10.0, 30.0, 0.0),
+        ..default()
+    };
+    let behind = Vec3::new(0.0, 50.0, 0.0);
+    let wall_entity = Entity::from_raw(0);
+    assert_eq!(
+        spotlight.illumination(&transform, behind, [(wall_entity, &wall)]),
+        0.0
+    );
+    wall.height = Some(5.0);
+    let raised = transform.with_translation(Vec3::new(0.0, 0.0, 10.0));
+    assert_eq!(
+        spotlight.illumination(&raised, behind, [(wall_entity, &wall)]),
+        1.0
+    );
+}
+
+#[test]
+fn spotlight_beam_test_1() {
+    let spotlight = Spotlight {
+        angle: std::f32::consts::FRAC_PI_2,
+        range: 100.0,
+        softness: 0.0,
+        ..default()
+    };
+    let transform = Transform::from_xyz(0.0, 0.0, 0.0);
+    let wall = Occluder {
+        top_left: Vec3::new(30.0, 10.0, 0.0),
+        bottom_right: Vec3::new(35.0, -10.0, 0.0),
+        ..default()
+    };
+    let beam = spotlight.beam(&transform, &[(Entity::from_raw(0), &wall)]);
+
+    // The beam is cut off by the wall, and follows its corners.
+    assert!(beam.contains_point(&Vec3::new(20.0, 0.0, 0.0)));
+    assert!(!beam.contains_point(&Vec3::new(50.0, 0.0, 0.0)));
+    assert!(beam.contains_point(&Vec3::new(50.0, 30.0, 0.0)));
+    assert!(!beam.contains_point(&Vec3::new(-20.0, 0.0, 0.0)));
+    assert!(beam
+        .rays
+        .iter()
+        .any(|ray| ray.end.distance(Vec3::new(30.0, 10.0, 0.0)) < 0.01));
+
+    // Hard edges are uniformly bright.
+    assert!(beam.rays.iter().all(|ray| ray.brightness == 1.0));
+}
+
+/// The ground that a [`Spotlight`] lights up. Beams are rendered in every view.
+#[derive(Component)]
+pub struct SpotlightBeam {
+    pub light: Entity,
+    pub region: BeamRegion,
+}
+
+/// Respawns the beams of spotlights that have moved, turned or changed, or of every spotlight
+/// when the occluders have changed, and despawns the beams of removed spotlights.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_spotlight_beams(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    spotlights: Query<(Entity, &Spotlight, &Transform)>,
+    changed_spotlights: Query<
+        Entity,
+        (
+            With<Spotlight>,
+            Or<(Changed<Spotlight>, Changed<Transform>)>,
+        ),
+    >,
+    occluders: Query<(Entity, &Occluder)>,
+    changed_occluders: Query<(), Changed<Occluder>>,
+    mut removed_occluders: RemovedComponents<Occluder>,
+    beams: Query<(Entity, &SpotlightBeam)>,
+) {
+    let world_changed = !changed_occluders.is_empty() || removed_occluders.iter().next().is_some();
+
+    let mut stale: Vec<Entity> = changed_spotlights.iter().collect();
+    let mut lit = Vec::new();
+    for (entity, spotlight_beam) in beams.iter() {
+        if world_changed
+            || !spotlights.contains(spotlight_beam.light)
+            || stale.contains(&spotlight_beam.light)
+        {
+            commands.entity(entity).despawn();
+            stale.push(spotlight_beam.light);
+        } else {
+            lit.push(spotlight_beam.light);
+        }
+    }
+
+    let occluders: Vec<(Entity, &Occluder)> = occluders.iter().collect();
+    for (light, spotlight, transform) in spotlights.iter() {
+        if lit.contains(&light) {
+            continue;
+        }
+
+        let material = color_materials.get_or_add(spotlight.color, &mut materials);
+        let region = spotlight.beam(transform, &occluders);
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(Mesh::from(&region)).into(),
+                material,
+                transform: Transform::from_xyz(0.0, 0.0, SPOTLIGHT_BEAM_Z),
+                ..default()
+            },
+            SpotlightBeam { light, region },
+        ));
+    }
+}
+
+#[test]
+fn update_spotlight_beams_test_1() {
+    use crate::{level::LevelObjectKind, movement::Direction, player::Player};
+
+    let mut level = crate::level::Level::default();
+    level.npcs[0].flashlight = Some(default());
+    level.players[0].flashlight = Some(default());
+    let mut app = crate::headless::app(level);
+    app.update();
+
+    // The NPC's beam stops at the wall in front of it.
+    let beams = |app: &mut App, kind: LevelObjectKind| -> Vec<BeamRegion> {
+        let world = &mut app.world;
+        let carriers: Vec<Entity> = world
+            .query::<(Entity, &crate::level::LevelObject)>()
+            .iter(world)
+            .filter(|(_, object)| object.kind == kind)
+            .map(|(entity, _)| entity)
+            .collect();
+        world
+            .query::<&SpotlightBeam>()
+            .iter(world)
+            .filter(|spotlight_beam| carriers.contains(&spotlight_beam.light))
+            .map(|spotlight_beam| spotlight_beam.region.clone())
+            .collect()
+    };
+    let npc_beams = beams(&mut app, LevelObjectKind::Npc);
+    assert_eq!(npc_beams.len(), 1);
+    assert!(npc_beams[0].contains_point(&Vec3::new(-70.0, 0.0, 0.0)));
+    assert!(!npc_beams[0].contains_point(&Vec3::new(-30.0, 0.0, 0.0)));
+
+    // The player's beam turns the way they walk.
+    assert!(beams(&mut app, LevelObjectKind::Player)[0].contains_point(&Vec3::new(30.0, 0.0, 0.0)));
+    let player = app
+        .world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&app.world);
+    app.world.get_mut::<Direction>(player).unwrap().value = -Vec2::Y;
+    for _ in 0..3 {
+        app.update();
+    }
+    let player_beams = beams(&mut app, LevelObjectKind::Player);
+    assert_eq!(player_beams.len(), 1);
+    assert!(player_beams[0].contains_point(&Vec3::new(0.0, -40.0, 0.0)));
+    assert!(!player_beams[0].contains_point(&Vec3::new(30.0, 0.0, 0.0)));
+}
+
+pub struct SpotlightPlugin;
+
+impl Plugin for SpotlightPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Spotlight>();
+
+        app.add_system(
+            update_spotlight_beams
+                .in_set(LightSet)
+                .after(update_sun_shadows),
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3, PI};
+
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    light::{
+        all_view_bounds, update_indirect_light, ColorMaterials, LightSet, PlayerCameraQuery,
+        SegmentShadow,
+    },
+    sight::{Occluder, OcclusionLayers},
+    viewport::{PlayerCamera, BACKGROUND_Z},
+};
+
+/// Just above the view background, and below the shadows of each player's view, which darken
+/// the ground further.
+const SUN_SHADOW_Z: f32 = BACKGROUND_Z + 0.005;
+
+const SUN_SHADOW_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.4);
+
+/// Moonlight is fainter and bluer than sunlight.
+const MOON_SHADOW_COLOR: Color = Color::rgba(0.0, 0.0, 0.2, 0.2);
+
+/// Light from far away, like the sun's or the moon's, whose shadows are parallel and the same from
+/// every point of view.
+///
+/// How far an occluder's shadow reaches depends on the occluder's [`Occluder::height`] and on how
+/// high the light is, so that low walls cast short shadows at noon and long ones at dusk.
+/// Occluders that can't be seen over cast shadows past the edge of the view, and nothing casts a
+/// shadow while the light is below the horizon.
+#[derive(Resource, Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
+pub struct Sunlight {
+    /// The direction that shadows point across the ground, in radians anticlockwise from the X
+    /// axis.
+    pub direction: f32,
+    /// How high the light is, in radians above the horizon.
+    pub elevation: f32,
+    /// The colour that shadows are drawn in. Shadows are lighter for fainter light.
+    pub shadow_color: Color,
+}
+
+impl Default for Sunlight {
+    fn default() -> Self {
+        Self {
+            direction: FRAC_PI_2,
+            elevation: FRAC_PI_3,
+            shadow_color: SUN_SHADOW_COLOR,
+        }
+    }
+}
+
+impl Sunlight {
+    /// Whether the light is above the horizon, so that it casts shadows.
+    pub fn is_up(&self) -> bool {
+        self.elevation > 0.0
+    }
+
+    /// How far the shadow of `occluder` reaches past it across the ground, or `None` if the
+    /// occluder can't be seen over, so there's no limit.
+    pub fn shadow_length(&self, occluder: &Occluder) -> Option<f32> {
+        occluder
+            .height
+            .map(|height| height.max(0.0) / self.elevation.tan())
+    }
+
+    /// The shadows of the edges of `occluder` that the light leaves it by, which together make up
+    /// the occluder's shadow, `length` long.
+    pub fn segment_shadows(&self, occluder: &Occluder, length: f32) -> Vec<SegmentShadow> {
+        let direction = Vec2::from_angle(self.direction);
+        occluder
+            .iter_segments()
+            .filter(|segment| {
+                let midpoint = (segment.0 + segment.1) / 2.0;
+                occluder.is_outside(segment, midpoint + direction.extend(0.0))
+            })
+            .map(|segment| SegmentShadow::swept(segment, direction * length))
+            .collect()
+    }
+}
+
+/// Moves the [`Sunlight`] across the sky from sunrise to sunset, and then through the night as
+/// moonlight, so that shadows swing round and lengthen towards dusk and dawn.
+#[derive(Resource, Debug, Clone, PartialEq, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
+pub struct DayNightCycle {
+    /// How long a whole day and night last, in seconds. The cycle stands still when this is 0.
+    pub length: f32,
+    /// How far through the cycle it is: 0 at sunrise, 0.25 at noon, 0.5 at sunset, 0.75 at
+    /// midnight, and 1 at the next sunrise.
+    pub time: f32,
+    /// The direction that shadows point at noon and at midnight, like [`Sunlight::direction`].
+    pub noon_direction: f32,
+    /// How high the sun is at noon, and the moon at midnight.
+    pub noon_elevation: f32,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self {
+            length: 120.0,
+            time: 0.25,
+            noon_direction: FRAC_PI_2,
+            noon_elevation: FRAC_PI_3,
+        }
+    }
+}
+
+impl DayNightCycle {
+    /// The light at the cycle's current time.
+    pub fn sunlight(&self) -> Sunlight {
+        let time = self.time.rem_euclid(1.0);
+        // How far the sun or the moon is across the sky, from rising at 0 to setting at 1.
+        let (progress, shadow_color) = if time < 0.5 {
+            (time * 2.0, SUN_SHADOW_COLOR)
+        } else {
+            (time * 2.0 - 1.0, MOON_SHADOW_COLOR)
+        };
+        Sunlight {
+            // Shadows point away from where the light rises, round to away from where it sets.
+            direction: self.noon_direction - (progress - 0.5) * PI,
+            elevation: self.noon_elevation * (progress * PI).sin(),
+            shadow_color,
+        }
+    }
+}
+
+#[test]
+fn day_night_cycle_sunlight_test_1() {
+    let mut cycle = DayNightCycle::default();
+    assert_eq!(
+        cycle.sunlight(),
+        Sunlight {
+            direction: cycle.noon_direction,
+            elevation: cycle.noon_elevation,
+            shadow_color: SUN_SHADOW_COLOR,
+        }
+    );
+
+    // Towards sunset, shadows are long and point away from the west.
+    cycle.time = 0.45;
+    let dusk = cycle.sunlight();
+    assert!(dusk.is_up());
+    assert!(dusk.direction.abs() < 0.5);
+    let low_wall = Occluder {
+        height: Some(10.0),
+        ..default()
+    };
+    assert!(dusk.shadow_length(&low_wall).unwrap() > 25.0);
+    assert!(Sunlight::default().shadow_length(&low_wall).unwrap() < 10.0);
+
+    // At midnight, the moon is where the sun was at noon.
+    cycle.time = 0.75;
+    let midnight = cycle.sunlight();
+    assert!((midnight.elevation - cycle.noon_elevation).abs() < 0.001);
+    assert_eq!(midnight.shadow_color, MOON_SHADOW_COLOR);
+}
+
+#[test]
+fn sunlight_segment_shadows_test_1() {
+    let occluder = Occluder {
+        top_left: Vec3::new(-5.0, 5.0, 0.0),
+        bottom_right: Vec3::new(5.0, -5.0, 0.0),
+        ..default()
+    };
+    let sunlight = Sunlight {
+        direction: 0.0,
+        ..default()
+    };
+
+    // Shadows pointing along the X axis are cast from the occluder's right edge.
+    let shadows = sunlight.segment_shadows(&occluder, 20.0);
+    assert_eq!(shadows.len(), 1);
+    assert!(shadows[0].contains_point(&Vec3::new(15.0, 0.0, 0.0)));
+    assert!(!shadows[0].contains_point(&Vec3::new(30.0, 0.0, 0.0)));
+    assert!(!shadows[0].contains_point(&Vec3::new(-10.0, 0.0, 0.0)));
+
+    // Diagonal shadows are cast from two edges.
+    let sunlight = Sunlight {
+        direction: -std::f32::consts::FRAC_PI_4,
+        ..default()
+    };
+    assert_eq!(sunlight.segment_shadows(&occluder, 20.0).len(), 2);
+}
+
+/// The shadow that an occluder casts in the [`Sunlight`], made up of the [`SegmentShadow`]s of its
+/// children. Sun shadows are rendered in every view.
+#[derive(Component)]
+pub struct SunShadow {
+    pub occluder: Entity,
+}
+
+fn advance_day_night_cycle(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut cycle: ResMut<DayNightCycle>,
+    sunlight: Option<ResMut<Sunlight>>,
+) {
+    if cycle.length > 0.0 {
+        cycle.time = (cycle.time + time.delta_seconds() / cycle.length).rem_euclid(1.0);
+    }
+    // The sunlight is only marked as changed when it has, so that the sun shadows aren't respawned
+    // while the cycle stands still.
+    match sunlight {
+        Some(mut sunlight) => {
+            sunlight.set_if_neq(cycle.sunlight());
+        }
+        None => commands.insert_resource(cycle.sunlight()),
+    }
+}
+
+#[test]
+fn advance_day_night_cycle_test_1() {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(DayNightCycle {
+            length: 0.0,
+            ..default()
+        })
+        .add_system(advance_day_night_cycle);
+    app.update();
+    app.update();
+    let last_changed = |app: &mut App| app.world.resource_mut::<Sunlight>().last_changed();
+    let before = last_changed(&mut app);
+
+    // While the cycle stands still, the sunlight isn't changed.
+    app.update();
+    assert_eq!(last_changed(&mut app), before);
+
+    app.world.resource_mut::<DayNightCycle>().time = 0.1;
+    app.update();
+    assert_ne!(last_changed(&mut app), before);
+    assert_eq!(
+        *app.world.resource::<Sunlight>(),
+        app.world.resource::<DayNightCycle>().sunlight()
+    );
+}
+
+/// Respawns the sun shadows when the [`Sunlight`], the occluders or the cameras have changed, and
+/// despawns them while there's no sunlight or it's below the horizon.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn update_sun_shadows(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    sunlight: Option<Res<Sunlight>>,
+    cameras: PlayerCameraQuery,
+    changed_cameras: Query<
+        (),
+        (
+            With<PlayerCamera>,
+            Or<(Changed<Transform>, Changed<OrthographicProjection>)>,
+        ),
+    >,
+    occluders: Query<(Entity, &Occluder)>,
+    changed_occluders: Query<(), Changed<Occluder>>,
+    mut removed_occluders: RemovedComponents<Occluder>,
+    sun_shadows: Query<Entity, With<SunShadow>>,
+) {
+    let occluders_removed = removed_occluders.iter().next().is_some();
+
+    let Some(sunlight) = sunlight.filter(|sunlight| sunlight.is_up()) else {
+        for entity in sun_shadows.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+    if !sunlight.is_changed()
+        && changed_cameras.is_empty()
+        && changed_occluders.is_empty()
+        && !occluders_removed
+    {
+        return;
+    }
+
+    for entity in sun_shadows.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let Some(bounds) = all_view_bounds(&cameras) else {
+        return;
+    };
+
+    for (occluder_entity, occluder) in occluders.iter() {
+        if !occluder.blocks(OcclusionLayers::LIGHT) {
+            continue;
+        }
+
+        // Shadows without a limit reach past the far side of the view.
+        let length = sunlight.shadow_length(occluder).unwrap_or_else(|| {
+            bounds.size().length() + bounds.center().distance(occluder.rect().center())
+        });
+
+        // Semi-transparent occluders cast fainter shadows.
+        let [r, g, b, a] = sunlight.shadow_color.as_rgba_f32();
+        let color = Color::rgba(r, g, b, a * occluder.opacity.clamp(0.0, 1.0));
+        let shadow_material = color_materials.get_or_add(color, &mut materials);
+
+        commands
+            .spawn((
+                SunShadow {
+                    occluder: occluder_entity,
+                },
+                SpatialBundle::default(),
+            ))
+            .with_children(|parent| {
+                for segment_shadow in sunlight.segment_shadows(occluder, length) {
+                    parent.spawn((
+                        MaterialMesh2dBundle {
+                            mesh: meshes.add(segment_shadow.quad_mesh()).into(),
+                            material: shadow_material.clone(),
+                            transform: Transform::from_xyz(0.0, 0.0, SUN_SHADOW_Z),
+                            ..default()
+                        },
+                        segment_shadow,
+                    ));
+                }
+            });
+    }
+}
+
+#[test]
+fn update_sun_shadows_test_1() {
+    let mut level = crate::level::Level::default();
+    level.walls[0].height = Some(20.0);
+    level.sun = Some(crate::level::SunSpec {
+        direction: 0.0,
+        elevation: 45.0,
+        day_length: Some(10.0),
+    });
+    let mut app = crate::headless::app(level);
+    for _ in 0..3 {
+        app.update();
+    }
+
+    // Every wall casts a shadow, and the low wall's is as long as the wall is high.
+    let world = &mut app.world;
+    assert_eq!(world.query::<&SunShadow>().iter(world).count(), 3);
+    let shadows: Vec<&SegmentShadow> = world.query::<&SegmentShadow>().iter(world).collect();
+    assert!(shadows
+        .iter()
+        .any(|shadow| shadow.contains_point(&Vec3::new(-35.0, 0.0, 0.0))));
+    assert!(!shadows
+        .iter()
+        .any(|shadow| shadow.contains_point(&Vec3::new(-20.0, 0.0, 0.0))));
+
+    // The sun sets, and the moon rises and casts shadows in its place.
+    assert_eq!(world.resource::<Sunlight>().shadow_color, SUN_SHADOW_COLOR);
+    for _ in 0..180 {
+        app.update();
+    }
+    let world = &mut app.world;
+    let night = world.resource::<Sunlight>().clone();
+    assert!(night.is_up());
+    assert_eq!(night.shadow_color, MOON_SHADOW_COLOR);
+    assert_eq!(world.query::<&SunShadow>().iter(world).count(), 3);
+
+    // Without sunlight, there are no sun shadows.
+    world.remove_resource::<DayNightCycle>();
+    world.remove_resource::<Sunlight>();
+    app.update();
+    let world = &mut app.world;
+    assert_eq!(world.query::<&SunShadow>().iter(world).count(), 0);
+}
+
+pub struct SunPlugin;
+
+impl Plugin for SunPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Sunlight>()
+            .register_type::<DayNightCycle>();
+
+        app.add_system(
+            advance_day_night_cycle
+                .run_if(resource_exists::<DayNightCycle>())
+                .before(update_sun_shadows),
+        )
+        .add_system(
+            update_sun_shadows
+                .in_set(LightSet)
+                .after(update_indirect_light),
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt::Write;
+
+use bevy::{
+    ecs::system::{SystemParam, SystemState},
+    prelude::*,
+};
+
+use crate::{
+    debug::SightLines,
+    level::LevelObject,
+    light::{IndirectLight, PlayerShadow, SegmentShadow},
+    sight::{Occluder, Portal, SightDiagnosis, VisibilityShape, Visible},
+    spotlight::SpotlightBeam,
+    sun::SunShadow,
+    team::VisionPolygon,
+};
+
+const STYLE: &str = "\
+.sun-shadow { fill: #202040; fill-opacity: 0.3; stroke: none; }
+.shadow { fill: #404040; fill-opacity: 0.5; stroke: #c00000; stroke-width: 0.5; }
+.spotlight-beam { fill: #ffffc0; fill-opacity: 0.5; stroke: #c0c000; stroke-width: 0.5; }
+.indirect-light { fill: #ffffff; fill-opacity: 0.5; stroke: #00c0c0; stroke-width: 0.5; }
+.vision { fill: #ffffff; stroke: #0000c0; stroke-width: 0.5; }
+.occluder { fill: #000000; stroke: #e0c000; stroke-width: 1; }
+.portal { stroke: #c000c0; stroke-width: 2; }
+.bounds { fill: none; stroke: #00a000; stroke-width: 1; }
+.bounds.hidden { stroke: #c00000; }
+.seen { stroke: #00c000; }
+.indirect { stroke: #00c0c0; }
+.blocked { stroke: #c00000; }
+.beyond { stroke: #808080; stroke-dasharray: 2 2; }
+.outside-field-of-view { stroke: #c0c000; }
+.out-of-range { stroke: #808080; }
+line { stroke-width: 1; }";
+
+/// How much space is left around the drawing.
+const MARGIN: f32 = 10.0;
+
+/// Formats a coordinate with at most two decimal places, so that documents are stable and small.
+fn number(value: f32) -> String {
+    let formatted = format!("{:.2}", value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" => "0".to_string(),
+        formatted => formatted.to_string(),
+    }
+}
+
+#[test]
+fn number_test_1() {
+    assert_eq!(number(1.0), "1");
+    assert_eq!(number(-2.5), "-2.5");
+    assert_eq!(number(0.12345), "0.12");
+    assert_eq!(number(-0.001), "0");
+}
+
+/// An SVG document drawn in world coordinates, with the Y axis pointing up.
+#[derive(Debug, Default)]
+struct Svg {
+    body: String,
+    bounds: Option<Rect>,
+}
+
+impl Svg {
+    fn include(&mut self, point: Vec3) {
+        let point = Rect::from_corners(point.truncate(), point.truncate());
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => bounds.union(point),
+            None => point,
+        });
+    }
+
+    fn open_group(&mut self, class: &str, title: Option<&str>) {
+        writeln!(self.body, r#"<g class="{}">"#, class).unwrap();
+        if let Some(title) = title {
+            writeln!(self.body, "<title>{}</title>", title).unwrap();
+        }
+    }
+
+    fn close_group(&mut self) {
+        self.body.push_str("</g>\n");
+    }
+
+    fn polygon(&mut self, class: &str, points: &[Vec3]) {
+        let mut formatted = Vec::with_capacity(points.len());
+        for point in points {
+            self.include(*point);
+            formatted.push(format!("{},{}", number(point.x), number(point.y)));
+        }
+        writeln!(
+            self.body,
+            r#"<polygon class="{}" points="{}"/>"#,
+            class,
+            formatted.join(" ")
+        )
+        .unwrap();
+    }
+
+    fn line(&mut self, class: &str, start: Vec3, end: Vec3) {
+        self.include(start);
+        self.include(end);
+        writeln!(
+            self.body,
+            r#"<line class="{}" x1="{}" y1="{}" x2="{}" y2="{}"/>"#,
+            class,
+            number(start.x),
+            number(start.y),
+            number(end.x),
+            number(end.y)
+        )
+        .unwrap();
+    }
+
+    fn circle(&mut self, class: &str, centre: Vec3, radius: f32) {
+        self.include(centre);
+        writeln!(
+            self.body,
+            r#"<circle class="{}" cx="{}" cy="{}" r="{}"/>"#,
+            class,
+            number(centre.x),
+            number(centre.y),
+            number(radius)
+        )
+        .unwrap();
+    }
+
+    fn finish(self) -> String {
+        let bounds = self.bounds.unwrap_or_default().inset(MARGIN);
+        format!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+                "\n<style>\n{}\n</style>\n",
+                r#"<g transform="scale(1 -1)">"#,
+                "\n{}</g>\n</svg>\n"
+            ),
+            number(bounds.min.x),
+            number(-bounds.max.y),
+            number(bounds.width()),
+            number(bounds.height()),
+            STYLE,
+            self.body
+        )
+    }
+}
+
+/// What's drawn in a world's SVG document.
+#[derive(SystemParam)]
+struct SvgSources<'w, 's> {
+    level_objects: Query<'w, 's, &'static LevelObject>,
+    sun_shadows: Query<'w, 's, (&'static SunShadow, &'static Children)>,
+    player_shadows: Query<'w, 's, (&'static PlayerShadow, &'static Children)>,
+    segment_shadows: Query<'w, 's, &'static SegmentShadow>,
+    vision_polygons: Query<'w, 's, &'static VisionPolygon>,
+    indirect_lights: Query<'w, 's, &'static IndirectLight>,
+    spotlight_beams: Query<'w, 's, &'static SpotlightBeam>,
+    occluders: Query<'w, 's, (Entity, &'static Occluder)>,
+    portals: Query<'w, 's, (Entity, &'static Portal)>,
+    visibility_shapes: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static VisibilityShape,
+            &'static GlobalTransform,
+            Option<&'static Visibility>,
+        ),
+        With<Visible>,
+    >,
+    sight_lines: SightLines<'w, 's>,
+}
+
+impl<'w, 's> SvgSources<'w, 's> {
+    /// How an entity is described in titles: as the level object it was spawned from, if it was.
+    fn name(&self, entity: Entity) -> String {
+        match self.level_objects.get(entity) {
+            Ok(level_object) => level_object.to_string(),
+            Err(_) => format!("{:?}", entity),
+        }
+    }
+
+    fn draw(&self) -> String {
+        let mut svg = Svg::default();
+
+        for (sun_shadow, children) in self.sun_shadows.iter() {
+            let title = format!("sun shadow of {}", self.name(sun_shadow.occluder));
+            svg.open_group("sun-shadows", Some(&title));
+            for segment_shadow in self.segment_shadows.iter_many(children) {
+                svg.polygon("sun-shadow", &segment_shadow.corners());
+            }
+            svg.close_group();
+        }
+
+        for (player_shadow, children) in self.player_shadows.iter() {
+            let title = format!(
+                "shadow of {} from {}",
+                self.name(player_shadow.occluder),
+                self.name(player_shadow.viewer)
+            );
+            svg.open_group("shadows", Some(&title));
+            for segment_shadow in self
+                .segment_shadows
+                .iter_many(children)
+                .filter(|segment_shadow| !segment_shadow.is_empty())
+            {
+                svg.polygon("shadow", &segment_shadow.corners());
+            }
+            svg.close_group();
+        }
+
+        for vision_polygon in self.vision_polygons.iter() {
+            let title = format!("vision of {}", self.name(vision_polygon.viewer));
+            svg.open_group("vision-polygons", Some(&title));
+            svg.polygon("vision", &vision_polygon.vertices);
+            svg.close_group();
+        }
+
+        for indirect_light in self.indirect_lights.iter() {
+            let title = format!(
+                "indirect light seen by {}",
+                self.name(indirect_light.viewer)
+            );
+            svg.open_group("indirect-lights", Some(&title));
+            svg.polygon("indirect-light", &indirect_light.region.outline());
+            svg.close_group();
+        }
+
+        for spotlight_beam in self.spotlight_beams.iter() {
+            let title = format!("beam of {}", self.name(spotlight_beam.light));
+            svg.open_group("spotlight-beams", Some(&title));
+            svg.polygon("spotlight-beam", &spotlight_beam.region.outline());
+            svg.close_group();
+        }
+
+        svg.open_group("occluders", None);
+        for (entity, occluder) in self.occluders.iter() {
+            svg.open_group("occluder", Some(&self.name(entity)));
+            let corners: Vec<Vec3> = occluder.iter_segments().map(|segment| segment.0).collect();
+            svg.polygon("occluder", &corners);
+            svg.close_group();
+        }
+        svg.close_group();
+
+        svg.open_group("portals", None);
+        for (entity, portal) in self.portals.iter() {
+            svg.open_group("portal", Some(&self.name(entity)));
+            for segment in [portal.a(), portal.b()] {
+                svg.line("portal", segment.0, segment.1);
+            }
+            svg.close_group();
+        }
+        svg.close_group();
+
+        svg.open_group("entity-bounds", None);
+        for (entity, shape, global_transform, visibility) in self.visibility_shapes.iter() {
+            let class = match visibility {
+                Some(Visibility::Hidden) => "bounds hidden",
+                _ => "bounds",
+            };
+            svg.open_group("entity", Some(&self.name(entity)));
+            let outline: Vec<Vec3> = shape
+                .outline_points()
+                .into_iter()
+                .map(|point| global_transform.transform_point(point.extend(0.0)))
+                .collect();
+            match outline.len() {
+                0 => svg.circle(class, global_transform.translation(), 2.0),
+                _ => svg.polygon(class, &outline),
+            }
+            svg.close_group();
+        }
+        svg.close_group();
+
+        svg.open_group("sight-lines", None);
+        for (start, end, diagnosis) in self.sight_lines.diagnose() {
+            match diagnosis {
+                SightDiagnosis::Seen { .. } => svg.line("seen", start, end),
+                SightDiagnosis::Indirect { point, .. } => {
+                    svg.line("seen", start, point);
+                    svg.line("indirect", point, end);
+                }
+                SightDiagnosis::Blocked {
+                    point: Some(point), ..
+                } => {
+                    svg.line("blocked", start, point);
+                    svg.line("beyond", point, end);
+                }
+                SightDiagnosis::Blocked { point: None, .. } => svg.line("blocked", start, end),
+                SightDiagnosis::OutsideFieldOfView => svg.line("outside-field-of-view", start, end),
+                SightDiagnosis::OutOfRange => svg.line("out-of-range", start, end),
+                SightDiagnosis::VieweeNotVisible | SightDiagnosis::ViewerNotSighted => {}
+            }
+        }
+        svg.close_group();
+
+        svg.finish()
+    }
+}
+
+/// An SVG document of the world's occluders, portals, shadows, vision polygons, indirect light,
+/// entity bounds and NPCs' sight lines to the players.
+pub fn world_svg(world: &mut World) -> String {
+    let mut system_state: SystemState<SvgSources> = SystemState::new(world);
+    system_state.get(world).draw()
+}
+
+/// The file that F12 exports the world to.
+const SVG_PATH: &str = "world.svg";
+
+fn save_world_svg(world: &mut World) {
+    match std::fs::write(SVG_PATH, world_svg(world)) {
+        Ok(()) => info!("exported {}", SVG_PATH),
+        Err(err) => warn!("couldn't export {}: {}", SVG_PATH, err),
+    }
+}
+
+fn export_world_svg(mut commands: Commands, keyboard_input: Res<Input<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::F12) {
+        commands.add(save_world_svg);
+    }
+}
+
+pub struct SvgPlugin;
+
+impl Plugin for SvgPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(export_world_svg);
+    }
+}
+
+#[test]
+fn world_svg_golden_test_1() {
+    use crate::level::{Level, LightSpec};
+
+    let mut level = Level::default();
+    level.lights.push(LightSpec {
+        position: Vec2::new(100.0, 0.0),
+        team: 0,
+    });
+
+    let mut app = crate::headless::app(level);
+    for _ in 0..3 {
+        app.update();
+    }
+
+    crate::golden::check("goldens/default.svg", world_svg(&mut app.world).as_bytes());
+}
@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology, view::RenderLayers},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+
+use crate::{
+    light::{all_view_bounds, ColorMaterials, LightSet, PlayerCameraQuery},
+    player::Player,
+    sight::{view_polygon, Occluder, Segment, Sighted, Team, Visible},
+    viewport::{PlayerCamera, ViewLayer, BACKGROUND_Z},
+};
+
+/// A stationary camera that shares what it sees with its team.
+#[derive(Bundle)]
+pub struct TeamCameraBundle {
+    pub sprite_bundle: SpriteBundle,
+    pub sighted: Sighted,
+    pub visible: Visible,
+    pub team: Team,
+}
+
+impl TeamCameraBundle {
+    pub fn new(team: Team) -> Self {
+        Self {
+            sprite_bundle: SpriteBundle {
+                sprite: Sprite {
+                    color: Color::PURPLE,
+                    custom_size: Some(Vec2 { x: 6.0, y: 6.0 }),
+                    ..default()
+                },
+                ..default()
+            },
+            sighted: Sighted::default(),
+            visible: Visible,
+            team,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.sprite_bundle.transform = transform;
+        self
+    }
+}
+
+/// The region that a team member can see.
+///
+/// A team's view is the union of its members' vision polygons, drawn over a background that's in
+/// shadow. Each polygon is cut to its member's range and field of view. Semi-transparent occluders
+/// don't cut the polygons, since what's behind them can still be seen dimly.
+#[derive(Component)]
+pub struct VisionPolygon {
+    pub viewer: Entity,
+    pub vertices: Vec<Vec3>,
+}
+
+/// Just above the view background.
+const VISION_POLYGON_Z: f32 = BACKGROUND_Z + 0.01;
+
+#[derive(Debug)]
+struct Fan {
+    centre: Vec3,
+    rim: Vec<Vec3>,
+}
+
+impl From<Fan> for Mesh {
+    fn from(value: Fan) -> Self {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        let rim_len = value.rim.len() as u32;
+        let mut positions = vec![value.centre];
+        positions.extend(value.rim);
+
+        let indices = (0..rim_len)
+            .flat_map(|index| [0, index + 1, (index + 1) % rim_len + 1])
+            .collect();
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+fn add_vision_polygons(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut color_materials: ResMut<ColorMaterials>,
+    members: Query<Entity, (With<Sighted>, With<Team>)>,
+    vision_polygons: Query<&VisionPolygon>,
+) {
+    let has_polygon: HashSet<Entity> = vision_polygons
+        .iter()
+        .map(|vision_polygon| vision_polygon.viewer)
+        .collect();
+
+    for member in members.iter() {
+        if has_polygon.contains(&member) {
+            continue;
+        }
+
+        let material = color_materials.get_or_add(Color::WHITE, &mut materials);
+
+        commands.spawn((
+            VisionPolygon {
+                viewer: member,
+                vertices: Vec::new(),
+            },
+            MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(Mesh::new(PrimitiveTopology::TriangleList))
+                    .into(),
+                material,
+                transform: Transform::from_xyz(0.0, 0.0, VISION_POLYGON_Z),
+                ..default()
+            },
+            RenderLayers::none(),
+        ));
+    }
+}
+
+/// Recomputes vision polygons when their viewer, the cameras or the occluders change, and shows
+/// each one in the viewports of its team's players.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_vision_polygons(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    members: Query<(&Transform, &Team, &Sighted)>,
+    moved_members: Query<
+        (),
+        (
+            With<Sighted>,
+            With<Team>,
+            Or<(Changed<Transform>, Changed<Sighted>)>,
+        ),
+    >,
+    players: Query<(&Team, &ViewLayer), With<Player>>,
+    cameras: PlayerCameraQuery,
+    changed_cameras: Query<
+        (),
+        (
+            With<PlayerCamera>,
+            Or<(Changed<Transform>, Changed<OrthographicProjection>)>,
+        ),
+    >,
+    occluders: Query<&Occluder>,
+    changed_occluders: Query<(), Changed<Occluder>>,
+    mut removed_occluders: RemovedComponents<Occluder>,
+    mut vision_polygons: Query<(Entity, &mut VisionPolygon, &Mesh2dHandle, &RenderLayers)>,
+) {
+    let Some(bounds) = all_view_bounds(&cameras) else {
+        return;
+    };
+
+    let world_changed = !changed_cameras.is_empty()
+        || !changed_occluders.is_empty()
+        || removed_occluders.iter().next().is_some();
+
+    for (entity, mut vision_polygon, mesh_handle, render_layers) in vision_polygons.iter_mut() {
+        let Ok((member_transform, team, sighted)) = members.get(vision_polygon.viewer) else {
+            continue;
+        };
+
+        let team_render_layers = players
+            .iter()
+            .filter(|(player_team, _)| *player_team == team)
+            .fold(RenderLayers::none(), |render_layers, (_, view_layer)| {
+                render_layers.with(view_layer.0)
+            });
+        if *render_layers != team_render_layers {
+            commands.entity(entity).insert(team_render_layers);
+        }
+
+        if world_changed
+            || moved_members.contains(vision_polygon.viewer)
+            || vision_polygon.vertices.is_empty()
+        {
+            let origin = member_transform.translation.truncate().extend(0.0);
+            let segments: Vec<Segment> = occluders
+                .iter()
+                .filter(|occluder| occluder.blocks(sighted.blocked_by) && occluder.is_opaque())
+                .flat_map(|occluder| occluder.iter_segments_from(origin))
+                .collect();
+            vision_polygon.vertices = view_polygon(
+                origin,
+                member_transform.rotation * Vec3::X,
+                sighted,
+                &segments,
+                bounds,
+            );
+
+            if let Some(mesh) = meshes.get_mut(&mesh_handle.0) {
+                *mesh = Fan {
+                    centre: origin,
+                    rim: vision_polygon.vertices.clone(),
+                }
+                .into();
+            }
+        }
+    }
+}
+
+fn remove_vision_polygons(
+    mut commands: Commands,
+    members: Query<(), (With<Sighted>, With<Team>)>,
+    vision_polygons: Query<(Entity, &VisionPolygon)>,
+) {
+    for (entity, vision_polygon) in vision_polygons.iter() {
+        if !members.contains(vision_polygon.viewer) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[test]
+fn team_vision_test_1() {
+    use crate::{
+        level::{ControlScheme, Level, PlayerSpawn},
+        npc::Npc,
+    };
+
+    // The NPC is behind a wall from the first player and the loner, but the second player sees it.
+    let mut level = Level::default();
+    level.players[0].team = Some(1);
+    let spawn = |x: f32, y: f32, team: Option<u32>| PlayerSpawn {
+        position: Vec2::new(x, y),
+        controls: ControlScheme::Arrows,
+        team,
+        ..level.players[0].clone()
+    };
+    level
+        .players
+        .extend([spawn(-150.0, 0.0, Some(1)), spawn(0.0, 20.0, None)]);
+    let mut app = crate::headless::app(level);
+    for _ in 0..3 {
+        app.update();
+    }
+
+    let mut players: Vec<(Entity, Vec3, ViewLayer)> = app
+        .world
+        .query_filtered::<(Entity, &Transform, &ViewLayer), With<Player>>()
+        .iter(&app.world)
+        .map(|(entity, transform, view_layer)| (entity, transform.translation, *view_layer))
+        .collect();
+    players.sort_by(|a, b| a.1.x.total_cmp(&b.1.x).then(a.1.y.total_cmp(&b.1.y)));
+    let [(member, _, member_layer), (_, _, first_member_layer), (_, _, loner_layer)] = players[..]
+    else {
+        panic!("expected three players, found {:?}", players);
+    };
+    let npc = app
+        .world
+        .query_filtered::<Entity, With<Npc>>()
+        .single(&app.world);
+    let npc_layers = |app: &App| app.world.get::<RenderLayers>(npc).copied();
+
+    let layers = npc_layers(&app).unwrap();
+    assert!(layers.intersects(&RenderLayers::layer(member_layer.0)));
+    assert!(layers.intersects(&RenderLayers::layer(first_member_layer.0)));
+    assert!(!layers.intersects(&RenderLayers::layer(loner_layer.0)));
+
+    // Turned away from the NPC, with a narrow field of view, the team can't see it.
+    app.world.get_mut::<Transform>(member).unwrap().rotation =
+        Quat::from_rotation_z(std::f32::consts::PI);
+    app.world.get_mut::<Sighted>(member).unwrap().field_of_view = Some(std::f32::consts::FRAC_PI_2);
+    // The member's global transform catches up at the end of the first update.
+    for _ in 0..2 {
+        app.update();
+    }
+
+    assert_eq!(npc_layers(&app), Some(RenderLayers::none()));
+    let member_polygon = app
+        .world
+        .query::<&VisionPolygon>()
+        .iter(&app.world)
+        .find(|vision_polygon| vision_polygon.viewer == member)
+        .unwrap();
+    assert!(!crate::sight::polygon_contains_point(
+        &member_polygon.vertices,
+        &Vec3::new(-100.0, 0.0, 0.0)
+    ));
+
+    // Leaving the team takes away the member's vision polygon.
+    app.world.entity_mut(member).remove::<Team>();
+    app.update();
+
+    let viewers: Vec<Entity> = app
+        .world
+        .query::<&VisionPolygon>()
+        .iter(&app.world)
+        .map(|vision_polygon| vision_polygon.viewer)
+        .collect();
+    assert_eq!(viewers.len(), 1);
+    assert!(!viewers.contains(&member));
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub struct TeamSet;
+
+pub struct TeamPlugin;
+
+impl Plugin for TeamPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_set(TeamSet.after(LightSet));
+
+        app.add_systems(
+            (
+                add_vision_polygons,
+                update_vision_polygons,
+                remove_vision_polygons,
+            )
+                .chain()
+                .in_set(TeamSet),
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{
+        camera::{ScalingMode, Viewport},
+        view::RenderLayers,
+    },
+    window::PrimaryWindow,
+};
+
+use crate::{movement::MovementSet, player::Player, sight::Team};
+
+/// How the players' viewports are arranged in the window.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ViewportLayout {
+    /// The window is divided into equally sized columns, one per player.
+    #[default]
+    SplitScreen,
+    /// The first player's viewport fills the window, and the other players' viewports are insets
+    /// in rows along its top right.
+    PictureInPicture,
+}
+
+/// The render layer that holds everything specific to a player's view: its shadows, and the
+/// entities that it can see.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ViewLayer(pub u8);
+
+impl ViewLayer {
+    pub fn render_layers(&self) -> RenderLayers {
+        RenderLayers::layer(self.0)
+    }
+}
+
+#[derive(Component)]
+pub struct PlayerCamera {
+    pub player: Entity,
+}
+
+/// Covers a player's viewport so that insets don't show the viewports beneath them.
+///
+/// A team player's view starts out in shadow, and is lit by its team's vision polygons.
+#[derive(Component)]
+struct ViewBackground {
+    camera: Entity,
+}
+
+/// Just in front of a 2D camera's far plane.
+pub const BACKGROUND_Z: f32 = -0.05;
+
+/// The highest that an entity can stand above the ground, such as on a watchtower. Elevation is
+/// the Z of an entity's translation, so this keeps elevated entities well behind a 2D camera,
+/// which is 999.9 above the ground, and so still drawn.
+pub const MAX_ELEVATION: f32 = 500.0;
+
+/// The area of the world that's visible to a camera.
+pub fn view_bounds(camera_transform: &Transform, projection: &OrthographicProjection) -> Rect {
+    let centre = camera_transform.translation.truncate();
+    Rect {
+        min: centre + projection.area.min,
+        max: centre + projection.area.max,
+    }
+}
+
+/// Gives new players a [`ViewLayer`] and a camera that renders it.
+fn spawn_player_cameras(
+    mut commands: Commands,
+    new_players: Query<Entity, (With<Player>, Without<ViewLayer>)>,
+    view_layers: Query<&ViewLayer>,
+) {
+    let mut used_layers: Vec<u8> = view_layers.iter().map(|view_layer| view_layer.0).collect();
+
+    for player in new_players.iter() {
+        let Some(layer) =
+            (1..RenderLayers::TOTAL_LAYERS as u8).find(|layer| !used_layers.contains(layer))
+        else {
+            warn!("no render layers left for player {:?}", player);
+            continue;
+        };
+        used_layers.push(layer);
+
+        let view_layer = ViewLayer(layer);
+        commands.entity(player).insert(view_layer);
+
+        let camera = commands
+            .spawn((
+                PlayerCamera { player },
+                Camera2dBundle {
+                    camera: Camera {
+                        order: layer as isize,
+                        ..default()
+                    },
+                    ..default()
+                },
+                view_layer.render_layers().with(0),
+            ))
+            .id();
+
+        commands.spawn((
+            ViewBackground { camera },
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::WHITE,
+                    ..default()
+                },
+                ..default()
+            },
+            view_layer.render_layers(),
+        ));
+    }
+}
+
+fn despawn_player_cameras(
+    mut commands: Commands,
+    players: Query<(), With<Player>>,
+    cameras: Query<(Entity, &PlayerCamera)>,
+    backgrounds: Query<(Entity, &ViewBackground)>,
+) {
+    for (entity, player_camera) in cameras.iter() {
+        if !players.contains(player_camera.player) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    for (entity, view_background) in backgrounds.iter() {
+        if !cameras.contains(view_background.camera) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Compute the viewport of each of `count` cameras in a window of the given physical size.
+fn layout_viewports(layout: ViewportLayout, window_size: UVec2, count: u32) -> Vec<Viewport> {
+    match layout {
+        ViewportLayout::SplitScreen => {
+            let width = window_size.x / count.max(1);
+            (0..count)
+                .map(|index| Viewport {
+                    physical_position: UVec2::new(index * width, 0),
+                    physical_size: UVec2::new(width, window_size.y),
+                    ..default()
+                })
+                .collect()
+        }
+        ViewportLayout::PictureInPicture => {
+            // The insets are a quarter of the window's size, in rows of three along the top right
+            // so that the top-left corner of the main view stays clear. When there are too many to
+            // fit, they're shrunk until they do.
+            let insets = count.saturating_sub(1);
+            let mut divisions = 4;
+            while (divisions - 1) * divisions < insets {
+                divisions += 1;
+            }
+            let per_row = divisions - 1;
+            let inset_size = window_size / divisions;
+            (0..count)
+                .map(|index| {
+                    if index == 0 {
+                        Viewport {
+                            physical_position: UVec2::ZERO,
+                            physical_size: window_size,
+                            ..default()
+                        }
+                    } else {
+                        let (column, row) = ((index - 1) % per_row, (index - 1) / per_row);
+                        Viewport {
+                            physical_position: UVec2::new(
+                                window_size.x - (column + 1) * inset_size.x,
+                                row * inset_size.y,
+                            ),
+                            physical_size: inset_size,
+                            ..default()
+                        }
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+#[test]
+fn layout_viewports_test_1() {
+    let viewports = layout_viewports(ViewportLayout::SplitScreen, UVec2::new(800, 600), 2);
+    assert_eq!(
+        viewports
+            .iter()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size))
+            .collect::<Vec<_>>(),
+        vec![
+            (UVec2::new(0, 0), UVec2::new(400, 600)),
+            (UVec2::new(400, 0), UVec2::new(400, 600))
+        ]
+    );
+
+    let viewports = layout_viewports(ViewportLayout::PictureInPicture, UVec2::new(800, 600), 2);
+    assert_eq!(
+        viewports
+            .iter()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size))
+            .collect::<Vec<_>>(),
+        vec![
+            (UVec2::new(0, 0), UVec2::new(800, 600)),
+            (UVec2::new(600, 0), UVec2::new(200, 150))
+        ]
+    );
+
+    // The fifth and sixth players' insets wrap onto a second row.
+    let viewports = layout_viewports(ViewportLayout::PictureInPicture, UVec2::new(800, 600), 6);
+    assert_eq!(
+        viewports
+            .iter()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size))
+            .collect::<Vec<_>>(),
+        vec![
+            (UVec2::new(0, 0), UVec2::new(800, 600)),
+            (UVec2::new(600, 0), UVec2::new(200, 150)),
+            (UVec2::new(400, 0), UVec2::new(200, 150)),
+            (UVec2::new(200, 0), UVec2::new(200, 150)),
+            (UVec2::new(600, 150), UVec2::new(200, 150)),
+            (UVec2::new(400, 150), UVec2::new(200, 150)),
+        ]
+    );
+
+    // Too many insets to fit at a quarter of the window's size are shrunk, and stay inside it.
+    let viewports = layout_viewports(ViewportLayout::PictureInPicture, UVec2::new(800, 600), 14);
+    assert_eq!(viewports[1].physical_size, UVec2::new(160, 120));
+    assert!(viewports.iter().all(|viewport| {
+        let end = viewport.physical_position + viewport.physical_size;
+        end.x <= 800 && end.y <= 600
+    }));
+}
+
+/// Arranges the player cameras according to the [`ViewportLayout`].
+///
+/// Every camera shows at least the window's area of the world, so each player sees the whole
+/// level regardless of how small their viewport is.
+fn arrange_player_cameras(
+    layout: Res<ViewportLayout>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(
+        &PlayerCamera,
+        &mut Camera,
+        &mut Camera2d,
+        &mut OrthographicProjection,
+    )>,
+    view_layers: Query<&ViewLayer>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+
+    let mut cameras: Vec<_> = cameras
+        .iter_mut()
+        .filter_map(|camera| {
+            let view_layer = view_layers.get(camera.0.player).ok()?;
+            Some((view_layer.0, camera))
+        })
+        .collect();
+    cameras.sort_by_key(|(layer, _)| *layer);
+
+    let viewports = layout_viewports(
+        *layout,
+        UVec2::new(
+            window.resolution.physical_width(),
+            window.resolution.physical_height(),
+        ),
+        cameras.len() as u32,
+    );
+
+    for (index, ((_, (_, mut camera, mut camera_2d, mut projection)), viewport)) in
+        cameras.into_iter().zip(viewports).enumerate()
+    {
+        if camera
+            .viewport
+            .as_ref()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size))
+            != Some((viewport.physical_position, viewport.physical_size))
+        {
+            camera.viewport = Some(viewport);
+        }
+
+        // Only the bottom-most camera clears the window; the others would clear it all.
+        let clears = index == 0;
+        if clears != matches!(camera_2d.clear_color, ClearColorConfig::Custom(_)) {
+            camera_2d.clear_color = if clears {
+                ClearColorConfig::Custom(Color::WHITE)
+            } else {
+                ClearColorConfig::None
+            };
+        }
+
+        let (min_width, min_height) = (window.width(), window.height());
+        if !matches!(
+            projection.scaling_mode,
+            ScalingMode::AutoMin { min_width: width, min_height: height }
+                if width == min_width && height == min_height
+        ) {
+            projection.scaling_mode = ScalingMode::AutoMin {
+                min_width,
+                min_height,
+            };
+        }
+    }
+}
+
+fn update_view_backgrounds(
+    cameras: Query<(&PlayerCamera, &Transform, &OrthographicProjection)>,
+    teams: Query<(), With<Team>>,
+    mut backgrounds: Query<(&ViewBackground, &mut Sprite, &mut Transform), Without<PlayerCamera>>,
+) {
+    for (view_background, mut sprite, mut transform) in backgrounds.iter_mut() {
+        if let Ok((player_camera, camera_transform, projection)) =
+            cameras.get(view_background.camera)
+        {
+            let color = if teams.contains(player_camera.player) {
+                Color::DARK_GRAY
+            } else {
+                Color::WHITE
+            };
+            if sprite.color != color {
+                sprite.color = color;
+            }
+
+            let bounds = view_bounds(camera_transform, projection);
+            let size = Some(bounds.size());
+            if sprite.custom_size != size {
+                sprite.custom_size = size;
+            }
+            let translation = bounds.center().extend(BACKGROUND_Z);
+            if transform.translation != translation {
+                transform.translation = translation;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub struct ViewportSet;
+
+pub struct ViewportPlugin;
+
+impl Plugin for ViewportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ViewportLayout>();
+
+        app.configure_set(ViewportSet.after(MovementSet));
+
+        app.add_systems(
+            (
+                spawn_player_cameras,
+                despawn_player_cameras,
+                arrange_player_cameras,
+                update_view_backgrounds,
+            )
+                .chain()
+                .in_set(ViewportSet),
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use bevy::prelude::*;
+use image::ImageEncoder;
+
+use crate::{
+    light::SegmentShadow,
+    sight::{
+        polygon_contains_point, unobstructed_fraction, visibility_polygon, Occluder,
+        OcclusionLayers, Segment, Sighted,
+    },
+};
+
+/// Which region of a [`VisibilityMap`] is shown as clear.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    /// The region lit by a light at the viewer, as the shadows in `light.rs` are cast.
+    #[default]
+    Lit,
+    /// The region that the viewer can see, as the vision polygons in `team.rs` are computed and
+    /// limited by the viewer's range and field of view.
+    Visible,
+}
+
+/// What's at a point in a [`VisibilityMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapCell {
+    Clear,
+    /// Partly lit or visible, through semi-transparent occluders.
+    Dim,
+    Shadow,
+    Occluder,
+    Viewer,
+}
+
+impl MapCell {
+    fn ascii(&self) -> char {
+        match self {
+            MapCell::Clear => '.',
+            MapCell::Dim => '~',
+            MapCell::Shadow => ':',
+            MapCell::Occluder => '#',
+            MapCell::Viewer => '@',
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            MapCell::Clear => Color::WHITE,
+            MapCell::Dim => Color::GRAY,
+            MapCell::Shadow => Color::DARK_GRAY,
+            MapCell::Occluder => Color::BLACK,
+            MapCell::Viewer => Color::BLUE,
+        }
+    }
+}
+
+/// A grid of square cells covering `bounds`, each showing whether it's covered by an occluder, or
+/// else whether its centre is clear as seen from a viewer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisibilityMap {
+    pub bounds: Rect,
+    pub width: usize,
+    pub height: usize,
+    /// The cells in rows from the top of `bounds` to the bottom.
+    cells: Vec<MapCell>,
+}
+
+impl VisibilityMap {
+    /// Draws the map of `bounds` with cells of `cell_size`, from a viewer at `viewer`.
+    pub fn render(
+        mode: MapMode,
+        viewer: (&Transform, &Sighted),
+        occluders: &[Occluder],
+        bounds: Rect,
+        cell_size: f32,
+    ) -> Self {
+        let (transform, sighted) = viewer;
+        let viewer_position = transform.translation;
+        let blocking_layers = match mode {
+            MapMode::Lit => OcclusionLayers::LIGHT,
+            MapMode::Visible => sighted.blocked_by,
+        };
+        let (opaque, translucent): (Vec<&Occluder>, Vec<&Occluder>) = occluders
+            .iter()
+            .filter(|occluder| occluder.blocks(blocking_layers))
+            .partition(|occluder| occluder.is_opaque());
+
+        // Each occluder's shadow, as the shadows of its segments.
+        let shadows: Vec<(&Occluder, Vec<SegmentShadow>)> = match mode {
+            MapMode::Lit => opaque
+                .iter()
+                .chain(&translucent)
+                .map(|occluder| {
+                    let segment_shadows = occluder
+                        .iter_segments_from(viewer_position)
+                        .filter_map(|segment| {
+                            let reach = occluder.shadow_reach(viewer_position.z);
+                            SegmentShadow::project(viewer_position, segment, bounds, reach).ok()
+                        })
+                        .collect();
+                    (*occluder, segment_shadows)
+                })
+                .collect(),
+            MapMode::Visible => Vec::new(),
+        };
+        let polygon = match mode {
+            MapMode::Lit => Vec::new(),
+            MapMode::Visible => {
+                let segments: Vec<Segment> = opaque
+                    .iter()
+                    .flat_map(|occluder| occluder.iter_segments_from(viewer_position))
+                    .collect();
+                visibility_polygon(viewer_position, &segments, bounds)
+            }
+        };
+        let facing = transform.rotation * Vec3::X;
+
+        // How much of the light or sight reaches a point, through any semi-transparent occluders.
+        let transmittance = |point: Vec3| match mode {
+            MapMode::Lit => shadows
+                .iter()
+                .filter(|(_, segment_shadows)| {
+                    segment_shadows
+                        .iter()
+                        .any(|shadow| shadow.contains_point(&point))
+                })
+                .map(|(occluder, _)| occluder.transmittance())
+                .product(),
+            MapMode::Visible => {
+                let offset = point - viewer_position;
+                if sighted.in_range(offset)
+                    && sighted.in_field_of_view(facing, offset)
+                    && polygon_contains_point(&polygon, &point)
+                {
+                    unobstructed_fraction(&[viewer_position], &[point], &translucent)
+                } else {
+                    0.0
+                }
+            }
+        };
+
+        let width = (bounds.width() / cell_size).ceil().max(1.0) as usize;
+        let height = (bounds.height() / cell_size).ceil().max(1.0) as usize;
+        let viewer_cell = (
+            ((viewer_position.x - bounds.min.x) / cell_size).floor(),
+            ((bounds.max.y - viewer_position.y) / cell_size).floor(),
+        );
+
+        let cell = Vec2::splat(cell_size);
+        let mut cells = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                let point = Vec3::new(
+                    bounds.min.x + (column as f32 + 0.5) * cell_size,
+                    bounds.max.y - (row as f32 + 0.5) * cell_size,
+                    viewer_position.z,
+                );
+
+                let cell = if (column as f32, row as f32) == viewer_cell {
+                    MapCell::Viewer
+                } else if occluders.iter().any(|occluder| {
+                    occluder_overlaps(occluder, Rect::from_center_size(point.truncate(), cell))
+                }) {
+                    MapCell::Occluder
+                } else {
+                    match transmittance(point) {
+                        transmittance if transmittance >= 1.0 => MapCell::Clear,
+                        transmittance if transmittance <= 0.0 => MapCell::Shadow,
+                        _ => MapCell::Dim,
+                    }
+                };
+                cells.push(cell);
+            }
+        }
+
+        Self {
+            bounds,
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> Option<MapCell> {
+        if column < self.width && row < self.height {
+            Some(self.cells[row * self.width + column])
+        } else {
+            None
+        }
+    }
+
+    /// One character per cell, and one line per row.
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((self.width + 1) * self.height);
+        for row in self.cells.chunks(self.width) {
+            ascii.extend(row.iter().map(MapCell::ascii));
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    /// The cells' colours as 8-bit sRGB, one pixel per cell.
+    fn rgb(&self) -> Vec<u8> {
+        self.cells
+            .iter()
+            .flat_map(|cell| color_rgb(cell.color()))
+            .collect()
+    }
+
+    /// A binary PPM image with one pixel per cell.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        encode_ppm(self.width, self.height, &self.rgb())
+    }
+
+    /// A PNG image with one pixel per cell.
+    pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+        encode_png(self.width, self.height, &self.rgb())
+    }
+}
+
+/// A binary PPM image of `width` by `height` 8-bit sRGB pixels.
+pub(crate) fn encode_ppm(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    ppm.extend(rgb);
+    ppm
+}
+
+/// A PNG image of `width` by `height` 8-bit sRGB pixels.
+pub(crate) fn encode_png(
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> Result<Vec<u8>, image::ImageError> {
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png).write_image(
+        rgb,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgb8,
+    )?;
+    Ok(png)
+}
+
+/// A colour as 8-bit sRGB.
+pub(crate) fn color_rgb(color: Color) -> [u8; 3] {
+    let [r, g, b, _] = color.as_rgba_f32();
+    [r, g, b].map(|component| (component * 255.0).round() as u8)
+}
+
+/// Whether `occluder` covers any of `rect`, so that occluders thinner than a cell are still drawn.
+fn occluder_overlaps(occluder: &Occluder, rect: Rect) -> bool {
+    !occluder.rect().intersect(rect).is_empty()
+}
+
+#[cfg(test)]
+fn default_level_map(mode: MapMode, viewer: &str, cell_size: f32) -> VisibilityMap {
+    let level = crate::level::Level::default();
+    let (transform, sighted) = level.viewer(viewer.parse().unwrap()).unwrap();
+    VisibilityMap::render(
+        mode,
+        (&transform, &sighted),
+        &level.occluders(),
+        Rect::from_center_size(Vec2::ZERO, Vec2::new(400.0, 240.0)),
+        cell_size,
+    )
+}
+
+#[test]
+fn visibility_map_golden_test_1() {
+    let map = default_level_map(MapMode::Lit, "players[0]", 5.0);
+    crate::golden::check("goldens/default_lit.txt", map.to_ascii().as_bytes());
+    crate::golden::check("goldens/default_lit.ppm", &map.to_ppm());
+}
+
+#[test]
+fn visibility_map_golden_test_2() {
+    let map = default_level_map(MapMode::Visible, "npcs[0]", 5.0);
+    crate::golden::check("goldens/default_npc_visible.txt", map.to_ascii().as_bytes());
+}
+
+#[test]
+fn visibility_map_test_1() {
+    // Light dimmed by a pane of glass, and then stopped by a wall behind part of it.
+    let pane = Occluder {
+        top_left: Vec3::new(10.0, 20.0, 0.0),
+        bottom_right: Vec3::new(12.0, -20.0, 0.0),
+        opacity: 0.5,
+        ..default()
+    };
+    let wall = Occluder {
+        top_left: Vec3::new(30.0, 20.0, 0.0),
+        bottom_right: Vec3::new(32.0, 0.0, 0.0),
+        ..default()
+    };
+    let map = VisibilityMap::render(
+        MapMode::Lit,
+        (&Transform::default(), &Sighted::default()),
+        &[pane, wall],
+        Rect::new(-4.0, -16.0, 56.0, 16.0),
+        4.0,
+    );
+    let cell_at = |x: f32, y: f32| {
+        map.get(
+            ((x - map.bounds.min.x) / 4.0) as usize,
+            ((map.bounds.max.y - y) / 4.0) as usize,
+        )
+    };
+
+    assert_eq!(cell_at(6.0, 2.0), Some(MapCell::Clear));
+    assert_eq!(cell_at(22.0, 2.0), Some(MapCell::Dim));
+    assert_eq!(cell_at(42.0, -6.0), Some(MapCell::Dim));
+    assert_eq!(cell_at(42.0, 6.0), Some(MapCell::Shadow));
+}
@@ -1,8 +1,10 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::sight::{Occluder, Visible};
+use crate::sight::{EdgeFacings, Occluder, OcclusionLayers, Visible};
 
-#[derive(Component)]
+#[derive(Component, Debug, Default, Clone, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct Wall;
 
 #[derive(Bundle)]
@@ -36,6 +38,7 @@ impl WallBundle {
                     y: -50.0,
                     z: 0.0,
                 },
+                ..default()
             },
             visible: Visible,
         }
@@ -64,6 +67,42 @@ impl WallBundle {
             };
         self
     }
+
+    pub fn with_layers(mut self, layers: OcclusionLayers) -> Self {
+        self.occluder.layers = layers;
+        self
+    }
+
+    /// Makes the wall semi-transparent if `opacity` is less than 1, and draws it in `tint`.
+    pub fn with_opacity(mut self, opacity: f32, tint: Color) -> Self {
+        self.occluder.opacity = opacity;
+        self.occluder.tint = tint;
+        if !self.occluder.is_opaque() {
+            self.sprite_bundle.sprite.color = tint.with_a(opacity);
+        }
+        self
+    }
+
+    /// Makes the wall one-way, blocking sight and light from one side of its edges only.
+    pub fn with_facing(mut self, facing: EdgeFacings) -> Self {
+        self.occluder.facing = facing;
+        self
+    }
+
+    /// Makes the wall a mirror if `mirror` is true, and draws it in silver.
+    pub fn with_mirror(mut self, mirror: bool) -> Self {
+        self.occluder.mirror = mirror;
+        if mirror {
+            self.sprite_bundle.sprite.color = Color::SILVER;
+        }
+        self
+    }
+
+    /// Makes the wall low enough to see over if `height` is given.
+    pub fn with_height(mut self, height: Option<f32>) -> Self {
+        self.occluder.height = height;
+        self
+    }
 }
 
 impl Default for WallBundle {
@@ -0,0 +1,247 @@
+use std::any::TypeId;
+
+use bevy::{
+    ecs::{entity::EntityMap, reflect::ReflectComponent, world::EntityRef},
+    prelude::*,
+    scene::{serde::SceneDeserializer, DynamicEntity, SceneSpawnError},
+};
+use serde::de::DeserializeSeed;
+
+use crate::{
+    controls::Controlled,
+    level::LevelObject,
+    movement::{Direction, Speed},
+    npc::{Npc, Patrol},
+    player::Player,
+    sight::{DerivedVisibilityShape, Occluder, Portal, Sighted, Team, VisibilityShape, Visible},
+    spotlight::Spotlight,
+    wall::Wall,
+};
+
+/// The components that are saved in a world scene.
+///
+/// Rendering components other than [`Sprite`] are left out, and are added back by
+/// [`complete_loaded_entities`] when the scene is loaded.
+fn saved_component_types() -> [TypeId; 18] {
+    [
+        TypeId::of::<Transform>(),
+        TypeId::of::<Sprite>(),
+        TypeId::of::<Player>(),
+        TypeId::of::<Npc>(),
+        TypeId::of::<Patrol>(),
+        TypeId::of::<Wall>(),
+        TypeId::of::<Occluder>(),
+        TypeId::of::<Portal>(),
+        TypeId::of::<Sighted>(),
+        TypeId::of::<Visible>(),
+        TypeId::of::<VisibilityShape>(),
+        TypeId::of::<DerivedVisibilityShape>(),
+        TypeId::of::<Team>(),
+        TypeId::of::<Speed>(),
+        TypeId::of::<Direction>(),
+        TypeId::of::<Controlled>(),
+        TypeId::of::<Spotlight>(),
+        TypeId::of::<LevelObject>(),
+    ]
+}
+
+/// Whether an entity is part of the game, rather than something derived from it like a camera or
+/// a shadow.
+fn is_saved_entity(entity: &EntityRef) -> bool {
+    entity.contains::<Player>()
+        || entity.contains::<Npc>()
+        || entity.contains::<Wall>()
+        || entity.contains::<Occluder>()
+        || entity.contains::<Portal>()
+        || entity.contains::<Sighted>()
+        || entity.contains::<Visible>()
+        || entity.contains::<Spotlight>()
+}
+
+#[derive(Debug)]
+pub enum WorldSceneError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Spawn(SceneSpawnError),
+}
+
+impl std::fmt::Display for WorldSceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldSceneError::Io(err) => write!(f, "{}", err),
+            WorldSceneError::Ron(err) => write!(f, "{}", err),
+            WorldSceneError::Spawn(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for WorldSceneError {}
+
+/// A scene of the game's entities, with their gameplay and visibility components.
+pub fn save_world_scene(world: &World) -> DynamicScene {
+    let type_registry = world.resource::<AppTypeRegistry>().read();
+    let reflect_components: Vec<&ReflectComponent> = saved_component_types()
+        .into_iter()
+        .filter_map(|type_id| type_registry.get(type_id)?.data::<ReflectComponent>())
+        .collect();
+
+    let entities = world
+        .iter_entities()
+        .filter(is_saved_entity)
+        .map(|entity| DynamicEntity {
+            entity: entity.id().index(),
+            components: reflect_components
+                .iter()
+                .filter_map(|reflect_component| {
+                    Some(reflect_component.reflect(entity)?.clone_value())
+                })
+                .collect(),
+        })
+        .collect();
+
+    DynamicScene { entities }
+}
+
+pub fn save_world_scene_ron(world: &World) -> Result<String, WorldSceneError> {
+    save_world_scene(world)
+        .serialize_ron(world.resource::<AppTypeRegistry>())
+        .map_err(WorldSceneError::Ron)
+}
+
+pub fn parse_world_scene(world: &World, source: &str) -> Result<DynamicScene, WorldSceneError> {
+    let type_registry = world.resource::<AppTypeRegistry>().read();
+    let mut deserializer =
+        ron::de::Deserializer::from_str(source).map_err(|err| WorldSceneError::Ron(err.code))?;
+    SceneDeserializer {
+        type_registry: &type_registry,
+    }
+    .deserialize(&mut deserializer)
+    .map_err(WorldSceneError::Ron)
+}
+
+/// Replaces the game's entities with the ones in `scene`.
+pub fn load_world_scene(world: &mut World, scene: &DynamicScene) -> Result<(), WorldSceneError> {
+    let saved_entities: Vec<Entity> = world
+        .iter_entities()
+        .filter(is_saved_entity)
+        .map(|entity| entity.id())
+        .collect();
+    for entity in saved_entities {
+        despawn_with_children_recursive(world, entity);
+    }
+
+    scene
+        .write_to_world(world, &mut EntityMap::default())
+        .map_err(WorldSceneError::Spawn)
+}
+
+/// Adds the components that loaded entities need to be rendered.
+#[allow(clippy::type_complexity)]
+fn complete_loaded_entities(
+    mut commands: Commands,
+    without_global_transform: Query<Entity, (With<Transform>, Without<GlobalTransform>)>,
+    without_image: Query<Entity, (With<Sprite>, Without<Handle<Image>>)>,
+) {
+    for entity in without_global_transform.iter() {
+        commands.entity(entity).insert((
+            GlobalTransform::default(),
+            Visibility::default(),
+            ComputedVisibility::default(),
+        ));
+    }
+
+    for entity in without_image.iter() {
+        commands.entity(entity).insert(Handle::<Image>::default());
+    }
+}
+
+/// The file that F9 saves the world to and F10 loads it from.
+const WORLD_SCENE_PATH: &str = "world.scn.ron";
+
+fn save_world_scene_file(world: &mut World) {
+    let result = save_world_scene_ron(world)
+        .and_then(|source| std::fs::write(WORLD_SCENE_PATH, source).map_err(WorldSceneError::Io));
+    match result {
+        Ok(()) => info!("saved {}", WORLD_SCENE_PATH),
+        Err(err) => warn!("couldn't save {}: {}", WORLD_SCENE_PATH, err),
+    }
+}
+
+fn load_world_scene_file(world: &mut World) {
+    let result = std::fs::read_to_string(WORLD_SCENE_PATH)
+        .map_err(WorldSceneError::Io)
+        .and_then(|source| parse_world_scene(world, &source))
+        .and_then(|scene| load_world_scene(world, &scene));
+    match result {
+        Ok(()) => info!("loaded {}", WORLD_SCENE_PATH),
+        Err(err) => warn!("couldn't load {}: {}", WORLD_SCENE_PATH, err),
+    }
+}
+
+fn save_and_load_world_scene(mut commands: Commands, keyboard_input: Res<Input<KeyCode>>) {
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        commands.add(save_world_scene_file);
+    }
+    if keyboard_input.just_pressed(KeyCode::F10) {
+        commands.add(load_world_scene_file);
+    }
+}
+
+pub struct WorldScenePlugin;
+
+impl Plugin for WorldScenePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(complete_loaded_entities.in_base_set(CoreSet::PreUpdate))
+            .add_system(save_and_load_world_scene);
+    }
+}
+
+#[test]
+fn world_scene_test_1() {
+    let mut world = World::new();
+    world.init_resource::<AppTypeRegistry>();
+    {
+        let mut type_registry = world.resource::<AppTypeRegistry>().write();
+        type_registry.register::<Transform>();
+        type_registry.register::<Vec3>();
+        type_registry.register::<Quat>();
+        type_registry.register::<Wall>();
+        type_registry.register::<Occluder>();
+        type_registry.register::<crate::sight::OcclusionLayers>();
+        type_registry.register::<crate::sight::EdgeFacings>();
+        type_registry.register::<crate::sight::EdgeFacing>();
+        type_registry.register::<Portal>();
+        type_registry.register::<u32>();
+        type_registry.register::<Color>();
+        type_registry.register::<Sighted>();
+        type_registry.register::<crate::sight::SightSampling>();
+        type_registry.register::<Option<f32>>();
+        type_registry.register::<f32>();
+        type_registry.register::<Visible>();
+    }
+
+    let occluder = Occluder {
+        top_left: Vec3::new(-5.0, 50.0, 0.0),
+        bottom_right: Vec3::new(5.0, -50.0, 0.0),
+        ..default()
+    };
+    world.spawn((
+        Wall,
+        occluder.clone(),
+        Visible,
+        Transform::from_xyz(1.0, 2.0, 0.0),
+    ));
+    world.spawn((Sighted::default(), Transform::default()));
+    // Not part of the game, so not saved.
+    world.spawn(Transform::default());
+
+    let source = save_world_scene_ron(&world).unwrap();
+    let scene = parse_world_scene(&world, &source).unwrap();
+    load_world_scene(&mut world, &scene).unwrap();
+
+    assert_eq!(world.entities().len(), 3);
+    let mut walls = world.query::<(&Wall, &Occluder, &Transform)>();
+    let (_, loaded_occluder, transform) = walls.single(&world);
+    assert_eq!(*loaded_occluder, occluder);
+    assert_eq!(transform.translation, Vec3::new(1.0, 2.0, 0.0));
+}